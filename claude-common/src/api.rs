@@ -1,45 +1,220 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
+const DEFAULT_MAX_TOKENS: u32 = 4096;
+const DEFAULT_BASE_URL: &str = "https://api.anthropic.com";
+
 pub struct ClaudeClient {
     api_key: String,
+    base_url: String,
     client: reqwest::Client,
+    system: Option<String>,
+    temperature: Option<f32>,
 }
 
 #[derive(Debug, Serialize)]
 struct ChatRequest {
     model: String,
+    max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
     messages: Vec<Message>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<ToolDefinition>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
-    role: String,
-    content: String,
+    pub role: String,
+    pub content: Vec<ContentBlock>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentBlock {
+    Text {
+        text: String,
+    },
+    Image {
+        source: MediaSource,
+    },
+    /// A PDF
+    Document {
+        source: MediaSource,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: serde_json::Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        content: String,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        is_error: bool,
+    },
+    #[serde(other)]
+    Unsupported,
+}
+
+/// Inline bytes of an image or document.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaSource {
+    /// Always `base64`
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub media_type: String,
+    pub data: String,
+}
+
+impl MediaSource {
+    pub fn base64(media_type: &str, data: &str) -> Self {
+        Self {
+            kind: "base64".to_string(),
+            media_type: media_type.to_string(),
+            data: data.to_string(),
+        }
+    }
+}
+
+/// A tool offered to the model, in the shape the Messages API expects.
+#[derive(Debug, Clone, Serialize)]
+pub struct ToolDefinition {
+    pub name: String,
+    pub description: String,
+    pub input_schema: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChatResponse {
+    pub content: Vec<ContentBlock>,
+    pub stop_reason: Option<String>,
+}
+
+impl Message {
+    pub fn text(role: &str, text: &str) -> Self {
+        Self {
+            role: role.to_string(),
+            content: vec![ContentBlock::Text { text: text.to_string() }],
+        }
+    }
+
+    pub fn user(text: &str) -> Self {
+        Self::text("user", text)
+    }
+}
+
+impl ChatResponse {
+    /// All text blocks of the response joined together.
+    pub fn text(&self) -> String {
+        self.content.iter()
+            .filter_map(|block| match block {
+                ContentBlock::Text { text } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
 }
 
 impl ClaudeClient {
     pub fn new(api_key: String) -> Self {
         Self {
             api_key,
+            base_url: DEFAULT_BASE_URL.to_string(),
             client: reqwest::Client::new(),
+            system: None,
+            temperature: None,
         }
     }
 
+    /// Send `system` as the system prompt with every request.
+    pub fn with_system(mut self, system: Option<String>) -> Self {
+        self.system = system;
+        self
+    }
+
+    /// Send requests to `base_url` instead of the Anthropic API.
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Change the system prompt for later requests.
+    pub fn set_system(&mut self, system: Option<String>) {
+        self.system = system;
+    }
+
+    /// Change the sampling temperature for later requests, returning the
+    /// previous one. `None` leaves it to the API.
+    pub fn set_temperature(&mut self, temperature: Option<f32>) -> Option<f32> {
+        std::mem::replace(&mut self.temperature, temperature)
+    }
+
     pub async fn chat(&self, message: &str, model: &str) -> Result<String> {
         let request = ChatRequest {
             model: model.to_string(),
-            messages: vec![Message {
-                role: "user".to_string(),
-                content: message.to_string(),
-            }],
+            max_tokens: DEFAULT_MAX_TOKENS,
+            system: self.system.clone(),
+            messages: vec![Message::user(message)],
+            tools: Vec::new(),
+            temperature: self.temperature,
+        };
+
+        let response = self.post(&request).await?;
+        let response_text = response.text().await?;
+        Ok(response_text)
+    }
+
+    /// Send a whole conversation, offering `tools` to the model.
+    pub async fn send(
+        &self,
+        model: &str,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+    ) -> Result<ChatResponse> {
+        let request = ChatRequest {
+            model: model.to_string(),
+            max_tokens: DEFAULT_MAX_TOKENS,
+            system: self.system.clone(),
+            messages: messages.to_vec(),
+            tools: tools.to_vec(),
+            temperature: self.temperature,
         };
 
+        let response = self.post(&request).await?;
+        Ok(response.json().await?)
+    }
+
+    /// One completion with an explicit system prompt and token limit and no tools.
+    pub async fn complete(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        max_tokens: u32,
+        messages: &[Message],
+    ) -> Result<ChatResponse> {
+        let request = ChatRequest {
+            model: model.to_string(),
+            max_tokens,
+            system: system.map(String::from),
+            messages: messages.to_vec(),
+            tools: Vec::new(),
+            temperature: self.temperature,
+        };
+
+        let response = self.post(&request).await?;
+        Ok(response.json().await?)
+    }
+
+    async fn post(&self, request: &ChatRequest) -> Result<reqwest::Response> {
         let response = self.client
-            .post("https://api.anthropic.com/v1/messages")
+            .post(format!("{}/v1/messages", self.base_url))
             .header("x-api-key", &self.api_key)
             .header("anthropic-version", "2023-06-01")
-            .json(&request)
+            .json(request)
             .send()
             .await?;
 
@@ -50,8 +225,7 @@ impl ClaudeClient {
             ));
         }
 
-        let response_text = response.text().await?;
-        Ok(response_text)
+        Ok(response)
     }
 }
 
@@ -64,4 +238,20 @@ mod tests {
         let client = ClaudeClient::new("test-key".to_string());
         assert_eq!(client.api_key, "test-key");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_response_blocks() {
+        let response: ChatResponse = serde_json::from_str(r#"{
+            "content": [
+                {"type": "text", "text": "Scanning"},
+                {"type": "tool_use", "id": "tu_1", "name": "nmap__scan", "input": {"target": "10.0.0.1"}},
+                {"type": "thinking", "thinking": "..."}
+            ],
+            "stop_reason": "tool_use"
+        }"#).unwrap();
+
+        assert_eq!(response.text(), "Scanning");
+        assert!(matches!(response.content[1], ContentBlock::ToolUse { .. }));
+        assert!(matches!(response.content[2], ContentBlock::Unsupported));
+    }
+}
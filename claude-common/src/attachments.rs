@@ -0,0 +1,350 @@
+use anyhow::{anyhow, bail, Context, Result};
+use ignore::overrides::OverrideBuilder;
+use ignore::WalkBuilder;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::process::Command;
+
+use crate::tools::read_pipes;
+use crate::types::{Attachment, AttachmentKind};
+
+/// Largest text file, or command output, that is attached.
+pub const MAX_FILE_BYTES: usize = 256 * 1024;
+/// Most text that the attachments of one message may hold.
+pub const MAX_TOTAL_BYTES: usize = 512 * 1024;
+/// Largest image the API accepts.
+pub const MAX_IMAGE_BYTES: usize = 5 * 1024 * 1024;
+/// Largest PDF attached.
+pub const MAX_DOCUMENT_BYTES: usize = 16 * 1024 * 1024;
+/// Most images and documents one message may carry, leaving room within
+/// the API's request limit once encoded.
+pub const MAX_MEDIA_BYTES: usize = 20 * 1024 * 1024;
+/// Most files one pattern attaches.
+const MAX_FILES: usize = 200;
+
+/// Images and documents the API takes, by their first bytes.
+const MEDIA_TYPES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+];
+
+/// Files found for a pattern, and notes on those passed over.
+#[derive(Debug, Default)]
+pub struct Collected {
+    pub attachments: Vec<Attachment>,
+    pub skipped: Vec<String>,
+}
+
+/// Room left in one message for more attachments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Budget {
+    pub text: usize,
+    pub media: usize,
+}
+
+impl Budget {
+    /// What remains once `attachments` are taken from a message's allowance.
+    pub fn after(attachments: &[Attachment]) -> Self {
+        let mut budget = Self { text: MAX_TOTAL_BYTES, media: MAX_MEDIA_BYTES };
+        budget.take(attachments);
+        budget
+    }
+
+    fn take(&mut self, attachments: &[Attachment]) {
+        for attachment in attachments {
+            let left = if attachment.is_media() { &mut self.media } else { &mut self.text };
+            *left = left.saturating_sub(attachment.size());
+        }
+    }
+
+    /// Why `attachment` does not fit, if it does not.
+    pub fn refuse(&self, attachment: &Attachment) -> Option<String> {
+        let (left, what) = match attachment.is_media() {
+            true => (self.media, "images and documents"),
+            false => (self.text, "text"),
+        };
+        (attachment.size() > left).then(|| {
+            format!("{} is {} bytes, more than the {} left for {} in this message", attachment.source, attachment.size(), left, what)
+        })
+    }
+}
+
+/// Attach the file at `path`, even one .gitignore leaves out. Fails if it is
+/// binary, too large, or more than the `budget` left for this message.
+pub fn read_file(path: &str, cwd: &Path, budget: Budget) -> Result<Attachment> {
+    let full = expand(path, cwd);
+    if full.is_dir() {
+        bail!("{} is a directory; use :attach for its files", path);
+    }
+    let attachment = read(&full, path)?;
+    if let Some(problem) = budget.refuse(&attachment) {
+        bail!(problem);
+    }
+    Ok(attachment)
+}
+
+/// Attach the text files, images and PDFs `pattern` names: a file, every
+/// file under a directory, or those matching a glob such as `src/**/*.rs`.
+/// Directories and globs skip hidden files and whatever .gitignore excludes.
+/// Other binary, oversized and over-budget files are skipped with a note
+/// rather than failing.
+pub fn collect(pattern: &str, cwd: &Path, mut budget: Budget) -> Result<Collected> {
+    if !pattern.contains(['*', '?', '[', '{']) && !expand(pattern, cwd).is_dir() {
+        let attachment = read_file(pattern, cwd, budget)?;
+        return Ok(Collected { attachments: vec![attachment], skipped: Vec::new() });
+    }
+
+    // Walk from the part of the pattern without wildcards; the rest is
+    // anchored there, so `*.md` only matches at the top as in a shell
+    let (base, glob) = split_glob(pattern);
+    let root = expand(&base, cwd);
+    let mut walk = WalkBuilder::new(&root);
+    walk.require_git(false).sort_by_file_name(|a, b| a.cmp(b));
+    if let Some(glob) = glob {
+        let overrides = OverrideBuilder::new(&root)
+            .add(&format!("/{}", glob))
+            .with_context(|| format!("Invalid pattern {:?}", pattern))?
+            .build()?;
+        walk.overrides(overrides);
+    }
+
+    let mut collected = Collected::default();
+    for entry in walk.build() {
+        let entry = entry?;
+        if !entry.file_type().is_some_and(|t| t.is_file()) {
+            continue;
+        }
+        let shown = shown_path(entry.path(), cwd);
+        if collected.attachments.len() == MAX_FILES {
+            collected.skipped.push(format!("stopped after {} files", MAX_FILES));
+            break;
+        }
+        match read(entry.path(), &shown) {
+            Ok(attachment) => match budget.refuse(&attachment) {
+                Some(problem) => collected.skipped.push(problem),
+                None => {
+                    budget.take(std::slice::from_ref(&attachment));
+                    collected.attachments.push(attachment);
+                }
+            },
+            Err(e) => collected.skipped.push(format!("{:#}", e)),
+        }
+    }
+
+    if collected.attachments.is_empty() && collected.skipped.is_empty() {
+        bail!("No files match {}", pattern);
+    }
+    Ok(collected)
+}
+
+/// Run `command` with `sh -c` in `cwd` and attach what it printed to stdout
+/// and stderr, up to `MAX_FILE_BYTES`, noting a failing exit status. Dropping
+/// the future kills the command and anything it started.
+pub async fn run_command(command: &str, cwd: &Path) -> Result<Attachment> {
+    let mut process = Command::new("sh");
+    process.arg("-c")
+        .arg(command)
+        .current_dir(cwd)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    #[cfg(unix)]
+    process.process_group(0);
+
+    let mut child = process.spawn().context("Could not start sh")?;
+    let mut group = ProcessGroup(child.id());
+    let mut output = Vec::new();
+    let mut cut = false;
+    read_pipes(child.stdout.take(), child.stderr.take(), |chunk| {
+        let room = MAX_FILE_BYTES - output.len();
+        output.extend_from_slice(&chunk[..room.min(chunk.len())]);
+        cut |= chunk.len() > room;
+    }).await?;
+    let status = child.wait().await?;
+    group.0 = None;
+
+    let mut text = String::from_utf8_lossy(&output).into_owned();
+    if cut {
+        text.push_str(&format!("\n[output cut at {} bytes]", MAX_FILE_BYTES));
+    }
+    if !status.success() {
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        match status.code() {
+            Some(code) => text.push_str(&format!("[exit status {}]", code)),
+            None => text.push_str("[killed by a signal]"),
+        }
+    }
+
+    Ok(Attachment { kind: AttachmentKind::Command, ..Attachment::file(command.to_string(), text) })
+}
+
+/// Kills a command's process group unless it finished.
+struct ProcessGroup(Option<u32>);
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(pid) = self.0 {
+            // SAFETY: signals only the process group created for the command
+            unsafe {
+                libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+            }
+        }
+    }
+}
+
+/// `path` from `cwd`, with a leading `~/` meaning the home directory.
+fn expand(path: &str, cwd: &Path) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir().unwrap_or_default().join(rest),
+        None => cwd.join(path),
+    }
+}
+
+fn shown_path(path: &Path, cwd: &Path) -> String {
+    path.strip_prefix(cwd).unwrap_or(path).display().to_string()
+}
+
+/// The leading directories of `pattern` without wildcards, and the rest.
+fn split_glob(pattern: &str) -> (String, Option<String>) {
+    let parts: Vec<&str> = pattern.split('/').collect();
+    let literal = parts.iter().take_while(|p| !p.contains(['*', '?', '[', '{'])).count();
+    if literal == parts.len() {
+        return (pattern.to_string(), None);
+    }
+    let base = match parts[..literal].join("/") {
+        base if base.is_empty() && pattern.starts_with('/') => "/".to_string(),
+        base if base.is_empty() => ".".to_string(),
+        base => base,
+    };
+    (base, Some(parts[literal..].join("/")))
+}
+
+/// The API's name for the image or document type `bytes` start with.
+pub fn media_type(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+    MEDIA_TYPES.iter().find(|(magic, _)| bytes.starts_with(magic)).map(|(_, mime)| *mime)
+}
+
+/// Read a text file, image or PDF, refusing other binaries and anything
+/// over the limit for its type. `shown` names it in errors.
+fn read(path: &Path, shown: &str) -> Result<Attachment> {
+    let cannot_read = || format!("Cannot read {}", shown);
+    // Devices and FIFOs could block or never end
+    if !std::fs::metadata(path).with_context(cannot_read)?.is_file() {
+        bail!("{} is not a regular file", shown);
+    }
+    let file = std::fs::File::open(path).with_context(cannot_read)?;
+    let mut bytes = Vec::new();
+    (&file).take(12).read_to_end(&mut bytes).with_context(cannot_read)?;
+
+    let (kind, limit) = match media_type(&bytes) {
+        Some("application/pdf") => (AttachmentKind::Document, MAX_DOCUMENT_BYTES),
+        Some(_) => (AttachmentKind::Image, MAX_IMAGE_BYTES),
+        None => (AttachmentKind::File, MAX_FILE_BYTES),
+    };
+    // The file may have grown since it was opened, so the limit is kept
+    // while reading rather than taken from its size
+    let rest = (limit + 1).saturating_sub(bytes.len()) as u64;
+    (&file).take(rest).read_to_end(&mut bytes).with_context(cannot_read)?;
+    if bytes.len() > limit {
+        bail!("{} is over the limit of {} bytes", shown, limit);
+    }
+    if let Some(mime) = media_type(&bytes) {
+        return Ok(Attachment::media(kind, shown.to_string(), mime, &bytes));
+    }
+
+    if bytes.iter().take(8192).any(|&b| b == 0) {
+        bail!("{} looks like a binary file", shown);
+    }
+    let content = String::from_utf8(bytes).map_err(|_| anyhow!("{} is not UTF-8 text", shown))?;
+    Ok(Attachment::file(shown.to_string(), content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect_globs_and_skips() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cwd = dir.path();
+        std::fs::create_dir_all(cwd.join("src/scan"))?;
+        std::fs::create_dir(cwd.join("target"))?;
+        std::fs::write(cwd.join(".gitignore"), "target/\n")?;
+        std::fs::write(cwd.join("notes.md"), "# notes\n")?;
+        std::fs::write(cwd.join("src/lib.rs"), "pub mod scan;\n")?;
+        std::fs::write(cwd.join("src/scan/mod.rs"), "pub fn scan() {}\n")?;
+        std::fs::write(cwd.join("src/logo.png"), b"\x89PNG\r\n\x1a\n\0\0")?;
+        std::fs::write(cwd.join("src/table.bin"), b"\0\x01\x02")?;
+        std::fs::write(cwd.join("target/out.rs"), "fn main() {}\n")?;
+
+        let sources = |c: &Collected| c.attachments.iter().map(|a| a.source.clone()).collect::<Vec<_>>();
+        let full = Budget::after(&[]);
+        let rust = collect("**/*.rs", cwd, full)?;
+        assert_eq!(sources(&rust), ["src/lib.rs", "src/scan/mod.rs"]);
+        assert!(rust.skipped.is_empty());
+        assert_eq!(sources(&collect("src/*.rs", cwd, full)?), ["src/lib.rs"]);
+
+        // Images are kept as bytes, other binaries skipped
+        let all = collect("src", cwd, full)?;
+        assert_eq!(sources(&all), ["src/lib.rs", "src/logo.png", "src/scan/mod.rs"]);
+        assert_eq!(all.skipped, ["src/table.bin looks like a binary file"]);
+        let logo = &all.attachments[1];
+        assert_eq!((logo.kind, logo.mime_type.as_deref(), logo.size()), (AttachmentKind::Image, Some("image/png"), 10));
+        assert_eq!(Budget::after(&all.attachments), Budget { text: MAX_TOTAL_BYTES - 31, media: MAX_MEDIA_BYTES - 10 });
+
+        // Over budget files are skipped, named ones refused
+        let tight = collect("src/**/*.rs", cwd, Budget { text: 20, media: 0 })?;
+        assert_eq!(sources(&tight), ["src/lib.rs"]);
+        assert_eq!(tight.skipped.len(), 1);
+        assert!(read_file("notes.md", cwd, Budget { text: 4, media: full.media }).is_err());
+        assert!(read_file("src/logo.png", cwd, Budget { text: full.text, media: 4 }).is_err());
+        assert_eq!(read_file("target/out.rs", cwd, full)?.content, "fn main() {}\n");
+        assert!(collect("*.txt", cwd, full).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_refuses_devices_and_oversized_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cwd = dir.path();
+        let full = Budget::after(&[]);
+        let refused = |path: &str| read_file(path, cwd, full).unwrap_err().to_string();
+
+        std::fs::write(cwd.join("big.txt"), "x".repeat(MAX_FILE_BYTES + 1))?;
+        assert!(refused("big.txt").contains("over the limit"));
+        #[cfg(unix)]
+        {
+            assert!(refused("/dev/zero").contains("not a regular file"));
+            let fifo = std::ffi::CString::new(cwd.join("pipe").into_os_string().into_encoded_bytes())?;
+            // SAFETY: `fifo` is a NUL-terminated path that outlives the call
+            assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) }, 0);
+            assert!(refused("pipe").contains("not a regular file"));
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_command_keeps_output_and_status() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let attachment = run_command("echo found; echo denied >&2; exit 3", dir.path()).await?;
+        assert_eq!(attachment.content, "found\ndenied\n[exit status 3]");
+        assert_eq!(attachment.render(), "<command line=\"echo found; echo denied >&amp;2; exit 3\">\nfound\ndenied\n[exit status 3]\n</command>");
+
+        let noisy = run_command("yes scan | head -c 1000000", dir.path()).await?;
+        assert!(noisy.content.starts_with("scan\nscan\n"));
+        assert!(noisy.content.ends_with(&format!("\n[output cut at {} bytes]", MAX_FILE_BYTES)));
+        assert!(noisy.size() < MAX_FILE_BYTES + 40);
+        Ok(())
+    }
+}
@@ -0,0 +1,351 @@
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+pub const AUDIT_FILE: &str = "audit.jsonl";
+
+/// `prev_hash` of the first record in a log.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// One line of the audit log. `hash` covers every other field, including
+/// `prev_hash`, so editing or removing a record breaks the chain after it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub seq: u64,
+    pub timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// A JSON-RPC request and the response it got
+    McpExchange {
+        server: String,
+        method: String,
+        params: Value,
+        response: Value,
+        duration_ms: u64,
+        /// Set for requests the server sent us, such as sampling
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        from_server: bool,
+    },
+    /// A tool call the model asked for and what became of it
+    ToolCall {
+        server: String,
+        tool: String,
+        arguments: Value,
+        decision: Decision,
+        duration_ms: u64,
+        result_bytes: usize,
+        is_error: bool,
+    },
+}
+
+/// How a tool call was let through or stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    /// Allowed by a policy rule
+    Allowed,
+    /// Approved by the user for this call
+    Approved,
+    /// Approved by the user, who also added an allow rule
+    ApprovedAlways,
+    /// Denied by a policy rule, or without anyone to ask
+    Denied,
+    /// Refused by the user
+    Rejected,
+    /// Not run, and nobody asked, because the arguments could not work
+    /// or name a command the shell settings refuse
+    Invalid,
+}
+
+impl AuditEvent {
+    pub fn server(&self) -> &str {
+        match self {
+            AuditEvent::McpExchange { server, .. } | AuditEvent::ToolCall { server, .. } => server,
+        }
+    }
+
+    /// Tool name for tool calls, method for exchanges.
+    pub fn action(&self) -> &str {
+        match self {
+            AuditEvent::McpExchange { method, .. } => method,
+            AuditEvent::ToolCall { tool, .. } => tool,
+        }
+    }
+}
+
+struct Chain {
+    file: File,
+    next_seq: u64,
+    last_hash: String,
+    /// Length of the file when `next_seq` and `last_hash` were taken from it
+    end: u64,
+    /// Whether the file ends in the middle of a line
+    torn: bool,
+}
+
+impl Chain {
+    /// Pick up records other processes appended since this one last wrote,
+    /// continuing from the last record that parses. Lines that do not are
+    /// left for `verify` to report.
+    fn catch_up(&mut self) -> Result<()> {
+        let len = self.file.metadata()?.len();
+        if len == self.end {
+            return Ok(());
+        }
+        // A shorter file has been replaced or cut; start over from its first line
+        let from = if len < self.end { 0 } else { self.end };
+        if from == 0 {
+            self.next_seq = 1;
+            self.last_hash = GENESIS_HASH.to_string();
+        }
+
+        let mut added = Vec::new();
+        self.file.seek(SeekFrom::Start(from))?;
+        self.file.read_to_end(&mut added)?;
+        let last = added.split(|&b| b == b'\n')
+            .rev()
+            .find_map(|line| serde_json::from_slice::<AuditRecord>(line).ok());
+        if let Some(last) = last {
+            self.next_seq = last.seq + 1;
+            self.last_hash = last.hash;
+        }
+        self.end = from + added.len() as u64;
+        self.torn = added.last().is_some_and(|&b| b != b'\n');
+        Ok(())
+    }
+}
+
+/// Holds an exclusive `flock` on the log while a record is appended, so
+/// processes sharing the log do not write the same sequence number.
+struct Lock(#[cfg(unix)] std::os::unix::io::RawFd);
+
+impl Lock {
+    fn new(file: &File) -> Result<Self> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::io::AsRawFd;
+            let fd = file.as_raw_fd();
+            // SAFETY: `fd` is open for as long as `file`, which the caller
+            // keeps until the lock is dropped
+            if unsafe { libc::flock(fd, libc::LOCK_EX) } != 0 {
+                return Err(std::io::Error::last_os_error()).context("Could not lock the audit log");
+            }
+            Ok(Self(fd))
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = file;
+            Ok(Self())
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        // SAFETY: unlocks the descriptor locked in `Lock::new`, still open
+        #[cfg(unix)]
+        unsafe {
+            libc::flock(self.0, libc::LOCK_UN);
+        }
+    }
+}
+
+/// Append-only, hash-chained JSONL log of tool calls and MCP traffic.
+pub struct AuditLog {
+    path: PathBuf,
+    chain: RefCell<Chain>,
+}
+
+impl AuditLog {
+    /// Open `audit.jsonl` in `log_dir`, continuing the chain already there.
+    /// A damaged log is still appended to; `verify` reports the damage.
+    pub fn open(log_dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(log_dir)
+            .with_context(|| format!("Could not create {}", log_dir.display()))?;
+        let path = log_dir.join(AUDIT_FILE);
+
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Could not open {}", path.display()))?;
+        let mut chain = Chain {
+            file,
+            next_seq: 1,
+            last_hash: GENESIS_HASH.to_string(),
+            end: 0,
+            torn: false,
+        };
+        chain.catch_up()
+            .with_context(|| format!("Could not read {}", path.display()))?;
+
+        Ok(Self {
+            path,
+            chain: RefCell::new(chain),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn record(&self, event: AuditEvent) -> Result<()> {
+        let mut chain = self.chain.borrow_mut();
+        let chain = &mut *chain;
+        let _lock = Lock::new(&chain.file)?;
+        chain.catch_up()
+            .with_context(|| format!("Could not read audit log {}", self.path.display()))?;
+
+        let mut record = AuditRecord {
+            seq: chain.next_seq,
+            timestamp: Utc::now(),
+            event,
+            prev_hash: chain.last_hash.clone(),
+            hash: String::new(),
+        };
+        record.hash = digest(&record)?;
+
+        // A torn last line is closed off so this record stays readable
+        let mut line = if chain.torn { String::from("\n") } else { String::new() };
+        line.push_str(&serde_json::to_string(&record)?);
+        line.push('\n');
+        chain.file.write_all(line.as_bytes())
+            .and_then(|_| chain.file.flush())
+            .with_context(|| format!("Could not write audit log {}", self.path.display()))?;
+
+        chain.next_seq += 1;
+        chain.last_hash = record.hash;
+        chain.end += line.len() as u64;
+        chain.torn = false;
+        Ok(())
+    }
+}
+
+/// Every record in the log at `path`; a missing file is an empty log.
+pub fn read(path: &Path) -> Result<Vec<AuditRecord>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    std::fs::read_to_string(path)?
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| serde_json::from_str(line)
+            .with_context(|| format!("{}: line {} is not an audit record", path.display(), i + 1)))
+        .collect()
+}
+
+/// Check sequence numbers and hashes, naming the first record that breaks the chain.
+pub fn verify(records: &[AuditRecord]) -> Result<()> {
+    let mut prev_hash = GENESIS_HASH;
+    for (i, record) in records.iter().enumerate() {
+        let expected_seq = i as u64 + 1;
+        if record.seq != expected_seq {
+            bail!("record {}: expected sequence number {}, found {}", expected_seq, expected_seq, record.seq);
+        }
+        if record.prev_hash != prev_hash {
+            bail!("record {}: does not follow the record before it", record.seq);
+        }
+        if digest(record)? != record.hash {
+            bail!("record {}: contents do not match its hash", record.seq);
+        }
+        prev_hash = &record.hash;
+    }
+    Ok(())
+}
+
+fn digest(record: &AuditRecord) -> Result<String> {
+    let unsigned = AuditRecord {
+        hash: String::new(),
+        ..record.clone()
+    };
+    Ok(format!("{:x}", Sha256::digest(serde_json::to_vec(&unsigned)?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn tool_call(tool: &str) -> AuditEvent {
+        AuditEvent::ToolCall {
+            server: "nmap".to_string(),
+            tool: tool.to_string(),
+            arguments: json!({ "target": "10.0.0.1", "ports": [22, 80] }),
+            decision: Decision::Approved,
+            duration_ms: 12,
+            result_bytes: 40,
+            is_error: false,
+        }
+    }
+
+    #[test]
+    fn test_chain_survives_reopen_and_detects_edits() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        AuditLog::open(dir.path())?.record(tool_call("scan"))?;
+        let log = AuditLog::open(dir.path())?;
+        log.record(tool_call("version"))?;
+        log.record(tool_call("scan"))?;
+
+        let records = read(log.path())?;
+        assert_eq!(records.iter().map(|r| r.seq).collect::<Vec<_>>(), [1, 2, 3]);
+        verify(&records)?;
+
+        let content = std::fs::read_to_string(log.path())?;
+        std::fs::write(log.path(), content.replacen("10.0.0.1", "10.0.0.2", 1))?;
+        let error = verify(&read(log.path())?).unwrap_err();
+        assert_eq!(error.to_string(), "record 1: contents do not match its hash");
+
+        let mut removed = records.clone();
+        removed.remove(1);
+        assert!(verify(&removed).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_logs_shared_between_processes_keep_one_chain() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let first = AuditLog::open(dir.path())?;
+        let second = AuditLog::open(dir.path())?;
+        first.record(tool_call("scan"))?;
+        second.record(tool_call("version"))?;
+        first.record(tool_call("scan"))?;
+        second.record(tool_call("scan"))?;
+
+        let records = read(first.path())?;
+        assert_eq!(records.iter().map(|r| r.seq).collect::<Vec<_>>(), [1, 2, 3, 4]);
+        verify(&records)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_torn_line_is_left_for_verify() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let log = AuditLog::open(dir.path())?;
+        log.record(tool_call("scan"))?;
+        let mut file = OpenOptions::new().append(true).open(log.path())?;
+        file.write_all(br#"{"seq": 2, "timest"#)?;
+
+        let log = AuditLog::open(dir.path())?;
+        log.record(tool_call("version"))?;
+        let content = std::fs::read_to_string(log.path())?;
+        let last: AuditRecord = serde_json::from_str(content.lines().last().unwrap_or_default())?;
+        assert_eq!(last.seq, 2);
+        assert!(read(log.path()).unwrap_err().to_string().contains("line 2 is not an audit record"));
+        Ok(())
+    }
+}
@@ -1,4 +1,8 @@
+pub mod mcp;
+pub mod permissions;
+
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::PathBuf;
 use anyhow::Result;
 
@@ -8,6 +12,126 @@ pub struct Config {
     pub default_model: String,
     pub output_format: OutputFormat,
     pub config_dir: PathBuf,
+    #[serde(default = "default_log_dir")]
+    pub log_dir: PathBuf,
+    /// Submitted prompts and commands, recalled with Up and Ctrl-R
+    #[serde(default = "default_history_file")]
+    pub history_file: PathBuf,
+    /// Named presets selectable with `--profile` or by MCP callers
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub profiles: HashMap<String, Profile>,
+    /// Cap on each completion an MCP server requests; 0 turns sampling off
+    #[serde(default = "default_sampling_max_tokens")]
+    pub sampling_max_tokens: u32,
+    /// Offer the built-in file tools, confined to the working directory
+    #[serde(default = "default_true")]
+    pub builtin_tools: bool,
+    #[serde(default)]
+    pub shell: ShellConfig,
+    #[serde(default)]
+    pub editor: EditorConfig,
+    #[serde(default)]
+    pub display: DisplayConfig,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Limits for the built-in `bash` tool.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShellConfig {
+    pub enabled: bool,
+    /// Longest a command may run; the model may ask for less, not more
+    pub timeout_secs: u64,
+    /// Output beyond this is cut from the middle
+    pub max_output_bytes: usize,
+    /// When non-empty, every command in a line must match one of these, and
+    /// substitutions and redirections to files are refused
+    pub allow: Vec<String>,
+    /// Commands matching any of these are refused
+    pub deny: Vec<String>,
+    /// Environment variables passed through in addition to PATH, HOME and locale
+    pub env: Vec<String>,
+}
+
+impl Default for ShellConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            timeout_secs: 120,
+            max_output_bytes: 30_000,
+            allow: Vec::new(),
+            deny: Vec::new(),
+            env: Vec::new(),
+        }
+    }
+}
+
+/// How the REPL's input line behaves.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EditorConfig {
+    /// Key that sends a message; the other of Enter and Alt-Enter adds a line
+    pub submit_key: SubmitKey,
+    pub keymap: Keymap,
+}
+
+/// Key bindings for editing chat input.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Keymap {
+    /// Readline-style keys, always inserting
+    #[default]
+    Emacs,
+    /// Normal, Insert and Visual modes
+    Vi,
+}
+
+/// How the REPL shows replies.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DisplayConfig {
+    /// Format replies as Markdown; off prints them as sent
+    pub markdown: bool,
+    /// Syntax highlighting theme for code blocks
+    pub theme: String,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        Self { markdown: true, theme: "base16-ocean.dark".to_string() }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SubmitKey {
+    #[default]
+    Enter,
+    AltEnter,
+}
+
+fn default_log_dir() -> PathBuf {
+    Config::default_dir().join("logs")
+}
+
+fn default_history_file() -> PathBuf {
+    Config::default_dir().join("history.jsonl")
+}
+
+fn default_sampling_max_tokens() -> u32 {
+    1024
+}
+
+/// Model and system prompt to use together, e.g. a "reviewer" persona.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Profile {
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub system: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,18 +149,32 @@ impl Default for Config {
             default_model: std::env::var("CLAUDE_MODEL")
                 .unwrap_or_else(|_| String::from("claude-3-sonnet")),
             output_format: OutputFormat::Text,
-            config_dir: dirs::config_dir()
-                .unwrap_or_else(|| PathBuf::from("~/.config"))
-                .join("claude-cli"),
+            config_dir: Self::default_dir(),
+            log_dir: default_log_dir(),
+            history_file: default_history_file(),
+            profiles: HashMap::new(),
+            sampling_max_tokens: default_sampling_max_tokens(),
+            builtin_tools: true,
+            shell: ShellConfig::default(),
+            editor: EditorConfig::default(),
+            display: DisplayConfig::default(),
         }
     }
 }
 
 impl Config {
+    /// `CLAUDE_CONFIG_DIR` when set, otherwise `~/.config/claude-cli`.
+    pub fn default_dir() -> PathBuf {
+        match std::env::var_os("CLAUDE_CONFIG_DIR") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => dirs::config_dir()
+                .unwrap_or_else(|| PathBuf::from("~/.config"))
+                .join("claude-cli"),
+        }
+    }
+
     pub fn load() -> Result<Self> {
-        let config_path = dirs::config_dir()
-            .unwrap_or_else(|| PathBuf::from("~/.config"))
-            .join("claude-cli/config.json");
+        let config_path = Self::default_dir().join("config.json");
 
         if !config_path.exists() {
             return Ok(Config::default());
@@ -47,6 +185,11 @@ impl Config {
         Ok(config)
     }
 
+    pub fn profile(&self, name: &str) -> Result<&Profile> {
+        self.profiles.get(name)
+            .ok_or_else(|| anyhow::anyhow!("No profile named '{}'", name))
+    }
+
     pub fn save(&self) -> Result<()> {
         let config_path = self.config_dir.join("config.json");
 
@@ -1,16 +1,34 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::path::PathBuf;
-use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, bail, Context, Result};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+mod import;
+
+pub use import::{find_project_file, known_sources, read_servers, ImportReport, ProjectFile, PROJECT_FILE};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct McpServer {
     pub name: String,
+    /// Endpoint for HTTP servers, unused when `command` is set
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub url: String,
+    /// Executable for servers launched locally and spoken to over stdio
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+    #[serde(default)]
     pub api_version: String,
+    #[serde(default)]
     pub tools: Vec<McpTool>,
     #[serde(default)]
     pub enabled: bool,
+    /// Project file this server came from; such servers are never saved
+    #[serde(skip)]
+    pub project: Option<PathBuf>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,44 +46,145 @@ pub struct ToolParameter {
     pub default: Option<serde_json::Value>,
 }
 
-#[derive(Debug, Default)]
+/// Version written to `mcp_servers.json`. Files without one are the bare
+/// array written by earlier releases.
+pub const CONFIG_VERSION: u32 = 1;
+
+impl McpServer {
+    /// Check the entry can actually be started or reached.
+    pub fn validate(&self) -> Result<()> {
+        if self.name.trim().is_empty() {
+            bail!("name is empty");
+        }
+        match (&self.command, self.url.is_empty()) {
+            (Some(_), false) => bail!("has both \"command\" and \"url\"; use one"),
+            (None, true) => bail!("needs either \"command\" or \"url\""),
+            (Some(command), true) if command.trim().is_empty() => bail!("\"command\" is empty"),
+            (None, false) => {
+                let url = reqwest::Url::parse(&self.url)
+                    .with_context(|| format!("\"url\" {:?} is not a valid URL", self.url))?;
+                if !matches!(url.scheme(), "http" | "https") {
+                    bail!("\"url\" must be http or https, not {}", url.scheme());
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Short description of how the server is reached, for listings.
+    pub fn transport(&self) -> String {
+        match &self.command {
+            Some(command) if self.args.is_empty() => format!("stdio: {}", command),
+            Some(command) => format!("stdio: {} {}", command, self.args.join(" ")),
+            None => format!("http: {}", self.url),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct McpConfig {
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default)]
     pub servers: Vec<McpServer>,
+    /// Servers from the project file, layered over `servers` but never saved
+    #[serde(skip)]
+    pub project_servers: Vec<McpServer>,
+    /// The project file those servers came from
+    #[serde(skip)]
+    pub project: Option<ProjectFile>,
+    #[serde(skip)]
     config_path: PathBuf,
 }
 
 impl McpConfig {
-    pub fn load() -> Result<Self> {
-        let config_dir = dirs::config_dir()
-            .unwrap_or_else(|| PathBuf::from("~/.config"))
-            .join("claude-cli");
-        
+    /// Load `mcp_servers.json` from `config_dir`.
+    pub fn load(config_dir: &Path) -> Result<Self> {
         let config_path = config_dir.join("mcp_servers.json");
-        
+
         if !config_path.exists() {
-            return Ok(Self::default());
+            return Ok(Self {
+                version: CONFIG_VERSION,
+                config_path,
+                ..Self::default()
+            });
         }
-        
+
         let content = std::fs::read_to_string(&config_path)?;
-        let servers = serde_json::from_str(&content)?;
-        
+        let mut config = Self::parse(&content)
+            .with_context(|| format!("Invalid MCP server config {}", config_path.display()))?;
+        config.config_path = config_path;
+        Ok(config)
+    }
+
+    /// Parse either the `{"version": 1, "servers": [...]}` shape or the bare
+    /// server array of earlier releases, then validate every entry.
+    fn parse(content: &str) -> Result<Self> {
+        let value: serde_json::Value = serde_json::from_str(content)?;
+
+        let (version, entries) = match value {
+            serde_json::Value::Array(entries) => (0, entries),
+            serde_json::Value::Object(mut object) => {
+                let version = match object.remove("version") {
+                    Some(v) => v.as_u64()
+                        .ok_or_else(|| anyhow!("\"version\" must be a number"))? as u32,
+                    None => CONFIG_VERSION,
+                };
+                if version > CONFIG_VERSION {
+                    bail!("version {} was written by a newer claude-cli (this one reads up to {})",
+                        version, CONFIG_VERSION);
+                }
+                match object.remove("servers") {
+                    Some(serde_json::Value::Array(entries)) => (version, entries),
+                    Some(_) => bail!("\"servers\" must be a list"),
+                    None => (version, Vec::new()),
+                }
+            }
+            _ => bail!("expected an object with a \"servers\" list"),
+        };
+
+        let mut servers = Vec::with_capacity(entries.len());
+        let mut names = HashSet::new();
+        for (i, entry) in entries.into_iter().enumerate() {
+            let label = match entry.get("name").and_then(|n| n.as_str()) {
+                Some(name) => format!("server {} ('{}')", i + 1, name),
+                None => format!("server {}", i + 1),
+            };
+            let server: McpServer = serde_json::from_value(entry)
+                .with_context(|| label.clone())?;
+            server.validate().with_context(|| label.clone())?;
+            if !names.insert(server.name.clone()) {
+                bail!("{}: name is used more than once", label);
+            }
+            servers.push(server);
+        }
+
         Ok(Self {
+            version,
             servers,
-            config_path,
+            ..Self::default()
         })
     }
-    
+
     pub fn save(&self) -> Result<()> {
         if let Some(parent) = self.config_path.parent() {
             std::fs::create_dir_all(parent)?;
         }
-        
-        let content = serde_json::to_string_pretty(&self.servers)?;
+
+        let file = Self {
+            version: CONFIG_VERSION,
+            servers: self.servers.clone(),
+            ..Self::default()
+        };
+        let content = serde_json::to_string_pretty(&file)?;
         std::fs::write(&self.config_path, content)?;
         Ok(())
     }
     
     pub fn add_server(&mut self, server: McpServer) -> Result<()> {
+        server.validate()
+            .with_context(|| format!("Invalid MCP server '{}'", server.name))?;
         // Check if server with same name exists
         if let Some(existing) = self.servers.iter_mut()
             .find(|s| s.name == server.name) 
@@ -84,9 +203,77 @@ impl McpConfig {
         Ok(())
     }
     
+    pub fn get_server(&self, name: &str) -> Option<&McpServer> {
+        self.servers.iter().find(|s| s.name == name)
+    }
+
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> Result<()> {
+        let server = self.servers.iter_mut()
+            .find(|s| s.name == name)
+            .ok_or_else(|| anyhow::anyhow!("No MCP server named '{}'", name))?;
+        server.enabled = enabled;
+        self.save()
+    }
+
+    /// User servers with any trusted project servers of the same name swapped in.
+    pub fn effective_servers(&self) -> Vec<&McpServer> {
+        let project: &[McpServer] = match &self.project {
+            Some(p) if p.trusted => &self.project_servers,
+            _ => &[],
+        };
+        let user = self.servers.iter()
+            .filter(|s| !project.iter().any(|p| p.name == s.name));
+        user.chain(project.iter()).collect()
+    }
+
     pub fn get_enabled_servers(&self) -> Vec<&McpServer> {
-        self.servers.iter()
+        self.effective_servers()
+            .into_iter()
             .filter(|s| s.enabled)
             .collect()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_documented_shape() -> Result<()> {
+        let config = McpConfig::parse(r#"{
+            "servers": [
+                { "name": "example", "url": "https://example.com/mcp", "api_version": "1.0", "enabled": true }
+            ]
+        }"#)?;
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.servers[0].name, "example");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_legacy_array() -> Result<()> {
+        let config = McpConfig::parse(r#"[
+            { "name": "nmap", "command": "nmap-mcp", "api_version": "1.0", "tools": [] }
+        ]"#)?;
+        assert_eq!(config.version, 0);
+        assert_eq!(config.servers[0].command.as_deref(), Some("nmap-mcp"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_errors_name_the_entry() {
+        let error = |content: &str| format!("{:#}", McpConfig::parse(content).unwrap_err());
+
+        assert!(error(r#"{"servers": [{"name": "a", "url": "x", "command": "y"}]}"#)
+            .contains("server 1 ('a'): has both"));
+        assert!(error(r#"{"servers": [{"name": "a", "url": "http://a"}, {"name": "b"}]}"#)
+            .contains("server 2 ('b'): needs either"));
+        assert!(error(r#"{"servers": [{"name": "a", "url": "ftp://a"}]}"#)
+            .contains("must be http or https"));
+        assert!(error(r#"{"servers": [{"url": "http://a"}]}"#)
+            .contains("server 1: missing field `name`"));
+        assert!(error(r#"{"version": 9, "servers": []}"#).contains("newer claude-cli"));
+        assert!(error(r#"[{"name": "a", "url": "http://a"}, {"name": "a", "url": "http://b"}]"#)
+            .contains("used more than once"));
+    }
+}
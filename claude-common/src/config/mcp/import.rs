@@ -0,0 +1,337 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use super::{McpConfig, McpServer};
+
+/// File holding project-scoped servers, looked up from the working directory upwards.
+pub const PROJECT_FILE: &str = ".mcp.json";
+
+/// Project files the user has trusted, by path and content hash, in the config dir.
+const TRUST_FILE: &str = "trusted_projects.json";
+
+/// The project server file found for this run.
+#[derive(Debug, Clone)]
+pub struct ProjectFile {
+    pub path: PathBuf,
+    /// SHA-256 of the content; any edit needs trusting again
+    pub hash: String,
+    /// Whether the user trusted this content, so its servers may start
+    pub trusted: bool,
+}
+
+/// The `{"mcpServers": {...}}` layout shared by desktop and editor clients.
+#[derive(Debug, Deserialize)]
+struct ExternalConfig {
+    #[serde(rename = "mcpServers", default)]
+    mcp_servers: BTreeMap<String, ExternalServer>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExternalServer {
+    command: Option<String>,
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default)]
+    env: HashMap<String, String>,
+    url: Option<String>,
+    #[serde(default)]
+    disabled: bool,
+}
+
+/// Outcome of merging external definitions into a config.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub added: Vec<String>,
+    pub replaced: Vec<String>,
+    pub unchanged: Vec<String>,
+    /// Names that already exist with a different definition and were left alone
+    pub conflicts: Vec<String>,
+}
+
+/// Config files of other clients that commonly hold `mcpServers` blocks.
+pub fn known_sources() -> Vec<PathBuf> {
+    let mut sources = Vec::new();
+    if let Some(config_dir) = dirs::config_dir() {
+        sources.push(config_dir.join("Claude").join("claude_desktop_config.json"));
+    }
+    if let Some(home) = dirs::home_dir() {
+        sources.push(home.join(".cursor").join("mcp.json"));
+    }
+    sources
+}
+
+/// Find the nearest project server file at or above `dir`.
+pub fn find_project_file(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|d| d.join(PROJECT_FILE))
+        .find(|p| p.is_file())
+}
+
+/// Read the servers defined in an `mcpServers` file.
+pub fn read_servers(path: &Path) -> Result<Vec<McpServer>> {
+    let content = std::fs::read(path)
+        .with_context(|| format!("Could not read {}", path.display()))?;
+    parse_servers(&content, path)
+}
+
+/// The servers in `content`, read from `path`, which names it in errors.
+fn parse_servers(content: &[u8], path: &Path) -> Result<Vec<McpServer>> {
+    let external: ExternalConfig = serde_json::from_slice(content)
+        .with_context(|| format!("{} is not an mcpServers config", path.display()))?;
+
+    external.mcp_servers.into_iter()
+        .map(|(name, server)| {
+            let server = McpServer {
+                name,
+                url: server.url.unwrap_or_default(),
+                command: server.command,
+                args: server.args,
+                env: server.env,
+                enabled: !server.disabled,
+                ..McpServer::default()
+            };
+            server.validate()
+                .with_context(|| format!("{}: server '{}'", path.display(), server.name))?;
+            Ok(server)
+        })
+        .collect()
+}
+
+impl McpServer {
+    /// Whether both definitions launch or reach the same server.
+    pub fn same_endpoint(&self, other: &McpServer) -> bool {
+        self.url == other.url
+            && self.command == other.command
+            && self.args == other.args
+            && self.env == other.env
+    }
+}
+
+impl McpConfig {
+    /// Merge `servers` into the user config. Existing names with a different
+    /// definition are reported as conflicts unless `overwrite` is set.
+    pub fn import(&mut self, servers: Vec<McpServer>, overwrite: bool) -> Result<ImportReport> {
+        let mut report = ImportReport::default();
+
+        for server in servers {
+            match self.servers.iter_mut().find(|s| s.name == server.name) {
+                None => {
+                    report.added.push(server.name.clone());
+                    self.servers.push(server);
+                }
+                Some(existing) if existing.same_endpoint(&server) => {
+                    report.unchanged.push(server.name);
+                }
+                Some(existing) if overwrite => {
+                    report.replaced.push(server.name.clone());
+                    *existing = server;
+                }
+                Some(_) => report.conflicts.push(server.name),
+            }
+        }
+
+        if !report.added.is_empty() || !report.replaced.is_empty() {
+            self.save()?;
+        }
+        Ok(report)
+    }
+
+    /// Read the project servers found from `dir`. They are used for this run
+    /// only once the user has trusted the file as it is now; until then they
+    /// are listed but never started.
+    pub fn merge_project(&mut self, dir: &Path) -> Result<Option<&ProjectFile>> {
+        let Some(path) = find_project_file(dir) else {
+            return Ok(None);
+        };
+
+        let content = std::fs::read(&path)
+            .with_context(|| format!("Could not read {}", path.display()))?;
+        let hash = format!("{:x}", Sha256::digest(&content));
+        let path = path.canonicalize().unwrap_or(path);
+        let trusted = self.trusted_projects()?.get(&path) == Some(&hash);
+
+        // Parsed from the bytes hashed, so what starts is what was trusted
+        self.project_servers = parse_servers(&content, &path)?;
+        for server in &mut self.project_servers {
+            server.project = Some(path.clone());
+        }
+        self.project = Some(ProjectFile { path, hash, trusted });
+        Ok(self.project.as_ref())
+    }
+
+    /// Trust the project file as read by `merge_project`, so its servers
+    /// start now and on later runs until the file changes.
+    pub fn trust_project(&mut self) -> Result<()> {
+        let mut trusted = self.trusted_projects()?;
+        let Some(project) = &mut self.project else {
+            bail!("No {} found", PROJECT_FILE);
+        };
+        trusted.insert(project.path.clone(), project.hash.clone());
+        project.trusted = true;
+
+        let path = self.trust_path();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, serde_json::to_string_pretty(&trusted)?)?;
+        Ok(())
+    }
+
+    /// Names of user servers replaced by trusted project servers.
+    pub fn project_overrides(&self) -> Vec<String> {
+        if !self.project.as_ref().is_some_and(|p| p.trusted) {
+            return Vec::new();
+        }
+        self.project_servers.iter()
+            .filter(|p| self.servers.iter().any(|s| s.name == p.name))
+            .map(|p| p.name.clone())
+            .collect()
+    }
+
+    /// Project servers found but not started because the file is not trusted.
+    pub fn untrusted_servers(&self) -> &[McpServer] {
+        match &self.project {
+            Some(project) if !project.trusted => &self.project_servers,
+            _ => &[],
+        }
+    }
+
+    fn trust_path(&self) -> PathBuf {
+        self.config_path.with_file_name(TRUST_FILE)
+    }
+
+    fn trusted_projects(&self) -> Result<BTreeMap<PathBuf, String>> {
+        let path = self.trust_path();
+        if !path.exists() {
+            return Ok(BTreeMap::new());
+        }
+        let content = std::fs::read_to_string(&path)?;
+        serde_json::from_str(&content).with_context(|| format!("Invalid {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const DESKTOP_CONFIG: &str = r#"{
+        "mcpServers": {
+            "nmap": { "command": "nmap-mcp", "args": ["--stdio"], "env": { "NMAP_PATH": "/usr/bin/nmap" } },
+            "remote": { "url": "https://example.com/mcp", "disabled": true }
+        },
+        "globalShortcut": ""
+    }"#;
+
+    fn config_in(dir: &TempDir) -> McpConfig {
+        McpConfig {
+            config_path: dir.path().join("mcp_servers.json"),
+            ..McpConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_read_servers() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("claude_desktop_config.json");
+        std::fs::write(&path, DESKTOP_CONFIG)?;
+
+        let servers = read_servers(&path)?;
+        assert_eq!(servers.len(), 2);
+        assert_eq!(servers[0].name, "nmap");
+        assert_eq!(servers[0].command.as_deref(), Some("nmap-mcp"));
+        assert_eq!(servers[0].env["NMAP_PATH"], "/usr/bin/nmap");
+        assert!(servers[0].enabled);
+        assert_eq!(servers[1].url, "https://example.com/mcp");
+        assert!(!servers[1].enabled);
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_reports_conflicts() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut config = config_in(&dir);
+        config.servers.push(McpServer {
+            name: "nmap".to_string(),
+            command: Some("other-nmap".to_string()),
+            ..McpServer::default()
+        });
+
+        let path = dir.path().join("desktop.json");
+        std::fs::write(&path, DESKTOP_CONFIG)?;
+
+        let report = config.import(read_servers(&path)?, false)?;
+        assert_eq!(report.added, vec!["remote"]);
+        assert_eq!(report.conflicts, vec!["nmap"]);
+        assert_eq!(config.get_server("nmap").unwrap().command.as_deref(), Some("other-nmap"));
+
+        let report = config.import(read_servers(&path)?, true)?;
+        assert_eq!(report.replaced, vec!["nmap"]);
+        assert_eq!(report.unchanged, vec!["remote"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_project_servers_are_not_saved() -> Result<()> {
+        let dir = TempDir::new()?;
+        let project = dir.path().join("project");
+        std::fs::create_dir_all(project.join("src"))?;
+        std::fs::write(project.join(PROJECT_FILE), DESKTOP_CONFIG)?;
+
+        let mut config = config_in(&dir);
+        config.servers.push(McpServer {
+            name: "nmap".to_string(),
+            command: Some("user-nmap".to_string()),
+            ..McpServer::default()
+        });
+
+        config.merge_project(&project.join("src"))?;
+        config.trust_project()?;
+        assert_eq!(config.project_overrides(), vec!["nmap"]);
+
+        let effective = config.effective_servers();
+        assert_eq!(effective.len(), 2);
+        assert!(effective.iter().all(|s| s.project.is_some()));
+
+        config.save()?;
+        let saved = std::fs::read_to_string(dir.path().join("mcp_servers.json"))?;
+        assert!(saved.contains("user-nmap"));
+        assert!(!saved.contains("nmap-mcp"));
+        assert!(!saved.contains("remote"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_project_servers_wait_for_trust() -> Result<()> {
+        let dir = TempDir::new()?;
+        let file = dir.path().join(PROJECT_FILE);
+        std::fs::write(&file, DESKTOP_CONFIG)?;
+
+        let mut config = config_in(&dir);
+        config.servers.push(McpServer {
+            name: "nmap".to_string(),
+            command: Some("user-nmap".to_string()),
+            enabled: true,
+            ..McpServer::default()
+        });
+        assert!(!config.merge_project(dir.path())?.unwrap().trusted);
+        assert_eq!(config.untrusted_servers().len(), 2);
+        assert!(config.project_overrides().is_empty());
+        assert_eq!(config.get_enabled_servers()[0].command.as_deref(), Some("user-nmap"));
+
+        config.trust_project()?;
+        let mut config = config_in(&dir);
+        assert!(config.merge_project(dir.path())?.unwrap().trusted);
+        assert!(config.untrusted_servers().is_empty());
+
+        // Any edit to the file needs trusting again
+        std::fs::write(&file, DESKTOP_CONFIG.replace("--stdio", "--stdio --verbose"))?;
+        let mut config = config_in(&dir);
+        assert!(!config.merge_project(dir.path())?.unwrap().trusted);
+        assert!(config.effective_servers().is_empty());
+        Ok(())
+    }
+}
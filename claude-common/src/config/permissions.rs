@@ -0,0 +1,249 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use regex::Regex;
+
+/// What to do when a tool call matches a rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    Allow,
+    Ask,
+    Deny,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PermissionRule {
+    /// Server name, `*` wildcards allowed
+    #[serde(default = "wildcard")]
+    pub server: String,
+    /// Tool name, `*` wildcards allowed
+    #[serde(default = "wildcard")]
+    pub tool: String,
+    pub permission: Permission,
+    /// Regex patterns that named arguments must match for the rule to apply
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub arguments: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PermissionPolicy {
+    /// Applied when no rule matches a call
+    #[serde(default = "default_permission")]
+    pub default: Permission,
+    #[serde(default)]
+    pub rules: Vec<PermissionRule>,
+    #[serde(skip)]
+    config_path: PathBuf,
+}
+
+fn wildcard() -> String {
+    String::from("*")
+}
+
+fn default_permission() -> Permission {
+    Permission::Ask
+}
+
+impl Default for PermissionPolicy {
+    fn default() -> Self {
+        Self {
+            default: default_permission(),
+            rules: Vec::new(),
+            config_path: PathBuf::new(),
+        }
+    }
+}
+
+impl PermissionRule {
+    fn matches(&self, server: &str, tool: &str, arguments: &serde_json::Value) -> bool {
+        if !glob_match(&self.server, server) || !glob_match(&self.tool, tool) {
+            return false;
+        }
+
+        self.arguments.iter().all(|(name, pattern)| {
+            let value = match arguments.get(name) {
+                Some(serde_json::Value::String(s)) => s.clone(),
+                Some(other) => other.to_string(),
+                None => return false,
+            };
+            // Patterns are validated on load, an invalid one never matches
+            Regex::new(pattern).map(|re| re.is_match(&value)).unwrap_or(false)
+        })
+    }
+}
+
+impl PermissionPolicy {
+    pub fn load(config_dir: &Path) -> Result<Self> {
+        let config_path = config_dir.join("permissions.json");
+
+        if !config_path.exists() {
+            return Ok(Self {
+                config_path,
+                ..Self::default()
+            });
+        }
+
+        let content = std::fs::read_to_string(&config_path)?;
+        let mut policy: Self = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid permission policy in {}", config_path.display()))?;
+        policy.validate()?;
+        policy.config_path = config_path;
+        Ok(policy)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.config_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let content = serde_json::to_string_pretty(self)?;
+        std::fs::write(&self.config_path, content)?;
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        for (i, rule) in self.rules.iter().enumerate() {
+            for (name, pattern) in &rule.arguments {
+                Regex::new(pattern).with_context(|| {
+                    format!("Rule {} has an invalid pattern for argument '{}'", i + 1, name)
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Decide what to do with a tool call. A matching `deny` rule always wins,
+    /// then `ask`, then `allow`, so a broad allow can be narrowed by later rules.
+    pub fn evaluate(&self, server: &str, tool: &str, arguments: &serde_json::Value) -> Permission {
+        let matched: Vec<Permission> = self.rules.iter()
+            .filter(|r| r.matches(server, tool, arguments))
+            .map(|r| r.permission)
+            .collect();
+
+        [Permission::Deny, Permission::Ask, Permission::Allow]
+            .into_iter()
+            .find(|p| matched.contains(p))
+            .unwrap_or(self.default)
+    }
+
+    /// Whether an allow rule added for this tool would let this call through,
+    /// i.e. no `ask` or `deny` rule matching it would still win.
+    pub fn can_allow_always(&self, server: &str, tool: &str, arguments: &serde_json::Value) -> bool {
+        !self.rules.iter()
+            .any(|r| r.permission != Permission::Allow && r.matches(server, tool, arguments))
+    }
+
+    /// Remember an "always allow" answer for every future call of this tool.
+    pub fn allow_always(&mut self, server: &str, tool: &str) -> Result<()> {
+        self.rules.push(PermissionRule {
+            server: server.to_string(),
+            tool: tool.to_string(),
+            permission: Permission::Allow,
+            arguments: HashMap::new(),
+        });
+        self.save()
+    }
+}
+
+/// Match `text` against a pattern where `*` stands for any run of characters.
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rule(server: &str, tool: &str, permission: Permission) -> PermissionRule {
+        PermissionRule {
+            server: server.to_string(),
+            tool: tool.to_string(),
+            permission,
+            arguments: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("scan_*", "scan_ports"));
+        assert!(glob_match("*_ports", "scan_ports"));
+        assert!(glob_match("s*n*s", "scan_ports"));
+        assert!(!glob_match("scan", "scan_ports"));
+        assert!(!glob_match("ab*ba", "aba"));
+    }
+
+    #[test]
+    fn test_default_applies_without_rules() {
+        let policy = PermissionPolicy::default();
+        assert_eq!(policy.evaluate("nmap", "scan", &json!({})), Permission::Ask);
+    }
+
+    #[test]
+    fn test_deny_overrides_allow() {
+        let mut policy = PermissionPolicy::default();
+        policy.rules.push(rule("nmap", "*", Permission::Allow));
+        policy.rules.push(rule("nmap", "exploit_*", Permission::Deny));
+
+        assert_eq!(policy.evaluate("nmap", "scan", &json!({})), Permission::Allow);
+        assert_eq!(policy.evaluate("nmap", "exploit_smb", &json!({})), Permission::Deny);
+        assert_eq!(policy.evaluate("other", "scan", &json!({})), Permission::Ask);
+    }
+
+    #[test]
+    fn test_always_only_offered_when_it_would_apply() {
+        let mut policy = PermissionPolicy::default();
+        policy.rules.push(rule("nmap", "exploit_*", Permission::Ask));
+        assert!(policy.can_allow_always("nmap", "scan", &json!({})));
+        assert!(!policy.can_allow_always("nmap", "exploit_smb", &json!({})));
+
+        // An allow rule next to the ask rule would change nothing
+        policy.rules.push(rule("nmap", "exploit_smb", Permission::Allow));
+        assert_eq!(policy.evaluate("nmap", "exploit_smb", &json!({})), Permission::Ask);
+    }
+
+    #[test]
+    fn test_argument_patterns() {
+        let mut policy = PermissionPolicy::default();
+        let mut lab_only = rule("nmap", "scan", Permission::Allow);
+        lab_only.arguments.insert("target".to_string(), r"^10\.0\.".to_string());
+        policy.rules.push(lab_only);
+
+        assert_eq!(policy.evaluate("nmap", "scan", &json!({"target": "10.0.0.5"})), Permission::Allow);
+        assert_eq!(policy.evaluate("nmap", "scan", &json!({"target": "8.8.8.8"})), Permission::Ask);
+        assert_eq!(policy.evaluate("nmap", "scan", &json!({})), Permission::Ask);
+    }
+
+    #[test]
+    fn test_invalid_pattern_rejected_on_load() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        std::fs::write(
+            dir.path().join("permissions.json"),
+            r#"{"rules": [{"tool": "scan", "permission": "allow", "arguments": {"target": "("}}]}"#,
+        )?;
+
+        let err = PermissionPolicy::load(dir.path()).unwrap_err();
+        assert!(format!("{:#}", err).contains("argument 'target'"));
+        Ok(())
+    }
+}
@@ -0,0 +1,400 @@
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::types::Message;
+
+/// Messages as a tree, so a conversation can go more than one way from the
+/// same point. Each branch follows one path from the start to its tip, and
+/// one branch is checked out at a time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "Parts")]
+pub struct Conversation {
+    nodes: Vec<Node>,
+    branches: Vec<Branch>,
+    /// Index in `branches` of the one checked out
+    current: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Node {
+    /// Always earlier in `nodes`, so following parents ends
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parent: Option<usize>,
+    message: Message,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Branch {
+    pub name: String,
+    /// Its last message, `None` while it has none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tip: Option<usize>,
+}
+
+/// A message as found on one or more branches.
+#[derive(Debug)]
+pub struct Placed<'a> {
+    /// Position on the branches through it, counting from 0
+    pub index: usize,
+    /// Names of those branches
+    pub branches: Vec<&'a str>,
+    pub message: &'a Message,
+}
+
+/// A conversation as saved, checked before use.
+#[derive(Deserialize)]
+struct Parts {
+    nodes: Vec<Node>,
+    branches: Vec<Branch>,
+    current: usize,
+}
+
+impl TryFrom<Parts> for Conversation {
+    type Error = anyhow::Error;
+
+    fn try_from(parts: Parts) -> Result<Self> {
+        let Parts { nodes, branches, current } = parts;
+        if let Some(i) = nodes.iter().enumerate().position(|(i, n)| n.parent.is_some_and(|p| p >= i)) {
+            bail!("message {} does not follow its parent", i);
+        }
+        if branches.iter().any(|b| b.tip.is_some_and(|t| t >= nodes.len())) {
+            bail!("a branch ends past the last message");
+        }
+        if current >= branches.len() {
+            bail!("the checked out branch does not exist");
+        }
+        Ok(Self { nodes, branches, current })
+    }
+}
+
+impl Default for Conversation {
+    fn default() -> Self {
+        Self {
+            nodes: Vec::new(),
+            branches: vec![Branch { name: "main".to_string(), tip: None }],
+            current: 0,
+        }
+    }
+}
+
+impl Conversation {
+    /// A single branch holding `messages`.
+    pub fn from_messages(messages: Vec<Message>) -> Self {
+        let mut conversation = Self::default();
+        for message in messages {
+            conversation.push(message);
+        }
+        conversation
+    }
+
+    /// The checked out branch's messages, from the first.
+    pub fn messages(&self) -> Vec<&Message> {
+        self.path(self.current().tip).into_iter().map(|i| &self.nodes[i].message).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.path(self.current().tip).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.current().tip.is_none()
+    }
+
+    /// Message `index` of the checked out branch, counting from 0.
+    pub fn get(&self, index: usize) -> Option<&Message> {
+        self.path(self.current().tip).get(index).map(|&i| &self.nodes[i].message)
+    }
+
+    /// Add `message` to the end of the checked out branch.
+    pub fn push(&mut self, message: Message) {
+        let parent = self.current().tip;
+        self.nodes.push(Node { parent, message });
+        self.branches[self.current].tip = Some(self.nodes.len() - 1);
+    }
+
+    /// Every message on every branch.
+    pub fn all_messages_mut(&mut self) -> impl Iterator<Item = &mut Message> {
+        self.nodes.iter_mut().map(|n| &mut n.message)
+    }
+
+    /// Every message on any branch, once each, in the order they were added.
+    pub fn every_message(&self) -> Vec<Placed<'_>> {
+        let mut branches = vec![Vec::new(); self.nodes.len()];
+        for branch in &self.branches {
+            for i in self.path(branch.tip) {
+                branches[i].push(branch.name.as_str());
+            }
+        }
+        let mut depth = vec![0; self.nodes.len()];
+        for (i, node) in self.nodes.iter().enumerate() {
+            depth[i] = node.parent.map_or(0, |p| depth[p] + 1);
+        }
+        self.nodes.iter()
+            .zip(branches)
+            .enumerate()
+            .filter(|(_, (_, branches))| !branches.is_empty())
+            .map(|(i, (node, branches))| Placed { index: depth[i], branches, message: &node.message })
+            .collect()
+    }
+
+    pub fn branches(&self) -> &[Branch] {
+        &self.branches
+    }
+
+    pub fn current(&self) -> &Branch {
+        &self.branches[self.current]
+    }
+
+    /// Start a branch from the first `at` messages of the checked out one
+    /// and check it out. Without a name it is called `fork-N`.
+    pub fn fork(&mut self, at: usize, name: Option<String>) -> Result<&Branch> {
+        let path = self.path(self.current().tip);
+        if at > path.len() {
+            bail!("Branch {} has only {} messages", self.current().name, path.len());
+        }
+        let name = match name {
+            Some(name) => {
+                if name.is_empty() || name.parse::<usize>().is_ok() || name.contains(char::is_whitespace) {
+                    bail!("Invalid branch name '{}': use a word that is not a number", name);
+                }
+                if self.find(&name).is_some() {
+                    bail!("There is already a branch named {}", name);
+                }
+                name
+            }
+            None => self.unused_name("fork"),
+        };
+        let tip = at.checked_sub(1).map(|i| path[i]);
+        self.branches.push(Branch { name, tip });
+        self.current = self.branches.len() - 1;
+        Ok(self.current())
+    }
+
+    /// Keep the checked out branch as it is on a new branch, `alt-N`, and
+    /// cut it back to its first `at` messages. Returns the new branch's name.
+    pub fn set_aside(&mut self, at: usize) -> String {
+        let name = self.unused_name("alt");
+        let path = self.path(self.current().tip);
+        self.branches.push(Branch { name: name.clone(), tip: self.current().tip });
+        self.branches[self.current].tip = at.min(path.len()).checked_sub(1).map(|i| path[i]);
+        name
+    }
+
+    /// Undo `set_aside`: the checked out branch gets back the messages kept
+    /// on `name`, which is removed.
+    pub fn take_back(&mut self, name: &str) -> Result<()> {
+        let index = self.find(name).ok_or_else(|| anyhow!("No branch named {}", name))?;
+        if index == self.current {
+            bail!("Cannot take back the checked out branch");
+        }
+        let kept = self.branches.remove(index);
+        if index < self.current {
+            self.current -= 1;
+        }
+        self.branches[self.current].tip = kept.tip;
+        Ok(())
+    }
+
+    /// Check out the branch with this name, or number as `tree` lists them.
+    pub fn checkout(&mut self, reference: &str) -> Result<&Branch> {
+        let index = match reference.parse::<usize>() {
+            Ok(n) if n >= 1 && n <= self.branches.len() => n - 1,
+            Ok(n) => bail!("No branch {} (see :branches)", n),
+            Err(_) => self.find(reference).ok_or_else(|| anyhow!("No branch named {} (see :branches)", reference))?,
+        };
+        self.current = index;
+        Ok(self.current())
+    }
+
+    /// The branches as a tree, one line for each run of messages they share,
+    /// numbered by position. A line names the branches ending there, `*`
+    /// marking the one checked out.
+    pub fn tree(&self) -> Vec<String> {
+        let mut children = vec![Vec::new(); self.nodes.len()];
+        let mut roots = Vec::new();
+        let mut depth = vec![0; self.nodes.len()];
+        for (i, node) in self.nodes.iter().enumerate() {
+            match node.parent {
+                Some(parent) => {
+                    children[parent].push(i);
+                    depth[i] = depth[parent] + 1;
+                }
+                None => roots.push(i),
+            }
+        }
+
+        let mut lines = Vec::new();
+        if self.branches.iter().any(|b| b.tip.is_none()) {
+            lines.push(format!("(no messages){}", self.labels(None)));
+        }
+        let tree = Tree { conversation: self, children: &children, depth: &depth };
+        for root in roots {
+            tree.segment(root, "", "", &mut lines);
+        }
+        lines
+    }
+
+    /// `  ← #1 main*` for the branches ending at `tip`.
+    fn labels(&self, tip: Option<usize>) -> String {
+        let names: Vec<String> = self.branches.iter()
+            .enumerate()
+            .filter(|(_, b)| b.tip == tip)
+            .map(|(i, b)| format!("#{} {}{}", i + 1, b.name, if i == self.current { "*" } else { "" }))
+            .collect();
+        match names.is_empty() {
+            true => String::new(),
+            false => format!("  ← {}", names.join(", ")),
+        }
+    }
+
+    /// Nodes from the first to `tip`.
+    fn path(&self, tip: Option<usize>) -> Vec<usize> {
+        let mut path: Vec<usize> = std::iter::successors(tip, |&i| self.nodes[i].parent).collect();
+        path.reverse();
+        path
+    }
+
+    fn find(&self, name: &str) -> Option<usize> {
+        self.branches.iter().position(|b| b.name == name)
+    }
+
+    fn unused_name(&self, prefix: &str) -> String {
+        (1..)
+            .map(|n| format!("{}-{}", prefix, n))
+            .find(|name| self.find(name).is_none())
+            .unwrap_or_default()
+    }
+}
+
+/// What drawing the tree needs to know about each message.
+struct Tree<'a> {
+    conversation: &'a Conversation,
+    children: &'a [Vec<usize>],
+    depth: &'a [usize],
+}
+
+impl Tree<'_> {
+    /// Add the line for the run of messages starting at `start`, which goes
+    /// on until the tree splits or a branch ends, then the runs after it.
+    fn segment(&self, start: usize, indent: &str, connector: &str, lines: &mut Vec<String>) {
+        let ends_branch = |node| self.conversation.branches.iter().any(|b| b.tip == Some(node));
+        let mut end = start;
+        while self.children[end].len() == 1 && !ends_branch(end) {
+            end = self.children[end][0];
+        }
+
+        let message = &self.conversation.nodes[start].message;
+        let first = message.content.lines().next().unwrap_or_default();
+        let preview: String = first.chars().take(50).collect();
+        let range = match (self.depth[start] + 1, self.depth[end] + 1) {
+            (a, b) if a == b => a.to_string(),
+            (a, b) => format!("{}-{}", a, b),
+        };
+        lines.push(format!(
+            "{}{}[{}] {}: {}{}",
+            indent, connector, range, message.role, preview, self.conversation.labels(Some(end))
+        ));
+
+        let indent = match connector {
+            "├─ " => format!("{}│  ", indent),
+            "└─ " => format!("{}   ", indent),
+            _ => indent.to_string(),
+        };
+        let children = &self.children[end];
+        for (i, &child) in children.iter().enumerate() {
+            let connector = if i + 1 == children.len() { "└─ " } else { "├─ " };
+            self.segment(child, &indent, connector, lines);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn message(role: &str, content: &str) -> Message {
+        Message {
+            role: role.to_string(),
+            content: content.to_string(),
+            timestamp: Utc::now(),
+            interrupted: false,
+            attachments: Vec::new(),
+        }
+    }
+
+    fn contents(conversation: &Conversation) -> Vec<&str> {
+        conversation.messages().iter().map(|m| m.content.as_str()).collect()
+    }
+
+    #[test]
+    fn test_fork_set_aside_and_checkout() -> Result<()> {
+        let mut conversation = Conversation::from_messages(vec![
+            message("user", "Scan 10.0.0.1"),
+            message("assistant", "Ports 22 and 443"),
+            message("user", "Check 443"),
+            message("assistant", "TLS 1.2"),
+        ]);
+
+        assert_eq!(conversation.fork(2, None)?.name, "fork-1");
+        conversation.push(message("user", "Check 22"));
+        assert_eq!(contents(&conversation), ["Scan 10.0.0.1", "Ports 22 and 443", "Check 22"]);
+        assert!(conversation.fork(9, None).is_err());
+        assert!(conversation.fork(0, Some("2".to_string())).is_err());
+
+        conversation.checkout("main")?;
+        assert_eq!(conversation.set_aside(2), "alt-1");
+        conversation.push(message("user", "Check 443 with sslscan"));
+        assert_eq!(conversation.len(), 3);
+        assert_eq!(conversation.checkout("3")?.name, "alt-1");
+        assert_eq!(conversation.get(3).map(|m| m.content.as_str()), Some("TLS 1.2"));
+        assert!(conversation.checkout("4").is_err());
+
+        assert_eq!(conversation.tree(), [
+            "[1-2] user: Scan 10.0.0.1",
+            "├─ [3-4] user: Check 443  ← #3 alt-1*",
+            "├─ [3] user: Check 22  ← #2 fork-1",
+            "└─ [3] user: Check 443 with sslscan  ← #1 main",
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_every_message_names_its_branches() -> Result<()> {
+        let mut conversation = Conversation::from_messages(vec![message("user", "Scan"), message("assistant", "Done")]);
+        conversation.fork(1, Some("quiet".to_string()))?;
+        conversation.push(message("assistant", "Done quietly"));
+        let kept = conversation.set_aside(1);
+        conversation.push(message("assistant", "Lost"));
+        conversation.take_back(&kept)?;
+
+        let placed: Vec<(usize, Vec<&str>, &str)> = conversation.every_message().into_iter()
+            .map(|p| (p.index, p.branches, p.message.content.as_str()))
+            .collect();
+        assert_eq!(placed, [
+            (0, vec!["main", "quiet"], "Scan"),
+            (1, vec!["main"], "Done"),
+            (1, vec!["quiet"], "Done quietly"),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_take_back_restores_the_branch() -> Result<()> {
+        let mut conversation = Conversation::from_messages(vec![message("user", "Scan"), message("assistant", "Done")]);
+        let kept = conversation.set_aside(0);
+        assert!(conversation.is_empty());
+        assert_eq!(conversation.tree()[0], "(no messages)  ← #1 main*");
+
+        conversation.take_back(&kept)?;
+        assert_eq!(contents(&conversation), ["Scan", "Done"]);
+        assert_eq!(conversation.branches().len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_broken_trees() {
+        let json = r#"{"nodes": [{"parent": 0, "message": {"role": "user", "content": "", "timestamp": "2024-01-01T00:00:00Z", "interrupted": false}}], "branches": [{"name": "main", "tip": 0}], "current": 0}"#;
+        assert!(serde_json::from_str::<Conversation>(json).is_err());
+    }
+}
@@ -1,9 +1,17 @@
 pub mod config;
 pub mod api;
+pub mod types;
+pub mod conversation;
+pub mod mcp;
+pub mod tools;
+pub mod sessions;
+pub mod audit;
+pub mod attachments;
 
 // Re-export main types
 pub use config::Config;
 pub use config::OutputFormat;
+pub use api::ClaudeClient;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -0,0 +1,563 @@
+use anyhow::{anyhow, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::audit::{AuditEvent, AuditLog};
+use crate::config::mcp::McpServer;
+
+mod sampling;
+mod transport;
+
+pub use sampling::{Sampler, SamplingMessage, SamplingRequest};
+use transport::Transport;
+
+const PROTOCOL_VERSION: &str = "2024-11-05";
+
+/// A tool as reported by a server's `tools/list`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RemoteTool {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(rename = "inputSchema", default = "empty_schema")]
+    pub input_schema: Value,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ToolOutput {
+    #[serde(default)]
+    pub content: Vec<Value>,
+    #[serde(rename = "isError", default)]
+    pub is_error: bool,
+}
+
+/// A resource listed by `resources/list`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Resource {
+    pub uri: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(rename = "mimeType", default)]
+    pub mime_type: Option<String>,
+}
+
+/// One item returned by `resources/read`: either text or base64 `blob`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResourceContents {
+    pub uri: String,
+    #[serde(rename = "mimeType", default)]
+    pub mime_type: Option<String>,
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub blob: Option<String>,
+}
+
+/// A prompt template listed by `prompts/list`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Prompt {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub arguments: Vec<PromptArgument>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PromptArgument {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub required: bool,
+}
+
+/// A message produced by `prompts/get`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PromptMessage {
+    pub role: String,
+    pub content: Value,
+}
+
+/// A workspace directory servers may operate on, as reported by `roots/list`.
+#[derive(Debug, Clone)]
+pub struct Root {
+    pub uri: String,
+    pub name: String,
+}
+
+impl Root {
+    /// The root for `path`, which should be absolute; its URI is percent-encoded.
+    pub fn directory(path: &Path) -> Self {
+        Self {
+            uri: reqwest::Url::from_directory_path(path)
+                .map_or_else(|_| format!("file://{}", path.display()), String::from),
+            name: path.file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.display().to_string()),
+        }
+    }
+}
+
+/// What this client offers servers beyond calling them: workspace roots and,
+/// when set, completions through `sampling/createMessage`. With `audit` set,
+/// every request in either direction is recorded.
+#[derive(Clone, Default)]
+pub struct ClientHost {
+    pub roots: Vec<Root>,
+    pub sampler: Option<Rc<Sampler>>,
+    pub audit: Option<Rc<AuditLog>>,
+}
+
+impl ClientHost {
+    fn capabilities(&self) -> Value {
+        let mut capabilities = json!({});
+        if !self.roots.is_empty() {
+            capabilities["roots"] = json!({ "listChanged": false });
+        }
+        if self.sampler.is_some() {
+            capabilities["sampling"] = json!({});
+        }
+        capabilities
+    }
+}
+
+fn empty_schema() -> Value {
+    json!({"type": "object", "properties": {}})
+}
+
+impl ToolOutput {
+    /// Flatten the result content into text for the model. Non-text items are
+    /// passed through as JSON so nothing the server returned is dropped.
+    pub fn text(&self) -> String {
+        self.content.iter()
+            .map(|item| match item.get("text").and_then(Value::as_str) {
+                Some(text) => text.to_string(),
+                None => item.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+impl ResourceContents {
+    /// Text for the model; binary contents are described rather than inlined.
+    pub fn text(&self) -> String {
+        match (&self.text, &self.blob) {
+            (Some(text), _) => text.clone(),
+            (None, Some(blob)) => format!(
+                "[binary {} resource, {} bytes base64]",
+                self.mime_type.as_deref().unwrap_or("unknown"),
+                blob.len()
+            ),
+            (None, None) => String::new(),
+        }
+    }
+}
+
+impl PromptMessage {
+    pub fn text(&self) -> String {
+        match self.content.get("type").and_then(Value::as_str) {
+            Some("text") => self.content["text"].as_str().unwrap_or_default().to_string(),
+            Some("resource") => serde_json::from_value::<ResourceContents>(self.content["resource"].clone())
+                .map(|r| r.text())
+                .unwrap_or_default(),
+            _ => self.content.to_string(),
+        }
+    }
+}
+
+/// JSON-RPC client for a single MCP server.
+pub struct McpClient {
+    server: McpServer,
+    transport: Transport,
+    host: ClientHost,
+    next_id: u64,
+    capabilities: Value,
+    /// `serverInfo` and `protocolVersion` from the initialize result
+    server_info: Value,
+    protocol_version: String,
+    /// Subscribed resources the server reported as changed, oldest first
+    updated_resources: Vec<String>,
+}
+
+impl McpClient {
+    /// Connect to `server` and perform the initialize handshake, offering it
+    /// whatever `host` provides.
+    pub async fn connect(server: &McpServer, host: &ClientHost) -> Result<Self> {
+        let mut client = Self {
+            server: server.clone(),
+            transport: Transport::open(server).await?,
+            host: host.clone(),
+            next_id: 1,
+            capabilities: Value::Null,
+            server_info: Value::Null,
+            protocol_version: String::new(),
+            updated_resources: Vec::new(),
+        };
+
+        let result = client.request("initialize", json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": host.capabilities(),
+            "clientInfo": {
+                "name": "claude-cli",
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+        })).await?;
+        client.capabilities = result.get("capabilities").cloned().unwrap_or(Value::Null);
+        client.server_info = result.get("serverInfo").cloned().unwrap_or(Value::Null);
+        client.protocol_version = result["protocolVersion"].as_str().unwrap_or_default().to_string();
+        client.notify("notifications/initialized", json!({})).await?;
+
+        Ok(client)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.server.name
+    }
+
+    /// Name and version the server reported, e.g. `"nmap-mcp 0.3.1"`.
+    pub fn server_info(&self) -> String {
+        let name = self.server_info["name"].as_str().unwrap_or("unknown");
+        match self.server_info["version"].as_str() {
+            Some(version) => format!("{} {}", name, version),
+            None => name.to_string(),
+        }
+    }
+
+    pub fn protocol_version(&self) -> &str {
+        &self.protocol_version
+    }
+
+    /// Whether the server advertised `capability` (e.g. `"resources"`) at initialize.
+    pub fn supports(&self, capability: &str) -> bool {
+        self.capabilities.get(capability).is_some_and(|c| !c.is_null())
+    }
+
+    pub async fn list_tools(&mut self) -> Result<Vec<RemoteTool>> {
+        if !self.supports("tools") {
+            return Ok(Vec::new());
+        }
+        self.list_paginated("tools/list", "tools").await
+    }
+
+    pub async fn list_resources(&mut self) -> Result<Vec<Resource>> {
+        if !self.supports("resources") {
+            return Ok(Vec::new());
+        }
+        self.list_paginated("resources/list", "resources").await
+    }
+
+    pub async fn read_resource(&mut self, uri: &str) -> Result<Vec<ResourceContents>> {
+        let result = self.request("resources/read", json!({ "uri": uri })).await?;
+        serde_json::from_value(result["contents"].clone()).context("Malformed resources/read response")
+    }
+
+    pub async fn subscribe(&mut self, uri: &str) -> Result<()> {
+        let subscribable = self.capabilities["resources"]["subscribe"].as_bool().unwrap_or(false);
+        if !subscribable {
+            return Err(anyhow!("{} does not support resource subscriptions", self.server.name));
+        }
+        self.request("resources/subscribe", json!({ "uri": uri })).await?;
+        Ok(())
+    }
+
+    pub async fn unsubscribe(&mut self, uri: &str) -> Result<()> {
+        self.request("resources/unsubscribe", json!({ "uri": uri })).await?;
+        Ok(())
+    }
+
+    pub async fn list_prompts(&mut self) -> Result<Vec<Prompt>> {
+        if !self.supports("prompts") {
+            return Ok(Vec::new());
+        }
+        self.list_paginated("prompts/list", "prompts").await
+    }
+
+    pub async fn get_prompt(
+        &mut self,
+        name: &str,
+        arguments: &HashMap<String, String>,
+    ) -> Result<Vec<PromptMessage>> {
+        let result = self.request("prompts/get", json!({
+            "name": name,
+            "arguments": arguments,
+        })).await?;
+        serde_json::from_value(result["messages"].clone()).context("Malformed prompts/get response")
+    }
+
+    /// Handle what the server sent while no request was waiting on it, such
+    /// as resource updates, without waiting for more. HTTP servers can only
+    /// send while a request is open, so this picks up little from them.
+    pub async fn poll(&mut self) -> Result<()> {
+        loop {
+            let received = match tokio::time::timeout(Duration::ZERO, self.transport.receive()).await {
+                Ok(received) => received.with_context(|| format!("{}: could not read", self.server.name))?,
+                Err(_) => return Ok(()),
+            };
+            match received {
+                Some(request) if request.get("method").is_some() && request.get("id").is_some() => {
+                    self.answer(request).await?
+                }
+                Some(notification) => self.handle_notification(&notification),
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Drain the URIs of subscribed resources the server reported as changed.
+    pub fn take_updated_resources(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.updated_resources)
+    }
+
+    async fn list_paginated<T: DeserializeOwned>(&mut self, method: &str, field: &str) -> Result<Vec<T>> {
+        let mut items = Vec::new();
+        let mut cursor: Option<String> = None;
+
+        loop {
+            let params = match &cursor {
+                Some(c) => json!({ "cursor": c }),
+                None => json!({}),
+            };
+            let result = self.request(method, params).await?;
+            let page: Vec<T> = serde_json::from_value(result[field].clone())
+                .with_context(|| format!("Malformed {} response", method))?;
+            items.extend(page);
+
+            cursor = result.get("nextCursor").and_then(Value::as_str).map(String::from);
+            if cursor.is_none() {
+                return Ok(items);
+            }
+        }
+    }
+
+    pub async fn call_tool(&mut self, name: &str, arguments: Value) -> Result<ToolOutput> {
+        let result = self.request("tools/call", json!({
+            "name": name,
+            "arguments": arguments,
+        })).await?;
+        serde_json::from_value(result).context("Malformed tools/call response")
+    }
+
+    async fn request(&mut self, method: &str, params: Value) -> Result<Value> {
+        let started = Instant::now();
+        let response = self.exchange(method, params.clone()).await;
+
+        if let Some(audit) = &self.host.audit {
+            let recorded = match &response {
+                Ok(response) => response.get("result").or(response.get("error")).cloned().unwrap_or(Value::Null),
+                Err(e) => json!({ "error": { "message": format!("{:#}", e) } }),
+            };
+            audit.record(AuditEvent::McpExchange {
+                server: self.server.name.clone(),
+                method: method.to_string(),
+                params,
+                response: recorded,
+                duration_ms: started.elapsed().as_millis() as u64,
+                from_server: false,
+            })?;
+        }
+
+        let response = response?;
+        if let Some(error) = response.get("error") {
+            return Err(anyhow!(
+                "{}: {} failed: {}",
+                self.server.name,
+                method,
+                error.get("message").and_then(Value::as_str).unwrap_or("unknown error")
+            ));
+        }
+
+        Ok(response.get("result").cloned().unwrap_or(Value::Null))
+    }
+
+    /// Send a request and wait for the matching response message.
+    async fn exchange(&mut self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let message = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        });
+
+        self.transport.send(&message).await
+            .with_context(|| format!("{}: {} failed", self.server.name, method))?;
+
+        // Servers may send their own requests and notifications before answering
+        loop {
+            let received = self.transport.receive().await
+                .with_context(|| format!("{}: {} failed", self.server.name, method))?
+                .ok_or_else(|| anyhow!("{}: no response to {}", self.server.name, method))?;
+            let is_request = received.get("method").is_some();
+            match received.get("id") {
+                Some(received_id) if !is_request && received_id.as_u64() == Some(id) => return Ok(received),
+                Some(_) if is_request => self.answer(received).await?,
+                _ => self.handle_notification(&received),
+            }
+        }
+    }
+
+    /// Reply to a request the server sent us.
+    async fn answer(&mut self, request: Value) -> Result<()> {
+        let started = Instant::now();
+        let method = request["method"].as_str().unwrap_or_default();
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+
+        let result = match method {
+            "ping" => Ok(json!({})),
+            "roots/list" if !self.host.roots.is_empty() => Ok(json!({
+                "roots": self.host.roots.iter()
+                    .map(|r| json!({ "uri": r.uri, "name": r.name }))
+                    .collect::<Vec<_>>(),
+            })),
+            "sampling/createMessage" => match self.host.sampler.clone() {
+                Some(sampler) => sampler.create_message(&self.server.name, params).await
+                    .map_err(|e| (-1, format!("{:#}", e))),
+                None => Err((-32601, String::from("Sampling is not enabled"))),
+            },
+            _ => Err((-32601, format!("Unknown method: {}", method))),
+        };
+
+        let reply = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }),
+            Err((code, message)) => json!({
+                "jsonrpc": "2.0",
+                "id": request["id"],
+                "error": { "code": code, "message": message },
+            }),
+        };
+
+        if let Some(audit) = &self.host.audit {
+            audit.record(AuditEvent::McpExchange {
+                server: self.server.name.clone(),
+                method: method.to_string(),
+                params: request.get("params").cloned().unwrap_or(Value::Null),
+                response: reply.get("result").or(reply.get("error")).cloned().unwrap_or(Value::Null),
+                duration_ms: started.elapsed().as_millis() as u64,
+                from_server: true,
+            })?;
+        }
+        self.transport.send(&reply).await
+    }
+
+    fn handle_notification(&mut self, message: &Value) {
+        if message.get("method").and_then(Value::as_str) == Some("notifications/resources/updated") {
+            if let Some(uri) = message["params"]["uri"].as_str() {
+                if !self.updated_resources.iter().any(|u| u == uri) {
+                    self.updated_resources.push(uri.to_string());
+                }
+            }
+        }
+    }
+
+    async fn notify(&mut self, method: &str, params: Value) -> Result<()> {
+        let message = json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+        });
+        self.transport.send(&message).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prompt_message_text() {
+        let text: PromptMessage = serde_json::from_value(json!({
+            "role": "user",
+            "content": {"type": "text", "text": "Review this host"}
+        })).unwrap();
+        let embedded: PromptMessage = serde_json::from_value(json!({
+            "role": "user",
+            "content": {"type": "resource", "resource": {"uri": "file:///scan.xml", "text": "<nmaprun/>"}}
+        })).unwrap();
+
+        assert_eq!(text.text(), "Review this host");
+        assert_eq!(embedded.text(), "<nmaprun/>");
+    }
+
+    #[test]
+    fn test_tool_output_text() {
+        let output: ToolOutput = serde_json::from_value(json!({
+            "content": [
+                {"type": "text", "text": "22/tcp open"},
+                {"type": "image", "data": "AAAA", "mimeType": "image/png"}
+            ]
+        })).unwrap();
+
+        assert!(!output.is_error);
+        assert!(output.text().starts_with("22/tcp open\n"));
+        assert!(output.text().contains("image/png"));
+    }
+
+    #[tokio::test]
+    async fn test_answers_server_requests_while_waiting() -> Result<()> {
+        // Asks for roots in the middle of a tool call, then echoes our reply back
+        let script = r#"
+            read init
+            echo '{"jsonrpc":"2.0","id":1,"result":{"protocolVersion":"2024-11-05","capabilities":{"tools":{}}}}'
+            read initialized
+            read call
+            echo '{"jsonrpc":"2.0","id":"s1","method":"roots/list"}'
+            read roots
+            echo "{\"jsonrpc\":\"2.0\",\"id\":2,\"result\":{\"reply\":$roots}}"
+        "#;
+        let server = McpServer {
+            name: "roots".to_string(),
+            command: Some("sh".to_string()),
+            args: vec!["-c".to_string(), script.to_string()],
+            ..McpServer::default()
+        };
+        let host = ClientHost {
+            roots: vec![Root::directory(Path::new("/work/project"))],
+            ..ClientHost::default()
+        };
+
+        let mut client = McpClient::connect(&server, &host).await?;
+        let result = client.request("tools/call", json!({ "name": "scan" })).await?;
+
+        assert_eq!(result["reply"]["id"], "s1");
+        assert_eq!(result["reply"]["result"]["roots"][0]["uri"], "file:///work/project/");
+        assert_eq!(result["reply"]["result"]["roots"][0]["name"], "project");
+        assert_eq!(Root::directory(Path::new("/work/my scans #2")).uri, "file:///work/my%20scans%20%232/");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_poll_picks_up_updates_sent_while_idle() -> Result<()> {
+        let script = r#"
+            read init
+            echo '{"jsonrpc":"2.0","id":1,"result":{"protocolVersion":"2024-11-05","capabilities":{"resources":{"subscribe":true}}}}'
+            read initialized
+            echo '{"jsonrpc":"2.0","method":"notifications/resources/updated","params":{"uri":"file:///scan.xml"}}'
+            read never
+        "#;
+        let server = McpServer {
+            name: "watch".to_string(),
+            command: Some("sh".to_string()),
+            args: vec!["-c".to_string(), script.to_string()],
+            ..McpServer::default()
+        };
+
+        let mut client = McpClient::connect(&server, &ClientHost::default()).await?;
+        let started = Instant::now();
+        while client.updated_resources.is_empty() && started.elapsed() < Duration::from_secs(5) {
+            client.poll().await?;
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(client.take_updated_resources(), ["file:///scan.xml"]);
+        Ok(())
+    }
+}
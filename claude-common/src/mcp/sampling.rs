@@ -0,0 +1,124 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+
+use crate::api::{ClaudeClient, Message};
+use crate::tools::Approver;
+
+/// A `sampling/createMessage` request from a server.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SamplingRequest {
+    pub messages: Vec<SamplingMessage>,
+    #[serde(default)]
+    pub system_prompt: Option<String>,
+    pub max_tokens: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SamplingMessage {
+    pub role: String,
+    pub content: Value,
+}
+
+impl SamplingMessage {
+    pub fn text(&self) -> Result<&str> {
+        match self.content.get("type").and_then(Value::as_str) {
+            Some("text") => self.content["text"].as_str().context("Text content without text"),
+            Some(other) => bail!("Unsupported sampling content type '{}'", other),
+            None => bail!("Sampling message without content type"),
+        }
+    }
+}
+
+/// Answers servers' sampling requests through the API, with the user's
+/// approval and never above `max_tokens`.
+pub struct Sampler {
+    client: ClaudeClient,
+    model: String,
+    max_tokens: u32,
+    approver: RefCell<Box<dyn Approver>>,
+}
+
+impl Sampler {
+    pub fn new(client: ClaudeClient, model: String, max_tokens: u32, approver: Box<dyn Approver>) -> Self {
+        Self {
+            client,
+            model,
+            max_tokens,
+            approver: RefCell::new(approver),
+        }
+    }
+
+    /// Handle `sampling/createMessage` params from `server`, returning the result.
+    pub async fn create_message(&self, server: &str, params: Value) -> Result<Value> {
+        let request: SamplingRequest = serde_json::from_value(params)
+            .context("Malformed sampling/createMessage request")?;
+        let messages = request.messages.iter()
+            .map(|m| Ok(Message::text(&m.role, m.text()?)))
+            .collect::<Result<Vec<_>>>()?;
+        let max_tokens = request.max_tokens.min(self.max_tokens);
+
+        let approved = self.approver.borrow_mut().approve_sampling(server, &request, max_tokens)?;
+        if !approved {
+            tracing::info!("Denied sampling request from {}", server);
+            bail!("User rejected sampling request");
+        }
+
+        tracing::info!("Sampling for {} (max {} tokens)", server, max_tokens);
+        let response = self.client
+            .complete(&self.model, request.system_prompt.as_deref(), max_tokens, &messages)
+            .await?;
+
+        Ok(json!({
+            "role": "assistant",
+            "content": { "type": "text", "text": response.text() },
+            "model": self.model,
+            "stopReason": stop_reason(response.stop_reason.as_deref()),
+        }))
+    }
+}
+
+/// Map the API's stop reasons onto the names MCP uses.
+fn stop_reason(reason: Option<&str>) -> Option<String> {
+    reason.map(|r| match r {
+        "end_turn" => "endTurn".to_string(),
+        "max_tokens" => "maxTokens".to_string(),
+        "stop_sequence" => "stopSequence".to_string(),
+        other => other.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::NonInteractive;
+
+    #[tokio::test]
+    async fn test_rejected_without_approval() {
+        let sampler = Sampler::new(
+            ClaudeClient::new(String::new()),
+            "claude-3-sonnet".to_string(),
+            512,
+            Box::new(NonInteractive),
+        );
+        let error = sampler.create_message("nmap", json!({
+            "messages": [{ "role": "user", "content": { "type": "text", "text": "Summarise" } }],
+            "maxTokens": 100
+        })).await.unwrap_err();
+        assert_eq!(error.to_string(), "User rejected sampling request");
+
+        let image = sampler.create_message("nmap", json!({
+            "messages": [{ "role": "user", "content": { "type": "image", "data": "AAAA" } }],
+            "maxTokens": 100
+        })).await.unwrap_err();
+        assert!(image.to_string().contains("Unsupported sampling content type"));
+    }
+
+    #[test]
+    fn test_stop_reason() {
+        assert_eq!(stop_reason(Some("end_turn")).as_deref(), Some("endTurn"));
+        assert_eq!(stop_reason(None), None);
+    }
+}
@@ -0,0 +1,206 @@
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+
+use crate::config::mcp::McpServer;
+
+/// How JSON-RPC messages reach a server: a child process speaking
+/// newline-delimited JSON on stdio, or an HTTP endpoint.
+pub enum Transport {
+    Stdio {
+        // Held so the process is killed when the transport is dropped
+        _child: Box<Child>,
+        stdin: ChildStdin,
+        stdout: Lines<BufReader<ChildStdout>>,
+    },
+    Http {
+        client: reqwest::Client,
+        url: String,
+        session_id: Option<String>,
+        /// Messages already received but not yet handed out
+        pending: VecDeque<Value>,
+        /// Event stream still being read, so server requests sent mid-stream
+        /// can be answered before the final response arrives
+        stream: Option<Box<EventStream>>,
+    },
+}
+
+pub struct EventStream {
+    response: reqwest::Response,
+    /// Bytes of a partly received event; chunks can split UTF-8 sequences
+    buffer: Vec<u8>,
+}
+
+impl Transport {
+    pub async fn open(server: &McpServer) -> Result<Self> {
+        let Some(command) = &server.command else {
+            return Ok(Transport::Http {
+                client: reqwest::Client::new(),
+                url: server.url.clone(),
+                session_id: None,
+                pending: VecDeque::new(),
+                stream: None,
+            });
+        };
+
+        let mut child = Command::new(command)
+            .args(&server.args)
+            .envs(&server.env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Could not launch '{}'", command))?;
+
+        let stdin = child.stdin.take().context("Server stdin unavailable")?;
+        let stdout = child.stdout.take().context("Server stdout unavailable")?;
+
+        Ok(Transport::Stdio {
+            _child: Box::new(child),
+            stdin,
+            stdout: BufReader::new(stdout).lines(),
+        })
+    }
+
+    /// Send one message without waiting for anything back.
+    pub async fn send(&mut self, message: &Value) -> Result<()> {
+        match self {
+            Transport::Stdio { stdin, .. } => {
+                let mut line = serde_json::to_string(message)?;
+                line.push('\n');
+                stdin.write_all(line.as_bytes()).await?;
+                stdin.flush().await?;
+                Ok(())
+            }
+            Transport::Http { client, url, session_id, pending, stream } => {
+                let mut request = client
+                    .post(url.as_str())
+                    .header("Accept", "application/json, text/event-stream")
+                    .json(message);
+                if let Some(session_id) = session_id.as_ref() {
+                    request = request.header("Mcp-Session-Id", session_id);
+                }
+
+                let response = request.send().await
+                    .with_context(|| format!("Could not reach {}", url))?;
+                if !response.status().is_success() {
+                    return Err(anyhow!("HTTP {}", response.status()));
+                }
+
+                if let Some(id) = response.headers().get("Mcp-Session-Id") {
+                    *session_id = id.to_str().ok().map(String::from);
+                }
+
+                let is_stream = response.headers()
+                    .get(reqwest::header::CONTENT_TYPE)
+                    .and_then(|v| v.to_str().ok())
+                    .is_some_and(|v| v.starts_with("text/event-stream"));
+                if is_stream {
+                    // The server ends each stream once it has answered, so any
+                    // earlier stream has nothing left worth waiting for
+                    *stream = Some(Box::new(EventStream {
+                        response,
+                        buffer: Vec::new(),
+                    }));
+                    return Ok(());
+                }
+
+                let body = response.text().await?;
+                if body.trim().is_empty() {
+                    return Ok(());
+                }
+                match serde_json::from_str(&body)? {
+                    Value::Array(batch) => pending.extend(batch),
+                    single => pending.push_back(single),
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Next message from the server, or `None` once nothing more will arrive.
+    pub async fn receive(&mut self) -> Result<Option<Value>> {
+        match self {
+            Transport::Stdio { stdout, .. } => {
+                while let Some(line) = stdout.next_line().await? {
+                    // Servers occasionally log to stdout; skip anything that isn't JSON
+                    if let Ok(value) = serde_json::from_str::<Value>(&line) {
+                        return Ok(Some(value));
+                    }
+                }
+                Ok(None)
+            }
+            Transport::Http { pending, stream, .. } => {
+                if let Some(message) = pending.pop_front() {
+                    return Ok(Some(message));
+                }
+                while let Some(events) = stream.as_mut() {
+                    if let Some(end) = events.buffer.windows(2).position(|w| w == b"\n\n") {
+                        let event: Vec<u8> = events.buffer.drain(..end + 2).collect();
+                        if let Some(message) = parse_event_stream(&String::from_utf8_lossy(&event)).pop() {
+                            return Ok(Some(message));
+                        }
+                        continue;
+                    }
+                    match events.response.chunk().await? {
+                        Some(chunk) => events.buffer.extend(chunk.iter().filter(|&&b| b != b'\r')),
+                        None => {
+                            let rest = std::mem::take(&mut events.buffer);
+                            *stream = None;
+                            pending.extend(parse_event_stream(&String::from_utf8_lossy(&rest)));
+                            return Ok(pending.pop_front());
+                        }
+                    }
+                }
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Pull the JSON payloads out of a `text/event-stream` body.
+fn parse_event_stream(body: &str) -> Vec<Value> {
+    body.split("\n\n")
+        .filter_map(|event| {
+            let data: Vec<&str> = event.lines()
+                .filter_map(|line| line.strip_prefix("data:"))
+                .map(str::trim_start)
+                .collect();
+            serde_json::from_str(&data.join("\n")).ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_event_stream() {
+        let body = "event: message\ndata: {\"jsonrpc\":\"2.0\",\"method\":\"notifications/progress\"}\n\n\
+                    event: message\ndata: {\"jsonrpc\":\"2.0\",\"id\":2,\"result\":{}}\n\n";
+        let messages = parse_event_stream(body);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1]["id"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_stdio_round_trip() -> Result<()> {
+        // `cat` echoes the request back, which is enough to exercise framing
+        let server = McpServer {
+            name: "echo".to_string(),
+            command: Some("cat".to_string()),
+            ..McpServer::default()
+        };
+        let mut transport = Transport::open(&server).await?;
+
+        let request = serde_json::json!({"jsonrpc": "2.0", "id": 7, "result": {}});
+        transport.send(&request).await?;
+        assert_eq!(transport.receive().await?, Some(request));
+        Ok(())
+    }
+}
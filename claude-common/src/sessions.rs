@@ -0,0 +1,301 @@
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+use crate::types::Session;
+
+/// Saved conversations, one JSON file per session under `<config_dir>/sessions`.
+/// Images and documents are kept once each under `sessions/blobs`, named by
+/// the SHA-256 of their bytes, and referred to from the JSON.
+pub struct SessionStore {
+    dir: PathBuf,
+}
+
+/// A message matching a history search.
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub session: String,
+    /// Position of the message on its branches
+    pub index: usize,
+    /// Branches the message is on
+    pub branches: Vec<String>,
+    pub role: String,
+    pub content: String,
+}
+
+impl SessionStore {
+    pub fn new(config_dir: &Path) -> Self {
+        Self {
+            dir: config_dir.join("sessions"),
+        }
+    }
+
+    pub fn save(&self, session: &Session) -> Result<()> {
+        let path = self.path(&session.id)?;
+        std::fs::create_dir_all(&self.dir)?;
+        let mut session = session.clone();
+        for attachment in session.conversation.all_messages_mut().flat_map(|m| &mut m.attachments) {
+            if let Some(data) = attachment.data.take() {
+                attachment.blob = Some(self.store_blob(&data)?);
+            }
+        }
+        std::fs::write(path, serde_json::to_string_pretty(&session)?)?;
+        Ok(())
+    }
+
+    /// A saved session with its images and documents read back in. One
+    /// whose blob has gone is kept without its bytes.
+    pub fn load(&self, name: &str) -> Result<Session> {
+        let mut session = self.read(name)?;
+        for attachment in session.conversation.all_messages_mut().flat_map(|m| &mut m.attachments) {
+            let Some(hash) = &attachment.blob else {
+                continue;
+            };
+            match self.load_blob(hash) {
+                Ok(data) => attachment.data = Some(data),
+                Err(e) => tracing::warn!("Session {}: {:#}", name, e),
+            }
+        }
+        Ok(session)
+    }
+
+    /// A saved session as stored, with images and documents only naming
+    /// their blobs.
+    pub fn read(&self, name: &str) -> Result<Session> {
+        let path = self.path(name)?;
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("No saved session named '{}'", name))?;
+        let mut session: Session = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid session file {}", path.display()))?;
+        session.id = name.to_string();
+        Ok(session)
+    }
+
+    /// Names of the saved sessions, without reading them.
+    pub fn names(&self) -> Result<Vec<String>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut names = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+                names.push(name.to_string());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Every readable session, most recently updated first.
+    pub fn list(&self) -> Result<Vec<Session>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut sessions = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            match self.read(name) {
+                Ok(session) => sessions.push(session),
+                Err(e) => tracing::warn!("Skipping session {}: {:#}", path.display(), e),
+            }
+        }
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.updated_at));
+        Ok(sessions)
+    }
+
+    /// Case-insensitive substring search over the saved messages of every
+    /// branch of each session.
+    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>> {
+        let query = query.to_lowercase();
+        let mut hits = Vec::new();
+        for session in self.list()? {
+            for placed in session.conversation.every_message() {
+                if hits.len() == limit {
+                    return Ok(hits);
+                }
+                let m = placed.message;
+                if m.content.to_lowercase().contains(&query) {
+                    hits.push(SearchHit {
+                        session: session.id.clone(),
+                        index: placed.index,
+                        branches: placed.branches.iter().map(|b| b.to_string()).collect(),
+                        role: m.role.clone(),
+                        content: m.content.clone(),
+                    });
+                }
+            }
+        }
+        Ok(hits)
+    }
+
+    /// Write the bytes base64 `data` holds to the blob named by their hash,
+    /// unless it is already there, and return the hash.
+    fn store_blob(&self, data: &str) -> Result<String> {
+        let bytes = base64::engine::general_purpose::STANDARD.decode(data)
+            .context("Attachment data is not valid base64")?;
+        let hash = format!("{:x}", Sha256::digest(&bytes));
+        let path = self.dir.join("blobs").join(&hash);
+        if !path.exists() {
+            std::fs::create_dir_all(self.dir.join("blobs"))?;
+            // Written aside and renamed, so a blob is never seen half written
+            let partial = path.with_extension(format!("{}.tmp", std::process::id()));
+            std::fs::write(&partial, &bytes)?;
+            std::fs::rename(&partial, &path)?;
+        }
+        Ok(hash)
+    }
+
+    fn load_blob(&self, hash: &str) -> Result<String> {
+        if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            bail!("Invalid blob name '{}'", hash);
+        }
+        let bytes = std::fs::read(self.dir.join("blobs").join(hash))
+            .with_context(|| format!("Missing blob {}", hash))?;
+        Ok(base64::engine::general_purpose::STANDARD.encode(bytes))
+    }
+
+    /// Session names become file names, so keep them inside the directory.
+    fn path(&self, name: &str) -> Result<PathBuf> {
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            bail!("Invalid session name '{}'", name);
+        }
+        Ok(self.dir.join(format!("{}.json", name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conversation::Conversation;
+    use crate::types::Message;
+    use chrono::Utc;
+
+    fn session(id: &str, messages: &[(&str, &str)]) -> Session {
+        Session {
+            id: id.to_string(),
+            model: "claude-3-sonnet".to_string(),
+            conversation: Conversation::from_messages(messages.iter()
+                .map(|(role, content)| Message {
+                    role: role.to_string(),
+                    content: content.to_string(),
+                    timestamp: Utc::now(),
+                    interrupted: false,
+                    attachments: Vec::new(),
+                })
+                .collect()),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_save_list_search() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let store = SessionStore::new(dir.path());
+        store.save(&session("recon", &[("user", "Scan 10.0.0.1"), ("assistant", "Port 22 is open")]))?;
+        store.save(&session("notes", &[("user", "nothing relevant")]))?;
+
+        assert_eq!(store.list()?.len(), 2);
+        assert_eq!(store.load("recon")?.conversation.len(), 2);
+
+        let hits = store.search("PORT 22", 10)?;
+        assert_eq!(hits.len(), 1);
+        assert_eq!((hits[0].session.as_str(), hits[0].index), ("recon", 1));
+
+        // Branches other than the checked out one are searched too
+        let mut recon = store.load("recon")?;
+        recon.conversation.fork(1, Some("udp".to_string()))?;
+        recon.conversation.push(Message {
+            role: "assistant".to_string(),
+            content: "Port 161 is open".to_string(),
+            timestamp: Utc::now(),
+            interrupted: false,
+            attachments: Vec::new(),
+        });
+        recon.conversation.checkout("main")?;
+        store.save(&recon)?;
+        let hits = store.search("port 161", 10)?;
+        assert_eq!(hits.len(), 1);
+        assert_eq!((hits[0].index, hits[0].branches.as_slice()), (1, ["udp".to_string()].as_slice()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_media_saved_as_blobs() -> Result<()> {
+        use crate::types::{Attachment, AttachmentKind};
+
+        let dir = tempfile::tempdir()?;
+        let store = SessionStore::new(dir.path());
+        let mut saved = session("shots", &[("user", "What is on screen?")]);
+        let image = Attachment::media(AttachmentKind::Image, "shot.png".to_string(), "image/png", b"\x89PNG\r\n\x1a\nscreen");
+        for message in saved.conversation.all_messages_mut() {
+            message.attachments = vec![image.clone(), image.clone()];
+        }
+        store.save(&saved)?;
+
+        let json = std::fs::read_to_string(dir.path().join("sessions/shots.json"))?;
+        assert!(!json.contains(image.data.as_deref().unwrap_or_default()));
+        let blobs: Vec<_> = std::fs::read_dir(dir.path().join("sessions/blobs"))?.collect::<Result<_, _>>()?;
+        assert_eq!(blobs.len(), 1);
+        assert_eq!(std::fs::read(blobs[0].path())?, b"\x89PNG\r\n\x1a\nscreen");
+
+        let loaded = store.load("shots")?;
+        assert_eq!(loaded.conversation.messages()[0].attachments[0].data, image.data);
+
+        // A lost blob leaves the attachment without its bytes
+        std::fs::remove_file(blobs[0].path())?;
+        let loaded = store.load("shots")?;
+        let attachment = &loaded.conversation.messages()[0].attachments[1];
+        assert_eq!(attachment.data, None);
+        assert!(attachment.blob.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_loads_flat_sessions() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("sessions"))?;
+        let message = |role: &str, content: &str| format!(
+            r#"{{"role": "{}", "content": "{}", "timestamp": "2024-05-01T10:00:00Z", "interrupted": false}}"#, role, content
+        );
+        let flat = format!(
+            r#"{{"id": "old", "model": "claude-3-opus", "messages": [{}, {}],
+                "created_at": "2024-05-01T10:00:00Z", "updated_at": "2024-05-01T10:05:00Z"}}"#,
+            message("user", "Scan 10.0.0.1"), message("assistant", "Port 22"),
+        );
+        std::fs::write(dir.path().join("sessions/old.json"), flat)?;
+
+        let store = SessionStore::new(dir.path());
+        let mut session = store.load("old")?;
+        assert_eq!(session.conversation.current().name, "main");
+        assert_eq!(session.conversation.len(), 2);
+        session.conversation.fork(1, Some("retry".to_string()))?;
+
+        // Saved again, the tree comes back as it was
+        store.save(&session)?;
+        let again = store.load("old")?;
+        assert_eq!(again.conversation.current().name, "retry");
+        assert_eq!(again.conversation.tree(), session.conversation.tree());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_path_names() {
+        let store = SessionStore::new(Path::new("/tmp"));
+        assert!(store.load("../config").is_err());
+        assert!(store.load(".hidden").is_err());
+    }
+}
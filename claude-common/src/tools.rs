@@ -0,0 +1,474 @@
+use anyhow::Result;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::time::Instant;
+
+use crate::api::{ClaudeClient, ContentBlock, Message, ToolDefinition};
+use crate::audit::{AuditEvent, Decision};
+use crate::config::mcp::McpServer;
+use crate::config::permissions::{Permission, PermissionPolicy};
+use crate::mcp::{ClientHost, McpClient, RemoteTool, SamplingRequest};
+
+mod builtin;
+mod patch;
+mod shell;
+
+pub use builtin::{BuiltinTools, BUILTIN_SERVER};
+pub use shell::{Shell, ShellRun};
+pub(crate) use shell::read_pipes;
+
+/// Upper bound on tool round trips for a single user message.
+const MAX_TOOL_ROUNDS: usize = 25;
+
+/// A tool call the model asked for, resolved to the server that provides it.
+#[derive(Debug, Clone)]
+pub struct ToolCall {
+    pub server: String,
+    pub tool: String,
+    pub arguments: Value,
+    /// Readable summary of what the call will do, shown instead of the raw
+    /// arguments when there is one (e.g. the diff of an edit)
+    pub preview: Option<String>,
+    /// Whether "always" may be offered; not when an `ask` rule or the tool
+    /// itself would keep asking regardless
+    pub always: bool,
+}
+
+/// Answer to a tool call the permission policy says to ask about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Approval {
+    Once,
+    Always,
+    Deny,
+}
+
+/// Decides tool calls the policy leaves to the user.
+pub trait Approver {
+    fn approve(&mut self, call: &ToolCall) -> Result<Approval>;
+
+    /// Whether `server` may have a completion of up to `max_tokens` tokens.
+    /// Denied unless the approver knows how to ask.
+    fn approve_sampling(&mut self, _server: &str, _request: &SamplingRequest, _max_tokens: u32) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+/// Approver for scripts: anything not explicitly allowed is denied.
+pub struct NonInteractive;
+
+impl Approver for NonInteractive {
+    fn approve(&mut self, _call: &ToolCall) -> Result<Approval> {
+        Ok(Approval::Deny)
+    }
+}
+
+/// Where a registered tool runs.
+#[derive(Clone, Copy)]
+enum Provider {
+    /// Index into `ToolRegistry::clients`
+    Mcp(usize),
+    Builtin,
+}
+
+struct RegisteredTool {
+    /// Name exposed to the model, unique across servers
+    name: String,
+    provider: Provider,
+    tool: RemoteTool,
+}
+
+/// Every tool available to the model, with permission checks on each call.
+pub struct ToolRegistry {
+    clients: Vec<McpClient>,
+    builtin: Option<BuiltinTools>,
+    tools: Vec<RegisteredTool>,
+    policy: PermissionPolicy,
+    /// Roots and sampling offered to every server connected
+    host: ClientHost,
+}
+
+impl ToolRegistry {
+    pub fn new(policy: PermissionPolicy, host: ClientHost) -> Self {
+        Self {
+            clients: Vec::new(),
+            builtin: None,
+            tools: Vec::new(),
+            policy,
+            host,
+        }
+    }
+
+    /// Connect to each server and register its tools. Servers that fail are
+    /// reported and skipped so one broken server does not block the session.
+    pub async fn connect(servers: &[&McpServer], policy: PermissionPolicy, host: ClientHost) -> Self {
+        let mut registry = Self::new(policy, host);
+        for server in servers {
+            if let Err(e) = registry.add_server(server).await {
+                tracing::warn!("Skipping MCP server {}: {:#}", server.name, e);
+                eprintln!("Warning: MCP server '{}' unavailable: {:#}", server.name, e);
+            }
+        }
+        registry
+    }
+
+    pub async fn add_server(&mut self, server: &McpServer) -> Result<()> {
+        let mut client = McpClient::connect(server, &self.host).await?;
+        let tools = client.list_tools().await?;
+
+        let index = self.clients.len();
+        for tool in tools {
+            let name = unique_name(&server.name, &tool.name, |name| self.tools.iter().any(|t| t.name == name));
+            self.tools.push(RegisteredTool {
+                name,
+                provider: Provider::Mcp(index),
+                tool,
+            });
+        }
+        self.clients.push(client);
+        Ok(())
+    }
+
+    /// Offer the built-in file tools under their own names, alongside MCP tools.
+    pub fn add_builtin(&mut self, builtin: BuiltinTools) {
+        for tool in builtin.definitions() {
+            self.tools.push(RegisteredTool {
+                name: tool.name.clone(),
+                provider: Provider::Builtin,
+                tool,
+            });
+        }
+        self.builtin = Some(builtin);
+    }
+
+    /// Commands the `bash` tool ran since the last call, for the session log.
+    pub fn take_shell_runs(&mut self) -> Vec<ShellRun> {
+        self.builtin.as_mut().map(BuiltinTools::take_shell_runs).unwrap_or_default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    /// Every tool with the server providing it, in the order registered.
+    pub fn tools(&self) -> Vec<(&str, &RemoteTool)> {
+        self.tools.iter()
+            .map(|t| (self.server_name(t.provider), &t.tool))
+            .collect()
+    }
+
+    fn server_name(&self, provider: Provider) -> &str {
+        match provider {
+            Provider::Mcp(client) => self.clients[client].name(),
+            Provider::Builtin => BUILTIN_SERVER,
+        }
+    }
+
+    /// Connected servers, for resources, prompts and other non-tool requests.
+    pub fn clients_mut(&mut self) -> impl Iterator<Item = &mut McpClient> {
+        self.clients.iter_mut()
+    }
+
+    pub fn client_mut(&mut self, server: &str) -> Option<&mut McpClient> {
+        self.clients.iter_mut().find(|c| c.name() == server)
+    }
+
+    pub fn definitions(&self) -> Vec<ToolDefinition> {
+        self.tools.iter()
+            .map(|t| ToolDefinition {
+                name: t.name.clone(),
+                description: t.tool.description.clone().unwrap_or_default(),
+                input_schema: t.tool.input_schema.clone(),
+            })
+            .collect()
+    }
+
+    /// Run one tool call through the permission policy and, if permitted,
+    /// the server. Failures and denials become error results for the model.
+    pub async fn execute(
+        &mut self,
+        id: &str,
+        name: &str,
+        arguments: Value,
+        approver: &mut dyn Approver,
+    ) -> ContentBlock {
+        let result = match self.tools.iter().position(|t| t.name == name) {
+            Some(index) => self.execute_registered(index, arguments, approver).await,
+            None => Err(anyhow::anyhow!("Unknown tool: {}", name)),
+        };
+
+        let (content, is_error) = match result {
+            Ok(output) => output,
+            Err(e) => (format!("{:#}", e), true),
+        };
+
+        ContentBlock::ToolResult {
+            tool_use_id: id.to_string(),
+            content,
+            is_error,
+        }
+    }
+
+    async fn execute_registered(
+        &mut self,
+        index: usize,
+        arguments: Value,
+        approver: &mut dyn Approver,
+    ) -> Result<(String, bool)> {
+        let registered = &self.tools[index];
+        let provider = registered.provider;
+        let mut call = ToolCall {
+            server: self.server_name(provider).to_string(),
+            tool: registered.tool.name.clone(),
+            arguments,
+            preview: None,
+            always: false,
+        };
+
+        let mut permission = self.policy.evaluate(&call.server, &call.tool, &call.arguments);
+        call.always = self.policy.can_allow_always(&call.server, &call.tool, &call.arguments);
+        if let (Provider::Builtin, Some(builtin)) = (provider, &self.builtin) {
+            if BuiltinTools::modifies_files(&call.tool) {
+                call.always = false;
+                if permission == Permission::Allow {
+                    permission = Permission::Ask;
+                }
+            }
+            // Check an edit applies, or a command is allowed, before asking anyone to approve it
+            match builtin.preview(&call.tool, &call.arguments) {
+                Ok(preview) => call.preview = preview,
+                Err(e) => {
+                    let result = Err(e);
+                    self.audit_call(call, Decision::Invalid, Instant::now(), &result);
+                    return result;
+                }
+            }
+        }
+
+        let decision = match permission {
+            Permission::Allow => Decision::Allowed,
+            Permission::Deny => Decision::Denied,
+            Permission::Ask => match approver.approve(&call)? {
+                Approval::Always if call.always => {
+                    self.policy.allow_always(&call.server, &call.tool)?;
+                    Decision::ApprovedAlways
+                }
+                Approval::Once | Approval::Always => Decision::Approved,
+                Approval::Deny => Decision::Rejected,
+            },
+        };
+
+        let started = Instant::now();
+        let result = if matches!(decision, Decision::Denied | Decision::Rejected) {
+            tracing::info!("Denied tool call {}.{}", call.server, call.tool);
+            Ok((String::from("Tool call denied by permission policy"), true))
+        } else {
+            tracing::info!("Calling tool {}.{}", call.server, call.tool);
+            match (provider, &mut self.builtin) {
+                (Provider::Mcp(client), _) => self.clients[client]
+                    .call_tool(&call.tool, call.arguments.clone()).await
+                    .map(|output| (output.text(), output.is_error)),
+                (Provider::Builtin, Some(builtin)) => builtin.run(&call.tool, &call.arguments).await
+                    .map(|text| (text, false)),
+                (Provider::Builtin, None) => Err(anyhow::anyhow!("Built-in tools are not enabled")),
+            }
+        };
+
+        self.audit_call(call, decision, started, &result);
+        result
+    }
+
+    /// Record the call in the audit log. The call has already happened by
+    /// then, so a record that cannot be written is reported rather than
+    /// passed to the model as the call failing.
+    fn audit_call(&self, call: ToolCall, decision: Decision, started: Instant, result: &Result<(String, bool)>) {
+        if let Some(audit) = &self.host.audit {
+            let (result_bytes, is_error) = match result {
+                Ok((text, is_error)) => (text.len(), *is_error),
+                Err(e) => (format!("{:#}", e).len(), true),
+            };
+            let (server, tool) = (call.server.clone(), call.tool.clone());
+            let recorded = audit.record(AuditEvent::ToolCall {
+                server: call.server,
+                tool: call.tool,
+                arguments: call.arguments,
+                decision,
+                duration_ms: started.elapsed().as_millis() as u64,
+                result_bytes,
+                is_error,
+            });
+            if let Err(e) = recorded {
+                tracing::error!("Tool call {}.{} not audited: {:#}", server, tool, e);
+                eprintln!("Warning: tool call {}.{} not recorded in the audit log: {:#}", server, tool, e);
+            }
+        }
+    }
+
+    /// Send `messages` and keep answering tool calls until the model replies
+    /// with plain text. The full exchange is appended to `messages`.
+    pub async fn converse(
+        &mut self,
+        client: &ClaudeClient,
+        model: &str,
+        messages: &mut Vec<Message>,
+        approver: &mut dyn Approver,
+    ) -> Result<String> {
+        let definitions = self.definitions();
+
+        for _ in 0..MAX_TOOL_ROUNDS {
+            let response = client.send(model, messages, &definitions).await?;
+            let content: Vec<ContentBlock> = response.content.iter()
+                .filter(|b| !matches!(b, ContentBlock::Unsupported))
+                .cloned()
+                .collect();
+            messages.push(Message {
+                role: "assistant".to_string(),
+                content: content.clone(),
+            });
+
+            let mut results = Vec::new();
+            for block in content {
+                if let ContentBlock::ToolUse { id, name, input } = block {
+                    results.push(self.execute(&id, &name, input, approver).await);
+                }
+            }
+
+            if results.is_empty() {
+                return Ok(response.text());
+            }
+            messages.push(Message {
+                role: "user".to_string(),
+                content: results,
+            });
+        }
+
+        Err(anyhow::anyhow!("Gave up after {} rounds of tool calls", MAX_TOOL_ROUNDS))
+    }
+}
+
+/// Build an API-safe tool name that stays unique across servers.
+fn qualified_name(server: &str, tool: &str) -> String {
+    format!("{}__{}", server, tool)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' })
+        .take(64)
+        .collect()
+}
+
+/// `qualified_name`, or when that is taken, as cleaning up and cutting
+/// names can make two alike, the same shortened with a hash of both names.
+fn unique_name(server: &str, tool: &str, taken: impl Fn(&str) -> bool) -> String {
+    let name = qualified_name(server, tool);
+    if !taken(&name) {
+        return name;
+    }
+    let hash = format!("{:x}", Sha256::digest(format!("{}\0{}", server, tool)));
+    format!("{}_{}", &name[..name.len().min(55)], &hash[..8])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_qualified_name() {
+        assert_eq!(qualified_name("nmap", "scan"), "nmap__scan");
+        assert_eq!(qualified_name("my server", "port.scan"), "my_server__port_scan");
+        assert_eq!(qualified_name(&"x".repeat(40), &"y".repeat(40)).len(), 64);
+    }
+
+    #[test]
+    fn test_unique_name_when_cut_names_clash() {
+        let server = "x".repeat(40);
+        let (first, second) = (format!("{}_a", "y".repeat(30)), format!("{}_b", "y".repeat(30)));
+        let mut names: Vec<String> = Vec::new();
+        for tool in [&first, &second] {
+            let name = unique_name(&server, tool, |name| names.iter().any(|n| n == name));
+            names.push(name);
+        }
+        assert_eq!(names[0], qualified_name(&server, &first));
+        assert_ne!(names[1], names[0]);
+        assert_eq!(names[1].len(), 64);
+        assert_eq!(unique_name("nmap", "scan", |_| false), "nmap__scan");
+    }
+
+    #[tokio::test]
+    async fn test_unknown_tool_is_error_result() {
+        let mut registry = ToolRegistry::new(PermissionPolicy::default(), ClientHost::default());
+        let result = registry.execute("tu_1", "missing", Value::Null, &mut NonInteractive).await;
+
+        match result {
+            ContentBlock::ToolResult { tool_use_id, is_error, .. } => {
+                assert_eq!(tool_use_id, "tu_1");
+                assert!(is_error);
+            }
+            other => panic!("unexpected block: {:?}", other),
+        }
+    }
+
+    /// Answers "always" and notes whether it was offered.
+    struct AlwaysApprover(Vec<bool>);
+
+    impl Approver for AlwaysApprover {
+        fn approve(&mut self, call: &ToolCall) -> Result<Approval> {
+            self.0.push(call.always);
+            Ok(Approval::Always)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_always_under_an_ask_rule_adds_no_rule() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut policy = PermissionPolicy::default();
+        policy.rules.push(serde_json::from_value(serde_json::json!(
+            { "server": "builtin", "tool": "list_directory", "permission": "ask" }
+        ))?);
+        let mut registry = ToolRegistry::new(policy, ClientHost::default());
+        registry.add_builtin(BuiltinTools::new(dir.path())?);
+
+        let mut approver = AlwaysApprover(Vec::new());
+        let arguments = serde_json::json!({ "path": "." });
+        for _ in 0..2 {
+            registry.execute("tu_1", "list_directory", arguments.clone(), &mut approver).await;
+        }
+        assert_eq!(approver.0, [false, false]);
+        assert_eq!(registry.policy.rules.len(), 1);
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_call_result_survives_an_unwritable_audit_log() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("found.txt"), "")?;
+        // Every write to /dev/full fails with "no space left"
+        let logs = dir.path().join("logs");
+        std::fs::create_dir(&logs)?;
+        std::os::unix::fs::symlink("/dev/full", logs.join(crate::audit::AUDIT_FILE))?;
+        let audit = std::rc::Rc::new(crate::audit::AuditLog::open(&logs)?);
+        assert!(audit.record(AuditEvent::McpExchange {
+            server: "test".to_string(),
+            method: "ping".to_string(),
+            params: Value::Null,
+            response: Value::Null,
+            duration_ms: 0,
+            from_server: false,
+        }).is_err());
+
+        let mut policy = PermissionPolicy::default();
+        policy.rules.push(serde_json::from_value(serde_json::json!(
+            { "server": "builtin", "tool": "list_directory", "permission": "allow" }
+        ))?);
+        let host = ClientHost { audit: Some(audit), ..ClientHost::default() };
+        let mut registry = ToolRegistry::new(policy, host);
+        registry.add_builtin(BuiltinTools::new(dir.path())?);
+        let arguments = serde_json::json!({ "path": "." });
+        match registry.execute("tu_1", "list_directory", arguments, &mut NonInteractive).await {
+            ContentBlock::ToolResult { content, is_error, .. } => {
+                assert!(!is_error, "{}", content);
+                assert!(content.contains("found.txt"));
+            }
+            other => panic!("unexpected block: {:?}", other),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,415 @@
+use anyhow::{anyhow, bail, Context, Result};
+use ignore::WalkBuilder;
+use regex::Regex;
+use serde_json::{json, Value};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use super::patch::Patch;
+use super::shell::{Shell, ShellRun};
+use crate::mcp::RemoteTool;
+
+/// Server name the permission policy and audit log use for built-in tools.
+pub const BUILTIN_SERVER: &str = "builtin";
+
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+const MAX_READ_LINES: usize = 2000;
+const MAX_LIST_ENTRIES: usize = 1000;
+const MAX_SEARCH_MATCHES: usize = 200;
+const MAX_MATCH_CHARS: usize = 200;
+
+/// File and shell tools that need no MCP server, confined to one directory tree.
+pub struct BuiltinTools {
+    root: PathBuf,
+    /// Offers the `bash` tool when set
+    shell: Option<Shell>,
+    /// Commands run since the last `take_shell_runs`
+    runs: Vec<ShellRun>,
+}
+
+impl BuiltinTools {
+    pub fn new(root: &Path) -> Result<Self> {
+        let root = root.canonicalize()
+            .with_context(|| format!("Invalid tool root {}", root.display()))?;
+        Ok(Self { root, shell: None, runs: Vec::new() })
+    }
+
+    pub fn with_shell(mut self, shell: Shell) -> Self {
+        self.shell = Some(shell);
+        self
+    }
+
+    /// Commands run since the last call, oldest first.
+    pub fn take_shell_runs(&mut self) -> Vec<ShellRun> {
+        std::mem::take(&mut self.runs)
+    }
+
+    pub fn definitions(&self) -> Vec<RemoteTool> {
+        let tool = |name: &str, description: String, schema: Value| RemoteTool {
+            name: name.to_string(),
+            description: Some(description),
+            input_schema: schema,
+        };
+        let root = self.root.display();
+
+        let mut tools = vec![
+            tool("read_file", format!("Read lines of a text file under {}, with line numbers", root), json!({
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string" },
+                    "start_line": { "type": "integer", "minimum": 1 },
+                    "end_line": { "type": "integer", "minimum": 1 },
+                },
+                "required": ["path"],
+            })),
+            tool("list_directory", format!("List files under {}, honouring .gitignore", root), json!({
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string", "default": "." },
+                    "depth": { "type": "integer", "minimum": 1, "default": 1 },
+                },
+            })),
+            tool("search_files", format!("Search files under {} for a regular expression", root), json!({
+                "type": "object",
+                "properties": {
+                    "pattern": { "type": "string" },
+                    "path": { "type": "string", "default": "." },
+                    "glob": { "type": "string", "description": "Only search files matching this glob, e.g. *.rs" },
+                },
+                "required": ["pattern"],
+            })),
+            tool("edit_file", String::from(
+                "Change or create a file by giving a unified diff against its current contents. \
+                 The user sees the diff and must approve it before it is written."
+            ), json!({
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string" },
+                    "diff": { "type": "string" },
+                },
+                "required": ["path", "diff"],
+            })),
+        ];
+        if self.shell.is_some() {
+            tools.push(tool("bash", format!(
+                "Run a bash command in {} or a directory under it. Output is truncated and \
+                 the command is stopped when it times out. There is no stdin.",
+                root
+            ), json!({
+                "type": "object",
+                "properties": {
+                    "command": { "type": "string" },
+                    "cwd": { "type": "string", "default": "." },
+                    "timeout_secs": { "type": "integer", "minimum": 1 },
+                },
+                "required": ["command"],
+            })));
+        }
+        tools
+    }
+
+    /// Whether `tool` writes files, so must be approved even when the policy allows it.
+    pub fn modifies_files(tool: &str) -> bool {
+        tool == "edit_file"
+    }
+
+    /// What to show the user when asking about a call, checking first that it
+    /// can succeed. Edits show their diff and commands the command line.
+    pub fn preview(&self, tool: &str, args: &Value) -> Result<Option<String>> {
+        match tool {
+            "edit_file" => {
+                let (path, _) = self.patched(args)?;
+                Ok(Some(format!("{}\n{}", self.display(&path), string_arg(args, "diff")?)))
+            }
+            "bash" => {
+                let (shell, command, cwd) = self.command(args)?;
+                shell.check(command)?;
+                Ok(Some(format!("$ {}  (in {})", command, self.display(&cwd))))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    pub async fn run(&mut self, tool: &str, args: &Value) -> Result<String> {
+        match tool {
+            "read_file" => self.read_file(args),
+            "list_directory" => self.list_directory(args),
+            "search_files" => self.search_files(args),
+            "edit_file" => self.edit_file(args),
+            "bash" => self.bash(args).await,
+            _ => bail!("Unknown built-in tool: {}", tool),
+        }
+    }
+
+    fn read_file(&self, args: &Value) -> Result<String> {
+        let path = self.resolve(string_arg(args, "path")?)?;
+        let content = read_text(&path)?;
+        let lines: Vec<&str> = content.lines().collect();
+
+        let line_arg = |name: &str| args[name].as_u64().map(|n| usize::try_from(n).unwrap_or(usize::MAX));
+        let start = line_arg("start_line").unwrap_or(1).max(1);
+        let requested_end = line_arg("end_line");
+        if let Some(end) = requested_end.filter(|&end| end < start) {
+            bail!("end_line {} is before start_line {}", end, start);
+        }
+        if start > lines.len().max(1) {
+            bail!("{} has only {} lines", self.display(&path), lines.len());
+        }
+        let end = requested_end.unwrap_or(lines.len())
+            .min(lines.len())
+            .min(start.saturating_add(MAX_READ_LINES - 1));
+
+        let mut out = format!("{} (lines {}-{} of {})\n", self.display(&path), start, end, lines.len());
+        for (i, line) in lines.iter().enumerate().take(end).skip(start - 1) {
+            writeln!(out, "{:>6}\t{}", i + 1, line)?;
+        }
+        Ok(out)
+    }
+
+    fn list_directory(&self, args: &Value) -> Result<String> {
+        let dir = self.resolve(args["path"].as_str().unwrap_or("."))?;
+        if !dir.is_dir() {
+            bail!("{} is not a directory", self.display(&dir));
+        }
+        let depth = args["depth"].as_u64().unwrap_or(1).max(1) as usize;
+
+        let mut entries = Vec::new();
+        for entry in self.walk(&dir).max_depth(Some(depth)).build().skip(1) {
+            let entry = entry?;
+            if entries.len() == MAX_LIST_ENTRIES {
+                entries.push(format!("... stopped after {} entries", MAX_LIST_ENTRIES));
+                break;
+            }
+            let path = self.display(entry.path());
+            match entry.metadata() {
+                Ok(meta) if meta.is_dir() => entries.push(format!("{}/", path)),
+                Ok(meta) => entries.push(format!("{}  ({} bytes)", path, meta.len())),
+                Err(_) => entries.push(path),
+            }
+        }
+
+        if entries.is_empty() {
+            return Ok(format!("{} is empty", self.display(&dir)));
+        }
+        Ok(entries.join("\n"))
+    }
+
+    fn search_files(&self, args: &Value) -> Result<String> {
+        let pattern = string_arg(args, "pattern")?;
+        let regex = Regex::new(pattern).with_context(|| format!("Invalid pattern {:?}", pattern))?;
+        let dir = self.resolve(args["path"].as_str().unwrap_or("."))?;
+
+        let mut walk = self.walk(&dir);
+        if let Some(glob) = args["glob"].as_str() {
+            let overrides = ignore::overrides::OverrideBuilder::new(&dir).add(glob)?.build()?;
+            walk.overrides(overrides);
+        }
+
+        let mut matches = Vec::new();
+        'files: for entry in walk.build() {
+            let entry = entry?;
+            if !entry.file_type().is_some_and(|t| t.is_file()) {
+                continue;
+            }
+            // Unreadable, oversized and binary files are skipped, not errors
+            let Ok(content) = read_text(entry.path()) else {
+                continue;
+            };
+            for (i, line) in content.lines().enumerate() {
+                if !regex.is_match(line) {
+                    continue;
+                }
+                if matches.len() == MAX_SEARCH_MATCHES {
+                    matches.push(format!("... stopped after {} matches", MAX_SEARCH_MATCHES));
+                    break 'files;
+                }
+                let text: String = line.trim().chars().take(MAX_MATCH_CHARS).collect();
+                matches.push(format!("{}:{}: {}", self.display(entry.path()), i + 1, text));
+            }
+        }
+
+        if matches.is_empty() {
+            return Ok(format!("No matches for {:?}", pattern));
+        }
+        Ok(matches.join("\n"))
+    }
+
+    fn edit_file(&self, args: &Value) -> Result<String> {
+        let (path, patched) = self.patched(args)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, patched)
+            .with_context(|| format!("Could not write {}", self.display(&path)))?;
+        Ok(format!("Updated {}", self.display(&path)))
+    }
+
+    async fn bash(&mut self, args: &Value) -> Result<String> {
+        let (shell, command, cwd) = self.command(args)?;
+        let run = shell.run(command, &cwd, self.display(&cwd), args["timeout_secs"].as_u64()).await?;
+        let text = format!("{}\n{}", run.status, run.output);
+        self.runs.push(run);
+        Ok(text)
+    }
+
+    /// The shell, command line and working directory of a `bash` call.
+    fn command<'a>(&self, args: &'a Value) -> Result<(&Shell, &'a str, PathBuf)> {
+        let shell = self.shell.as_ref().context("The bash tool is not enabled")?;
+        let command = string_arg(args, "command")?;
+        let cwd = self.resolve(args["cwd"].as_str().unwrap_or("."))?;
+        if !cwd.is_dir() {
+            bail!("{} is not a directory", self.display(&cwd));
+        }
+        Ok((shell, command, cwd))
+    }
+
+    /// The target of an edit and its contents with the diff applied.
+    fn patched(&self, args: &Value) -> Result<(PathBuf, String)> {
+        let path = self.resolve(string_arg(args, "path")?)?;
+        let patch = Patch::parse(string_arg(args, "diff")?)?;
+
+        let original = if path.exists() {
+            read_text(&path)?
+        } else if patch.creates_file {
+            String::new()
+        } else {
+            bail!("{} does not exist; use --- /dev/null to create it", self.display(&path));
+        };
+        let patched = patch.apply(&original)
+            .with_context(|| format!("Could not apply the diff to {}", self.display(&path)))?;
+        Ok((path, patched))
+    }
+
+    fn walk(&self, dir: &Path) -> WalkBuilder {
+        let mut walk = WalkBuilder::new(dir);
+        walk.hidden(false)
+            .require_git(false)
+            .filter_entry(|e| e.file_name() != ".git")
+            .sort_by_file_name(|a, b| a.cmp(b));
+        walk
+    }
+
+    /// Resolve `path` against the root, following symlinks, and refuse
+    /// anything that ends up outside it. Paths need not exist yet.
+    fn resolve(&self, path: &str) -> Result<PathBuf> {
+        let candidate = self.root.join(path);
+
+        let mut existing = candidate.as_path();
+        let mut missing = Vec::new();
+        while !existing.exists() {
+            match (existing.parent(), existing.file_name()) {
+                (Some(parent), Some(name)) => {
+                    missing.push(name.to_owned());
+                    existing = parent;
+                }
+                _ => bail!("Invalid path '{}'", path),
+            }
+        }
+
+        let mut resolved = existing.canonicalize()?;
+        resolved.extend(missing.iter().rev());
+        if !resolved.starts_with(&self.root) {
+            return Err(anyhow!("'{}' is outside the allowed directory {}", path, self.root.display()));
+        }
+        Ok(resolved)
+    }
+
+    /// `path` relative to the root, for output the model can feed back in.
+    fn display(&self, path: &Path) -> String {
+        match path.strip_prefix(&self.root) {
+            Ok(relative) if relative.as_os_str().is_empty() => String::from("."),
+            Ok(relative) => relative.display().to_string(),
+            Err(_) => path.display().to_string(),
+        }
+    }
+}
+
+fn string_arg<'a>(args: &'a Value, name: &str) -> Result<&'a str> {
+    args[name].as_str()
+        .with_context(|| format!("Missing string argument '{}'", name))
+}
+
+/// Read a file the model can usefully see: not too large, and text.
+fn read_text(path: &Path) -> Result<String> {
+    let size = std::fs::metadata(path)
+        .with_context(|| format!("Cannot read {}", path.display()))?
+        .len();
+    if size > MAX_FILE_BYTES {
+        bail!("{} is {} bytes; the limit is {}", path.display(), size, MAX_FILE_BYTES);
+    }
+    let bytes = std::fs::read(path)?;
+    if bytes.iter().take(8192).any(|&b| b == 0) {
+        bail!("{} looks like a binary file", path.display());
+    }
+    String::from_utf8(bytes).map_err(|_| anyhow!("{} is not UTF-8 text", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tools() -> Result<(tempfile::TempDir, BuiltinTools)> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("src"))?;
+        std::fs::write(dir.path().join("src/lib.rs"), "pub fn scan() {}\npub fn report() {}\n")?;
+        std::fs::write(dir.path().join(".gitignore"), "target/\n")?;
+        std::fs::create_dir(dir.path().join("target"))?;
+        std::fs::write(dir.path().join("target/out.rs"), "pub fn scan() {}\n")?;
+        let tools = BuiltinTools::new(dir.path())?;
+        Ok((dir, tools))
+    }
+
+    #[tokio::test]
+    async fn test_read_list_search() -> Result<()> {
+        let (_dir, mut tools) = tools()?;
+
+        let read = tools.run("read_file", &json!({ "path": "src/lib.rs", "start_line": 2 })).await?;
+        assert_eq!(read, "src/lib.rs (lines 2-2 of 2)\n     2\tpub fn report() {}\n");
+        let backwards = tools.run("read_file", &json!({ "path": "src/lib.rs", "start_line": 2, "end_line": 1 })).await;
+        assert_eq!(backwards.unwrap_err().to_string(), "end_line 1 is before start_line 2");
+        let far = tools.run("read_file", &json!({ "path": "src/lib.rs", "start_line": u64::MAX })).await;
+        assert_eq!(far.unwrap_err().to_string(), "src/lib.rs has only 2 lines");
+
+        let listing = tools.run("list_directory", &json!({ "depth": 2 })).await?;
+        assert!(listing.contains("src/lib.rs  (36 bytes)"));
+        assert!(!listing.contains("target"));
+
+        let found = tools.run("search_files", &json!({ "pattern": "fn sc.n", "glob": "*.rs" })).await?;
+        assert_eq!(found, "src/lib.rs:1: pub fn scan() {}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_paths_stay_inside_root() -> Result<()> {
+        let (dir, tools) = tools()?;
+        let mut tools = tools.with_shell(Shell::new(Default::default()));
+        std::os::unix::fs::symlink("/etc", dir.path().join("etc"))?;
+
+        for path in ["../secret", "/etc/passwd", "etc/passwd", "src/../../x"] {
+            let error = tools.run("read_file", &json!({ "path": path })).await.unwrap_err();
+            assert!(format!("{:#}", error).contains("outside the allowed directory")
+                || format!("{:#}", error).contains("Invalid path"), "{}: {:#}", path, error);
+        }
+        assert!(tools.run("bash", &json!({ "command": "ls", "cwd": "etc" })).await.is_err());
+        assert_eq!(tools.run("bash", &json!({ "command": "ls", "cwd": "src" })).await?, "exit status 0\nlib.rs\n");
+        assert_eq!(tools.take_shell_runs()[0].cwd, "src");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_edit_file() -> Result<()> {
+        let (dir, mut tools) = tools()?;
+        let args = json!({
+            "path": "src/lib.rs",
+            "diff": "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,2 +1,2 @@\n pub fn scan() {}\n-pub fn report() {}\n+pub fn report() -> String { String::new() }\n",
+        });
+
+        assert!(tools.preview("edit_file", &args)?.unwrap().starts_with("src/lib.rs\n--- a/src/lib.rs"));
+        tools.run("edit_file", &args).await?;
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("src/lib.rs"))?,
+            "pub fn scan() {}\npub fn report() -> String { String::new() }\n"
+        );
+        Ok(())
+    }
+}
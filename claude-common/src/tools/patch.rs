@@ -0,0 +1,185 @@
+use anyhow::{anyhow, bail, Result};
+use regex::Regex;
+
+/// A unified diff for a single file.
+#[derive(Debug)]
+pub struct Patch {
+    hunks: Vec<Hunk>,
+    /// The `---` side is `/dev/null`, i.e. the diff creates the file
+    pub creates_file: bool,
+}
+
+#[derive(Debug)]
+struct Hunk {
+    /// 1-based first line in the original, as given in the `@@` header
+    old_start: usize,
+    lines: Vec<HunkLine>,
+    /// `\ No newline at end of file` followed the last added line
+    no_final_newline: bool,
+}
+
+#[derive(Debug)]
+enum HunkLine {
+    Context(String),
+    Remove(String),
+    Add(String),
+}
+
+impl Hunk {
+    fn old_lines(&self) -> Vec<&str> {
+        self.lines.iter()
+            .filter_map(|l| match l {
+                HunkLine::Context(s) | HunkLine::Remove(s) => Some(s.as_str()),
+                HunkLine::Add(_) => None,
+            })
+            .collect()
+    }
+
+    fn new_lines(&self) -> Vec<String> {
+        self.lines.iter()
+            .filter_map(|l| match l {
+                HunkLine::Context(s) | HunkLine::Add(s) => Some(s.clone()),
+                HunkLine::Remove(_) => None,
+            })
+            .collect()
+    }
+}
+
+impl Patch {
+    /// Parse a unified diff. Hunk line counts are not trusted, since diffs
+    /// written by hand or by a model often get them wrong.
+    pub fn parse(diff: &str) -> Result<Self> {
+        let header = Regex::new(r"^@@ -(\d+)(?:,\d+)? \+\d+(?:,\d+)? @@")?;
+        let lines: Vec<&str> = diff.lines().collect();
+
+        let mut patch = Patch { hunks: Vec::new(), creates_file: false };
+        let mut i = 0;
+        while i < lines.len() {
+            let line = lines[i];
+            let is_file_header = line.starts_with("--- ")
+                && lines.get(i + 1).is_some_and(|next| next.starts_with("+++ "));
+
+            if is_file_header {
+                if !patch.hunks.is_empty() {
+                    bail!("The diff changes more than one file; send one diff per file");
+                }
+                patch.creates_file = line[4..].trim() == "/dev/null";
+                i += 2;
+                continue;
+            }
+            if let Some(captures) = header.captures(line) {
+                patch.hunks.push(Hunk {
+                    old_start: captures[1].parse()?,
+                    lines: Vec::new(),
+                    no_final_newline: false,
+                });
+                i += 1;
+                continue;
+            }
+
+            let Some(hunk) = patch.hunks.last_mut() else {
+                // `diff --git`, `index` and similar preamble lines
+                i += 1;
+                continue;
+            };
+            match line.chars().next() {
+                Some(' ') => hunk.lines.push(HunkLine::Context(line[1..].to_string())),
+                Some('-') => hunk.lines.push(HunkLine::Remove(line[1..].to_string())),
+                Some('+') => hunk.lines.push(HunkLine::Add(line[1..].to_string())),
+                Some('\\') => {
+                    hunk.no_final_newline = matches!(hunk.lines.last(), Some(HunkLine::Add(_)));
+                }
+                // Editors often strip the single space from blank context lines
+                None => hunk.lines.push(HunkLine::Context(String::new())),
+                Some(_) => bail!("Line {} of the diff is not part of a hunk: {:?}", i + 1, line),
+            }
+            i += 1;
+        }
+
+        if patch.hunks.is_empty() {
+            bail!("The diff has no hunks");
+        }
+        Ok(patch)
+    }
+
+    /// Apply to `original`. Each hunk is placed where its context matches,
+    /// searching outward from the line its header names.
+    pub fn apply(&self, original: &str) -> Result<String> {
+        let mut lines: Vec<String> = original.lines().map(String::from).collect();
+        let mut final_newline = original.is_empty() || original.ends_with('\n');
+        // How far the file has shifted relative to the header line numbers
+        let mut shift: isize = 0;
+        // Hunks apply in order and may not overlap
+        let mut earliest = 0;
+
+        for (n, hunk) in self.hunks.iter().enumerate() {
+            let old = hunk.old_lines();
+            let new = hunk.new_lines();
+            // A pure insertion's header names the line it goes after
+            let anchor = if old.is_empty() { hunk.old_start } else { hunk.old_start.saturating_sub(1) };
+            let expected = (anchor as isize + shift).max(earliest as isize) as usize;
+
+            let position = find(&lines, &old, expected, earliest).ok_or_else(|| anyhow!(
+                "Hunk {} does not apply: its context was not found near line {}",
+                n + 1,
+                hunk.old_start
+            ))?;
+
+            lines.splice(position..position + old.len(), new.iter().cloned());
+            shift = position as isize - anchor as isize + new.len() as isize - old.len() as isize;
+            earliest = position + new.len();
+            if hunk.no_final_newline && earliest == lines.len() {
+                final_newline = false;
+            }
+        }
+
+        let mut result = lines.join("\n");
+        if final_newline && !lines.is_empty() {
+            result.push('\n');
+        }
+        Ok(result)
+    }
+}
+
+/// Nearest position at or after `earliest` where `needle` occurs, preferring
+/// positions close to `expected`.
+fn find(lines: &[String], needle: &[&str], expected: usize, earliest: usize) -> Option<usize> {
+    if needle.len() > lines.len() {
+        return None;
+    }
+    let last = lines.len() - needle.len();
+    let matches_at = |p: usize| lines[p..p + needle.len()].iter().zip(needle).all(|(a, b)| a == b);
+
+    let expected = expected.clamp(earliest, last.max(earliest));
+    (0..=last.max(expected))
+        .flat_map(|distance| [expected.checked_add(distance), expected.checked_sub(distance)])
+        .flatten()
+        .filter(|&p| p >= earliest && p <= last)
+        .find(|&p| matches_at(p))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGINAL: &str = "fn main() {\n    let a = 1;\n    let b = 2;\n    println!(\"{}\", a + b);\n}\n";
+
+    #[test]
+    fn test_apply_with_offset() -> Result<()> {
+        // Header line numbers are off by two; the context still locates the hunk
+        let patch = Patch::parse("--- a/src/main.rs\n+++ b/src/main.rs\n@@ -4,3 +4,3 @@\n     let a = 1;\n-    let b = 2;\n+    let b = 3;\n     println!(\"{}\", a + b);\n")?;
+        assert_eq!(patch.apply(ORIGINAL)?, ORIGINAL.replace("b = 2", "b = 3"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_new_file_and_missing_context() -> Result<()> {
+        let create = Patch::parse("--- /dev/null\n+++ b/notes.txt\n@@ -0,0 +1,2 @@\n+first\n+second\n\\ No newline at end of file\n")?;
+        assert!(create.creates_file);
+        assert_eq!(create.apply("")?, "first\nsecond");
+
+        let stale = Patch::parse("@@ -1,1 +1,1 @@\n-fn start() {\n+fn begin() {\n")?;
+        assert!(stale.apply(ORIGINAL).unwrap_err().to_string().contains("Hunk 1 does not apply"));
+        Ok(())
+    }
+}
@@ -0,0 +1,537 @@
+use anyhow::{bail, Context, Result};
+use std::collections::VecDeque;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::process::{ChildStderr, ChildStdout, Command};
+
+use crate::config::permissions::glob_match;
+use crate::config::ShellConfig;
+
+/// Passed through to commands regardless of configuration.
+const BASE_ENV: &[&str] = &["PATH", "HOME", "USER", "LANG", "LC_ALL", "TERM", "TMPDIR"];
+
+/// Never passed through, even if configured.
+const SECRET_ENV: &[&str] = &["CLAUDE_API_KEY", "ANTHROPIC_API_KEY"];
+
+/// Runs commands for the `bash` tool within the configured limits.
+pub struct Shell {
+    config: ShellConfig,
+}
+
+/// A finished (or abandoned) command, as reported to the model and the session.
+#[derive(Debug, Clone)]
+pub struct ShellRun {
+    pub command: String,
+    pub cwd: String,
+    /// `exit status 0`, `timed out after 120s` and the like
+    pub status: String,
+    /// Combined stdout and stderr, truncated to the configured size
+    pub output: String,
+}
+
+impl ShellRun {
+    pub fn summary(&self) -> String {
+        format!("$ {}  (in {})\n{}\n{}", self.command, self.cwd, self.status, self.output)
+    }
+}
+
+impl Shell {
+    pub fn new(config: ShellConfig) -> Self {
+        Self { config }
+    }
+
+    /// Refuse commands the allow and deny lists rule out. Every command in a
+    /// pipeline or list is checked on its own, so `ls; rm -rf ~` is not let
+    /// through by an `ls *` rule. Deny patterns also see each command without
+    /// its quotes and escapes, and the commands inside substitutions and
+    /// `sh -c` scripts. With an allow list, anything that runs or writes more
+    /// than the listed commands (substitutions, redirections to files) is
+    /// refused.
+    pub fn check(&self, command: &str) -> Result<()> {
+        let line = CommandLine::parse(command);
+        if line.commands.is_empty() {
+            bail!("Empty command");
+        }
+
+        for denied in &self.config.deny {
+            let matches = |c: &SimpleCommand| glob_match(denied, &c.text) || glob_match(denied, &c.words.join(" "));
+            if glob_match(denied, command.trim()) || line.commands.iter().any(matches) {
+                bail!("Command not allowed: matches deny pattern '{}'", denied);
+            }
+        }
+
+        if !self.config.allow.is_empty() {
+            if let Some(construct) = line.unchecked {
+                bail!("Command not allowed: {} cannot be checked against the allow list", construct);
+            }
+            if let Some(other) = line.commands.iter().find(|c| !self.config.allow.iter().any(|a| glob_match(a, &c.text))) {
+                bail!("Command not allowed: '{}' matches no allow pattern", other.text);
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn run(&self, command: &str, cwd: &Path, shown_cwd: String, timeout_secs: Option<u64>) -> Result<ShellRun> {
+        self.check(command)?;
+        let timeout = timeout_secs.unwrap_or(self.config.timeout_secs).min(self.config.timeout_secs);
+
+        let mut process = Command::new("bash");
+        process.arg("-c")
+            .arg(command)
+            .current_dir(cwd)
+            .env_clear()
+            .envs(self.environment())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        // Own process group, so a timeout can stop everything the command started
+        #[cfg(unix)]
+        process.process_group(0);
+
+        let mut child = process.spawn().context("Could not start bash")?;
+        let pid = child.id();
+        let (stdout, stderr) = (child.stdout.take(), child.stderr.take());
+
+        let mut output = Output::new(self.config.max_output_bytes);
+        let finished = async {
+            read_pipes(stdout, stderr, |chunk| output.push(chunk)).await?;
+            child.wait().await
+        };
+        let result = tokio::time::timeout(Duration::from_secs(timeout), finished).await;
+        let status = match result {
+            Ok(status) => match status?.code() {
+                Some(code) => format!("exit status {}", code),
+                None => String::from("killed by a signal"),
+            },
+            Err(_) => {
+                #[cfg(unix)]
+                if let Some(pid) = pid {
+                    // SAFETY: signals only the process group created for this command
+                    unsafe {
+                        libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+                    }
+                }
+                format!("timed out after {}s", timeout)
+            }
+        };
+
+        Ok(ShellRun {
+            command: command.to_string(),
+            cwd: shown_cwd,
+            status,
+            output: output.text(),
+        })
+    }
+
+    fn environment(&self) -> Vec<(String, String)> {
+        BASE_ENV.iter()
+            .copied()
+            .chain(self.config.env.iter().map(String::as_str))
+            .filter(|name| !SECRET_ENV.contains(name))
+            .filter_map(|name| std::env::var(name).ok().map(|value| (name.to_string(), value)))
+            .collect()
+    }
+}
+
+/// A command line cut into the commands of its lists and pipelines, the way
+/// bash reads it: quotes and escapes are respected and `2>&1` is a
+/// redirection, not a background `&`. Commands inside substitutions and
+/// scripts passed to `sh -c` or `eval` are listed too.
+struct CommandLine {
+    commands: Vec<SimpleCommand>,
+    /// The first construct found that runs or writes something the commands
+    /// alone do not show
+    unchecked: Option<&'static str>,
+}
+
+/// One command as written, and as the words it runs with once quotes and
+/// escapes are taken out.
+#[derive(Default)]
+struct SimpleCommand {
+    text: String,
+    words: Vec<String>,
+}
+
+impl SimpleCommand {
+    /// The script a shell is told to run with `-c`, or the words of `eval`.
+    fn script(&self) -> Option<String> {
+        let name = self.words.first()?.rsplit('/').next()?;
+        match name {
+            "eval" => Some(self.words[1..].join(" ")),
+            "sh" | "bash" | "dash" | "zsh" | "ksh" => {
+                let flag = self.words.iter()
+                    .position(|w| w.starts_with('-') && !w.starts_with("--") && w.contains('c'))?;
+                self.words.get(flag + 1).cloned()
+            }
+            _ => None,
+        }
+    }
+
+    fn end_word(&mut self, word: &mut Option<String>) {
+        if let Some(word) = word.take() {
+            self.words.push(word);
+        }
+    }
+}
+
+impl CommandLine {
+    fn parse(line: &str) -> Self {
+        let mut parsed = Self { commands: Vec::new(), unchecked: None };
+        let mut current = SimpleCommand::default();
+        // The word being read, once one has started; `''` starts an empty one
+        let mut word: Option<String> = None;
+        let mut chars = line.chars().peekable();
+        let mut quote = None;
+
+        while let Some(c) = chars.next() {
+            let next = chars.peek().copied();
+            match (quote, c) {
+                (Some('\''), '\'') => quote = None,
+                (Some('\''), _) => word.get_or_insert_with(String::new).push(c),
+                (_, '\\') => {
+                    current.text.push(c);
+                    if let Some(escaped) = chars.next() {
+                        current.text.push(escaped);
+                        let word = word.get_or_insert_with(String::new);
+                        match (quote, escaped) {
+                            (_, '\n') => {}
+                            (None, _) | (_, '$' | '`' | '"' | '\\') => word.push(escaped),
+                            _ => word.extend([c, escaped]),
+                        }
+                    }
+                    continue;
+                }
+                (_, '`') => {
+                    let body = backquoted(&mut chars);
+                    let text = format!("`{}`", body);
+                    current.text.push_str(&text);
+                    word.get_or_insert_with(String::new).push_str(&text);
+                    parsed.substitute("command substitution", &body);
+                    continue;
+                }
+                (_, '$') if next == Some('(') => {
+                    chars.next();
+                    let body = parenthesized(&mut chars);
+                    let text = format!("$({})", body);
+                    current.text.push_str(&text);
+                    word.get_or_insert_with(String::new).push_str(&text);
+                    parsed.substitute("command substitution", &body);
+                    continue;
+                }
+                (Some('"'), '"') => quote = None,
+                (Some(_), _) => word.get_or_insert_with(String::new).push(c),
+                (None, '\'' | '"') => {
+                    quote = Some(c);
+                    word.get_or_insert_with(String::new);
+                }
+                (None, '<' | '>') if next == Some('(') => {
+                    chars.next();
+                    let body = parenthesized(&mut chars);
+                    current.text.push_str(&format!("{}({})", c, body));
+                    current.end_word(&mut word);
+                    parsed.substitute("process substitution", &body);
+                    continue;
+                }
+                (None, '>') => {
+                    current.end_word(&mut word);
+                    current.text.push(c);
+                    parsed.redirect(&mut chars, &mut current.text);
+                    continue;
+                }
+                (None, '<') if next == Some('>') => parsed.refuse("a redirection to a file"),
+                (None, '&') if next == Some('>') => {
+                    current.end_word(&mut word);
+                    current.text.push(c);
+                    current.text.push(chars.next().unwrap_or_default());
+                    parsed.redirect(&mut chars, &mut current.text);
+                    continue;
+                }
+                (None, ';' | '\n' | '&' | '|') => {
+                    if (c == '&' && next == Some('&')) || (c == '|' && matches!(next, Some('|' | '&'))) {
+                        chars.next();
+                    }
+                    current.end_word(&mut word);
+                    parsed.end(&mut current);
+                    continue;
+                }
+                (None, _) if c.is_whitespace() => current.end_word(&mut word),
+                (None, _) => word.get_or_insert_with(String::new).push(c),
+            }
+            current.text.push(c);
+        }
+        current.end_word(&mut word);
+        parsed.end(&mut current);
+        parsed
+    }
+
+    /// Read the target of an output redirection whose `>` was just taken.
+    /// Duplicating a descriptor (`2>&1`) and `/dev/null` write no file.
+    fn redirect(&mut self, chars: &mut std::iter::Peekable<std::str::Chars>, current: &mut String) {
+        while let Some(c) = chars.next_if(|&c| c == '>' || c == '|') {
+            current.push(c);
+        }
+        if let Some(c) = chars.next_if(|&c| c == '&') {
+            current.push(c);
+            return;
+        }
+        while let Some(c) = chars.next_if(|c| c.is_whitespace() && *c != '\n') {
+            current.push(c);
+        }
+        let mut target = String::new();
+        while let Some(c) = chars.next_if(|&c| !c.is_whitespace() && !";&|<>()".contains(c)) {
+            target.push(c);
+        }
+        if target != "/dev/null" {
+            self.refuse("a redirection to a file");
+        }
+        current.push_str(&target);
+    }
+
+    fn refuse(&mut self, construct: &'static str) {
+        self.unchecked.get_or_insert(construct);
+    }
+
+    /// List the commands a substitution runs.
+    fn substitute(&mut self, construct: &'static str, body: &str) {
+        self.refuse(construct);
+        self.include(body);
+    }
+
+    fn include(&mut self, script: &str) {
+        let inner = Self::parse(script);
+        if let Some(construct) = inner.unchecked {
+            self.refuse(construct);
+        }
+        self.commands.extend(inner.commands);
+    }
+
+    fn end(&mut self, current: &mut SimpleCommand) {
+        let mut command = std::mem::take(current);
+        command.text = command.text.trim().to_string();
+        if command.text.is_empty() {
+            return;
+        }
+        let script = command.script();
+        self.commands.push(command);
+        if let Some(script) = script {
+            self.include(&script);
+        }
+    }
+}
+
+/// The body of a `$(` or `<(` just taken, up to its closing parenthesis.
+fn parenthesized(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut body = String::new();
+    let (mut depth, mut quote) = (1, None);
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => {
+                body.push(c);
+                if let Some(escaped) = chars.next() {
+                    body.push(escaped);
+                }
+                continue;
+            }
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            (None, _) => {}
+        }
+        body.push(c);
+    }
+    body
+}
+
+/// The body of a backquoted substitution whose opening quote was just taken.
+fn backquoted(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut body = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '`' => break,
+            '\\' => match chars.next() {
+                Some(escaped @ ('`' | '$' | '\\')) => body.push(escaped),
+                Some(escaped) => body.extend([c, escaped]),
+                None => body.push(c),
+            },
+            _ => body.push(c),
+        }
+    }
+    body
+}
+
+/// Read a child's stdout and stderr to the end while it runs, handing each
+/// chunk to `keep` as it arrives, so nothing beyond what `keep` holds on to
+/// is buffered.
+pub(crate) async fn read_pipes(
+    stdout: Option<ChildStdout>,
+    stderr: Option<ChildStderr>,
+    mut keep: impl FnMut(&[u8]),
+) -> std::io::Result<()> {
+    let (mut stdout, mut stderr) = (stdout, stderr);
+    let (mut out, mut err) = ([0; 8192], [0; 8192]);
+    while stdout.is_some() || stderr.is_some() {
+        // Biased, so output already waiting on both keeps the order it was written in
+        tokio::select! {
+            biased;
+            read = async { stdout.as_mut().unwrap().read(&mut out).await }, if stdout.is_some() => match read? {
+                0 => stdout = None,
+                n => keep(&out[..n]),
+            },
+            read = async { stderr.as_mut().unwrap().read(&mut err).await }, if stderr.is_some() => match read? {
+                0 => stderr = None,
+                n => keep(&err[..n]),
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Combined output, holding on to no more than its start and end, which is
+/// where compiler errors and test summaries usually are.
+struct Output {
+    max: usize,
+    head: Vec<u8>,
+    tail: VecDeque<u8>,
+    omitted: usize,
+}
+
+impl Output {
+    fn new(max: usize) -> Self {
+        Self { max, head: Vec::new(), tail: VecDeque::new(), omitted: 0 }
+    }
+
+    fn push(&mut self, chunk: &[u8]) {
+        let room = (self.max / 2).saturating_sub(self.head.len());
+        let (head, rest) = chunk.split_at(room.min(chunk.len()));
+        self.head.extend_from_slice(head);
+
+        // The tail only fills once the head is full
+        self.tail.extend(rest);
+        let excess = self.tail.len().saturating_sub(self.max - self.max / 2);
+        self.tail.drain(..excess);
+        self.omitted += excess;
+    }
+
+    fn text(mut self) -> String {
+        let head = String::from_utf8_lossy(&self.head);
+        let tail = String::from_utf8_lossy(self.tail.make_contiguous());
+        match self.omitted {
+            0 => format!("{}{}", head, tail),
+            omitted => format!("{}\n[... {} bytes omitted ...]\n{}", head, omitted, tail),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shell(allow: &[&str], deny: &[&str]) -> Shell {
+        Shell::new(ShellConfig {
+            allow: allow.iter().map(|s| s.to_string()).collect(),
+            deny: deny.iter().map(|s| s.to_string()).collect(),
+            env: vec!["CLAUDE_API_KEY".to_string()],
+            timeout_secs: 1,
+            max_output_bytes: 100,
+            ..ShellConfig::default()
+        })
+    }
+
+    #[test]
+    fn test_allow_and_deny_patterns() {
+        let shell = shell(&["ls*", "cargo test*", "grep *"], &["* --force*"]);
+        assert!(shell.check("ls -la | grep src").is_ok());
+        assert!(shell.check("cargo test && ls").is_ok());
+        assert!(shell.check("ls; rm -rf ~").unwrap_err().to_string().contains("'rm -rf ~'"));
+        assert!(shell.check("ls $(cat secret)").is_err());
+        assert!(shell.check("cargo test --force").unwrap_err().to_string().contains("deny pattern"));
+
+        // Without an allow list, the deny list still sees past quoting and into substitutions
+        let shell = self::shell(&[], &["rm *"]);
+        for command in [
+            "\\rm -rf ~",
+            "\"rm\" -rf ~",
+            "r'm' -rf ~",
+            "echo $(rm -rf ~)",
+            "echo \"$(cd /; rm -rf ~)\"",
+            "echo `rm -rf ~`",
+            "cat <(rm -rf ~)",
+            "bash -c 'rm -rf ~'",
+            "/bin/sh -ec \"ls; rm -rf ~\"",
+            "eval 'rm -rf ~'",
+        ] {
+            assert!(shell.check(command).unwrap_err().to_string().contains("deny pattern"), "{}", command);
+        }
+        assert!(shell.check("echo 'rm -rf ~'").is_ok());
+        assert!(shell.check("grep -r \"rm \" src").is_ok());
+    }
+
+    #[test]
+    fn test_allow_list_sees_through_quotes_and_redirections() {
+        let shell = shell(&["ls*", "cargo test*", "grep *"], &[]);
+        let refused = |command: &str| shell.check(command).unwrap_err().to_string();
+
+        assert!(shell.check("cargo test 2>&1").is_ok());
+        assert!(shell.check("cargo test 2>&1 | grep FAILED").is_ok());
+        assert!(shell.check("ls missing 2> /dev/null").is_ok());
+        assert!(shell.check("grep ';' notes | grep 'a|b'").is_ok());
+        assert!(refused("ls $(rm -rf ~)").contains("command substitution"));
+        assert!(refused("ls `rm -rf ~`").contains("command substitution"));
+        assert!(refused("grep \"$(id)\" notes").contains("command substitution"));
+        assert!(refused("ls <(rm -rf ~)").contains("process substitution"));
+        assert!(refused("ls > ~/.bashrc").contains("redirection to a file"));
+        assert!(refused("ls 2>>log").contains("redirection to a file"));
+        assert!(refused("ls &> out").contains("redirection to a file"));
+        assert!(refused("ls & rm -rf ~").contains("'rm -rf ~'"));
+        assert!(refused("cargo test |& rm out").contains("'rm out'"));
+
+        // Without an allow list only the deny list applies
+        assert!(self::shell(&[], &[]).check("ls > out").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_run_scrubs_environment_and_times_out() -> Result<()> {
+        std::env::set_var("CLAUDE_API_KEY", "sk-test-secret");
+        let shell = shell(&[], &[]);
+        let dir = tempfile::tempdir()?;
+
+        let env = shell.run("env; pwd", dir.path(), ".".to_string(), None).await?;
+        assert_eq!(env.status, "exit status 0");
+        assert!(!env.output.contains("sk-test-secret"));
+        assert!(env.output.contains(&dir.path().canonicalize()?.display().to_string()));
+
+        let slow = shell.run("sleep 5", dir.path(), ".".to_string(), Some(30)).await?;
+        assert_eq!(slow.status, "timed out after 1s");
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_keeps_both_ends() {
+        let mut output = Output::new(20);
+        output.push(b"start");
+        for _ in 0..100 {
+            output.push(b"xxxxxxxxxx");
+        }
+        output.push(b"end");
+        assert_eq!(output.head.len() + output.tail.len(), 20);
+        let cut = output.text();
+        assert!(cut.starts_with("start"));
+        assert!(cut.ends_with("end"));
+        assert!(cut.contains("[... 988 bytes omitted ...]"));
+
+        let mut short = Output::new(20);
+        short.push(b"ok\n");
+        assert_eq!(short.text(), "ok\n");
+    }
+}
@@ -1,6 +1,9 @@
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
+use crate::api::{self, ContentBlock, MediaSource};
+use crate::conversation::Conversation;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum OutputFormat {
     Text,
@@ -22,19 +25,197 @@ pub enum LogLevel {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "SessionFile")]
 pub struct Session {
     pub id: String,
     pub model: String,
-    pub messages: Vec<Message>,
+    /// Every branch of the conversation
+    pub conversation: Conversation,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
 }
 
+/// A session as saved: a conversation tree, or in older files the messages
+/// of a single line of conversation.
+#[derive(Deserialize)]
+struct SessionFile {
+    id: String,
+    model: String,
+    #[serde(default)]
+    conversation: Option<Conversation>,
+    #[serde(default)]
+    messages: Vec<Message>,
+    created_at: chrono::DateTime<chrono::Utc>,
+    updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<SessionFile> for Session {
+    fn from(file: SessionFile) -> Self {
+        Self {
+            id: file.id,
+            model: file.model,
+            conversation: file.conversation.unwrap_or_else(|| Conversation::from_messages(file.messages)),
+            created_at: file.created_at,
+            updated_at: file.updated_at,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub role: String,
     pub content: String,
     pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// A reply cut short by the user; `content` is what arrived before that
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub interrupted: bool,
+    /// Files, command output and resources sent along with `content`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+}
+
+/// Something sent along with a message, kept apart from what was typed:
+/// text the model reads inline, or an image or document sent as its own
+/// content block.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attachment {
+    pub kind: AttachmentKind,
+    /// Path, command line or URI
+    pub source: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    /// Text of a file, command output or resource
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub content: String,
+    /// Base64 bytes of an image or document. Saved sessions hold `blob`
+    /// instead, and `None` after loading means the blob has gone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+    /// SHA-256 of the bytes, naming the file that holds them in a saved session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AttachmentKind {
+    File,
+    /// Output of a shell command the user ran
+    Command,
+    /// Contents of an MCP resource
+    Resource,
+    Image,
+    /// A PDF
+    Document,
+}
+
+impl Attachment {
+    /// A text attachment from a file.
+    pub fn file(source: String, content: String) -> Self {
+        Self { kind: AttachmentKind::File, source, mime_type: None, content, data: None, blob: None }
+    }
+
+    /// An image or document holding `bytes` of `mime_type`.
+    pub fn media(kind: AttachmentKind, source: String, mime_type: &str, bytes: &[u8]) -> Self {
+        use base64::Engine;
+        Self {
+            kind,
+            source,
+            mime_type: Some(mime_type.to_string()),
+            content: String::new(),
+            data: Some(base64::engine::general_purpose::STANDARD.encode(bytes)),
+            blob: None,
+        }
+    }
+
+    /// Sent as a content block of its own rather than as text.
+    pub fn is_media(&self) -> bool {
+        matches!(self.kind, AttachmentKind::Image | AttachmentKind::Document)
+    }
+
+    /// Size in bytes of the text, or of the image or document.
+    pub fn size(&self) -> usize {
+        match &self.data {
+            Some(data) => data.len() / 4 * 3 - data.chars().rev().take_while(|&c| c == '=').count(),
+            None => self.content.len(),
+        }
+    }
+
+    /// The content block an image or document is sent as. One whose bytes
+    /// are missing is described in text instead.
+    pub fn block(&self) -> Option<ContentBlock> {
+        if !self.is_media() {
+            return None;
+        }
+        let Some(data) = &self.data else {
+            return Some(ContentBlock::Text { text: format!("[{} is no longer available]", self.source) });
+        };
+        let source = MediaSource::base64(self.mime_type.as_deref().unwrap_or_default(), data);
+        Some(match self.kind {
+            AttachmentKind::Image => ContentBlock::Image { source },
+            _ => ContentBlock::Document { source },
+        })
+    }
+
+    /// The attachment as the model is sent it, tagged with where it came
+    /// from. Images and documents are only named, as their content goes in
+    /// a block of its own.
+    pub fn render(&self) -> String {
+        let content = self.content.trim_end_matches('\n');
+        let source = attribute(&self.source);
+        let mime = |default| attribute(self.mime_type.as_deref().unwrap_or(default));
+        match self.kind {
+            AttachmentKind::File => format!("<file path=\"{}\">\n{}\n</file>", source, content),
+            AttachmentKind::Command => format!("<command line=\"{}\">\n{}\n</command>", source, content),
+            AttachmentKind::Resource => format!(
+                "<resource uri=\"{}\" mime=\"{}\">\n{}\n</resource>",
+                source,
+                mime("text/plain"),
+                content
+            ),
+            AttachmentKind::Image | AttachmentKind::Document => format!(
+                "<attachment path=\"{}\" mime=\"{}\"/>",
+                source,
+                mime("")
+            ),
+        }
+    }
+}
+
+/// `value` escaped to sit between the double quotes of a tag's attribute.
+fn attribute(value: &str) -> String {
+    value.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;")
+}
+
+impl Message {
+    /// Whether the model sees this message. Others, such as the `tool` records
+    /// of shell commands, are kept only for the user.
+    pub fn is_conversation(&self) -> bool {
+        matches!(self.role.as_str(), "user" | "assistant")
+    }
+
+    /// `content` as the model is sent it, with any attachments after it and
+    /// noting where a reply was cut short.
+    pub fn conversation_text(&self) -> String {
+        let mut text = match (self.interrupted, self.content.is_empty()) {
+            (false, _) => self.content.clone(),
+            (true, true) => String::from("[interrupted]"),
+            (true, false) => format!("{}\n[interrupted]", self.content),
+        };
+        for attachment in &self.attachments {
+            text.push_str("\n\n");
+            text.push_str(&attachment.render());
+        }
+        text
+    }
+
+    /// The message for the API: images and documents first, as the model
+    /// reads them best that way, then the text.
+    pub fn api_message(&self) -> api::Message {
+        let mut content: Vec<ContentBlock> = self.attachments.iter().filter_map(Attachment::block).collect();
+        content.push(ContentBlock::Text { text: self.conversation_text() });
+        api::Message { role: self.role.clone(), content }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -65,4 +246,51 @@ impl Default for Config {
             log_dir: config_dir.join("logs"),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_api_message_puts_media_before_text() {
+        let message = Message {
+            role: "user".to_string(),
+            content: "What is on this screen?".to_string(),
+            timestamp: chrono::Utc::now(),
+            interrupted: false,
+            attachments: vec![
+                Attachment::file("notes.md".to_string(), "Login page".to_string()),
+                Attachment::media(AttachmentKind::Image, "screen.png".to_string(), "image/png", b"\x89PNG"),
+                Attachment::media(AttachmentKind::Document, "report.pdf".to_string(), "application/pdf", b"%PDF"),
+            ],
+        };
+
+        let content = message.api_message().content;
+        assert!(matches!(
+            content.as_slice(),
+            [ContentBlock::Image { .. }, ContentBlock::Document { .. }, ContentBlock::Text { .. }]
+        ));
+        let ContentBlock::Text { text } = &content[2] else { unreachable!() };
+        assert!(text.starts_with("What is on this screen?\n\n<file path=\"notes.md\">"));
+        assert!(text.ends_with("<attachment path=\"report.pdf\" mime=\"application/pdf\"/>"));
+    }
+
+    #[test]
+    fn test_render_escapes_attributes() {
+        let grep = Attachment {
+            kind: AttachmentKind::Command,
+            ..Attachment::file("grep \"a<b\" f && ls".to_string(), "f:a<b\n".to_string())
+        };
+        assert_eq!(grep.render(), "<command line=\"grep &quot;a&lt;b&quot; f &amp;&amp; ls\">\nf:a<b\n</command>");
+    }
+
+    #[test]
+    fn test_size_of_padded_base64_is_the_byte_count() {
+        for bytes in [&b""[..], b"a", b"ab", b"abc", b"abcd", b"abcde"] {
+            let attachment = Attachment::media(AttachmentKind::Image, "a.png".to_string(), "image/png", bytes);
+            assert_eq!(attachment.size(), bytes.len(), "{:?}", attachment.data);
+        }
+        assert_eq!(Attachment::file("a.txt".to_string(), "four".to_string()).size(), 4);
+    }
+}
@@ -1,7 +1,9 @@
 use anyhow::Result;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use claude_common::Config;  // This should work now with the re-export
 
+mod mcp;
+
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
@@ -12,6 +14,18 @@ struct Cli {
     /// Reset configuration to defaults
     #[arg(short, long)]
     reset: bool,
+
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Manage MCP servers
+    Mcp {
+        #[command(subcommand)]
+        action: mcp::McpCommand,
+    },
 }
 
 #[tokio::main]
@@ -20,6 +34,10 @@ async fn main() -> Result<()> {
     
     let cli = Cli::parse();
     
+    if let Some(Commands::Mcp { action }) = cli.command {
+        return mcp::run(action).await;
+    }
+
     if cli.show {
         println!("Current configuration:");
         let config = Config::load()?;
@@ -33,4 +51,4 @@ async fn main() -> Result<()> {
     }
     
     Ok(())
-}
\ No newline at end of file
+}
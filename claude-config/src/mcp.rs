@@ -0,0 +1,277 @@
+use anyhow::{anyhow, Result};
+use clap::{ArgGroup, Subcommand};
+use claude_common::config::mcp::{self, McpConfig, McpServer};
+use claude_common::mcp::{ClientHost, McpClient};
+use claude_common::Config;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// How long `test` waits for each step before declaring the server unhealthy.
+const TEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Subcommand)]
+pub enum McpCommand {
+    /// Add a server, or replace one with the same name
+    #[command(group(ArgGroup::new("transport").required(true).args(["url", "command"])))]
+    Add {
+        name: String,
+
+        /// URL of an HTTP server
+        #[arg(long)]
+        url: Option<String>,
+
+        /// Executable to launch for a stdio server
+        #[arg(long)]
+        command: Option<String>,
+
+        /// Environment variable for the launched server, as KEY=VALUE
+        #[arg(short, long, value_parser = parse_env)]
+        env: Vec<(String, String)>,
+
+        /// Add the server without enabling it
+        #[arg(long)]
+        disabled: bool,
+
+        /// Arguments for the launched server, after `--`
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+    /// Remove a server
+    Remove { name: String },
+    /// List configured servers
+    List,
+    /// Enable a server
+    Enable { name: String },
+    /// Disable a server
+    Disable { name: String },
+    /// Import servers from `mcpServers` config files of other clients
+    Import {
+        /// Files to read; defaults to the known desktop and editor configs
+        paths: Vec<PathBuf>,
+
+        /// Import the project's .mcp.json into the user-level config
+        #[arg(long, conflicts_with = "paths")]
+        project: bool,
+
+        /// Replace existing servers whose definition differs
+        #[arg(long)]
+        force: bool,
+    },
+    /// Trust the project's .mcp.json as it is now, so its servers start
+    Trust,
+    /// Connect to servers and report their health
+    Test {
+        /// Server to test; all enabled servers when omitted
+        name: Option<String>,
+    },
+}
+
+fn parse_env(value: &str) -> Result<(String, String), String> {
+    value.split_once('=')
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got '{}'", value))
+}
+
+pub async fn run(command: McpCommand) -> Result<()> {
+    let mut config = McpConfig::load(&Config::load()?.config_dir)?;
+    let untrusted = config.merge_project(&std::env::current_dir()?)?
+        .filter(|p| !p.trusted)
+        .map(|p| p.path.clone());
+    if let Some(path) = &untrusted {
+        if !matches!(command, McpCommand::Trust) {
+            println!("Note: {} was found but is not trusted; its servers are not started", path.display());
+        }
+    }
+    for name in config.project_overrides() {
+        println!("Note: project server '{}' overrides the user-level definition", name);
+    }
+
+    match command {
+        McpCommand::Add { name, url, command, env, disabled, args } => {
+            config.add_server(McpServer {
+                name: name.clone(),
+                url: url.unwrap_or_default(),
+                command,
+                args,
+                env: env.into_iter().collect::<HashMap<_, _>>(),
+                enabled: !disabled,
+                ..McpServer::default()
+            })?;
+            println!("Added MCP server '{}'", name);
+        }
+        McpCommand::Remove { name } => {
+            if config.get_server(&name).is_none() {
+                return Err(anyhow!("No MCP server named '{}'", name));
+            }
+            config.remove_server(&name)?;
+            println!("Removed MCP server '{}'", name);
+        }
+        McpCommand::List => list(&config),
+        McpCommand::Enable { name } => {
+            config.set_enabled(&name, true)?;
+            println!("Enabled MCP server '{}'", name);
+        }
+        McpCommand::Disable { name } => {
+            config.set_enabled(&name, false)?;
+            println!("Disabled MCP server '{}'", name);
+        }
+        McpCommand::Import { paths, project, force } => import(&mut config, paths, project, force)?,
+        McpCommand::Trust => {
+            let Some(path) = untrusted else {
+                match &config.project {
+                    Some(project) => println!("{} is already trusted", project.path.display()),
+                    None => println!("No {} found", mcp::PROJECT_FILE),
+                }
+                return Ok(());
+            };
+            config.trust_project()?;
+            println!("Trusted {}", path.display());
+            for server in config.effective_servers().into_iter().filter(|s| s.project.is_some()) {
+                println!("  {}  {}", server.name, server.transport());
+            }
+        }
+        McpCommand::Test { name } => {
+            let servers: Vec<&McpServer> = match &name {
+                Some(name) => vec![config.effective_servers().into_iter()
+                    .find(|s| &s.name == name)
+                    .ok_or_else(|| anyhow!("No MCP server named '{}'", name))?],
+                None => config.get_enabled_servers(),
+            };
+            if servers.is_empty() {
+                println!("No enabled MCP servers to test");
+                return Ok(());
+            }
+
+            let mut failed = 0;
+            for server in servers {
+                if !test(server).await {
+                    failed += 1;
+                }
+            }
+            if failed > 0 {
+                return Err(anyhow!("{} server(s) failed the health check", failed));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn list(config: &McpConfig) {
+    let servers: Vec<&McpServer> = config.effective_servers().into_iter()
+        .chain(config.untrusted_servers())
+        .collect();
+    if servers.is_empty() {
+        println!("No MCP servers configured");
+        return;
+    }
+
+    let width = servers.iter().map(|s| s.name.len()).max().unwrap_or(0);
+    let trusted = config.project.as_ref().is_some_and(|p| p.trusted);
+    for server in servers {
+        let state = match server.enabled {
+            _ if server.project.is_some() && !trusted => "untrusted",
+            true => "enabled",
+            false => "disabled",
+        };
+        let scope = if server.project.is_some() { "project" } else { "user" };
+        println!(
+            "{:width$}  {:9}  {:7}  {}",
+            server.name,
+            state,
+            scope,
+            server.transport(),
+            width = width
+        );
+    }
+}
+
+fn import(config: &mut McpConfig, paths: Vec<PathBuf>, project: bool, force: bool) -> Result<()> {
+    let sources = if project {
+        let cwd = std::env::current_dir()?;
+        vec![mcp::find_project_file(&cwd)
+            .ok_or_else(|| anyhow!("No {} found in {} or its parents", mcp::PROJECT_FILE, cwd.display()))?]
+    } else if paths.is_empty() {
+        mcp::known_sources().into_iter().filter(|p| p.is_file()).collect()
+    } else {
+        paths
+    };
+    if sources.is_empty() {
+        println!("No client configs found; pass the file to import");
+        return Ok(());
+    }
+
+    let mut conflicts = 0;
+    for source in sources {
+        let report = config.import(mcp::read_servers(&source)?, force)?;
+        println!("{}", source.display());
+        for (label, names) in [
+            ("added", &report.added),
+            ("replaced", &report.replaced),
+            ("unchanged", &report.unchanged),
+            ("conflict", &report.conflicts),
+        ] {
+            for name in names {
+                println!("  {:9}  {}", label, name);
+            }
+        }
+        conflicts += report.conflicts.len();
+    }
+
+    if conflicts > 0 {
+        println!("{} server(s) already exist with a different definition; use --force to replace them", conflicts);
+    }
+    Ok(())
+}
+
+/// Run initialize and tools/list against `server`, printing a report.
+/// Returns whether the server is healthy.
+async fn test(server: &McpServer) -> bool {
+    println!("{} ({})", server.name, server.transport());
+
+    let started = Instant::now();
+    let mut client = match tokio::time::timeout(TEST_TIMEOUT, McpClient::connect(server, &ClientHost::default())).await {
+        Ok(Ok(client)) => client,
+        Ok(Err(e)) => {
+            println!("  initialize  FAILED  {:#}", e);
+            return false;
+        }
+        Err(_) => {
+            println!("  initialize  FAILED  timed out after {}s", TEST_TIMEOUT.as_secs());
+            return false;
+        }
+    };
+    println!(
+        "  initialize  OK      {:>5} ms  {} (protocol {})",
+        started.elapsed().as_millis(),
+        client.server_info(),
+        client.protocol_version()
+    );
+
+    let started = Instant::now();
+    let tools = match tokio::time::timeout(TEST_TIMEOUT, client.list_tools()).await {
+        Ok(Ok(tools)) => tools,
+        Ok(Err(e)) => {
+            println!("  tools/list  FAILED  {:#}", e);
+            return false;
+        }
+        Err(_) => {
+            println!("  tools/list  FAILED  timed out after {}s", TEST_TIMEOUT.as_secs());
+            return false;
+        }
+    };
+    println!(
+        "  tools/list  OK      {:>5} ms  {} tool(s)",
+        started.elapsed().as_millis(),
+        tools.len()
+    );
+    for tool in tools {
+        match tool.description {
+            Some(description) => println!("    - {}: {}", tool.name, description),
+            None => println!("    - {}", tool.name),
+        }
+    }
+
+    true
+}
@@ -0,0 +1,129 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::{Args, ValueEnum};
+use claude_common::Config;
+use claude_common::audit::{self, AuditEvent, AuditRecord, Decision, AUDIT_FILE};
+
+#[derive(Args)]
+pub struct AuditArgs {
+    /// Check the hash chain instead of listing records
+    #[arg(long)]
+    verify: bool,
+
+    /// Only records involving this MCP server
+    #[arg(long)]
+    server: Option<String>,
+
+    /// Only calls to this tool or MCP method
+    #[arg(long)]
+    tool: Option<String>,
+
+    /// Only this kind of record
+    #[arg(long, value_enum)]
+    event: Option<EventKind>,
+
+    /// Only records from this time on (RFC 3339 or YYYY-MM-DD)
+    #[arg(long, value_parser = parse_since)]
+    since: Option<DateTime<Utc>>,
+
+    /// Only tool calls that were denied or failed
+    #[arg(long)]
+    failed: bool,
+
+    /// Print matching records as JSON lines
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum EventKind {
+    Tool,
+    Mcp,
+}
+
+/// List or verify the audit log in `Config::log_dir`.
+pub fn run(config: &Config, args: &AuditArgs) -> Result<()> {
+    let path = config.log_dir.join(AUDIT_FILE);
+    let records = audit::read(&path)?;
+
+    if args.verify {
+        audit::verify(&records)
+            .map_err(|e| anyhow!("Audit log {} has been tampered with: {}", path.display(), e))?;
+        println!("{}: {} records, chain intact", path.display(), records.len());
+        return Ok(());
+    }
+
+    for record in records.iter().filter(|r| args.matches(r)) {
+        if args.json {
+            println!("{}", serde_json::to_string(record)?);
+        } else {
+            println!("{}", summary(record));
+        }
+    }
+    Ok(())
+}
+
+impl AuditArgs {
+    fn matches(&self, record: &AuditRecord) -> bool {
+        let event = &record.event;
+        let kind_matches = matches!(
+            (self.event, event),
+            (None, _)
+                | (Some(EventKind::Tool), AuditEvent::ToolCall { .. })
+                | (Some(EventKind::Mcp), AuditEvent::McpExchange { .. })
+        );
+        let failed = matches!(
+            event,
+            AuditEvent::ToolCall { decision: Decision::Denied | Decision::Rejected, .. }
+                | AuditEvent::ToolCall { is_error: true, .. }
+        );
+
+        kind_matches
+            && self.server.as_ref().is_none_or(|s| s == event.server())
+            && self.tool.as_ref().is_none_or(|t| t == event.action())
+            && self.since.is_none_or(|since| record.timestamp >= since)
+            && (!self.failed || failed)
+    }
+}
+
+fn summary(record: &AuditRecord) -> String {
+    let time = record.timestamp.format("%Y-%m-%d %H:%M:%S");
+    match &record.event {
+        AuditEvent::ToolCall { server, tool, arguments, decision, duration_ms, result_bytes, is_error } => {
+            let decision = serde_json::to_value(decision).ok()
+                .and_then(|d| d.as_str().map(String::from))
+                .unwrap_or_default();
+            format!(
+                "{:>6}  {}  tool  {}.{}  {}  {} ms  {} bytes{}  {}",
+                record.seq, time, server, tool, decision, duration_ms, result_bytes,
+                if *is_error { "  error" } else { "" },
+                arguments
+            )
+        }
+        AuditEvent::McpExchange { server, method, duration_ms, from_server, .. } => {
+            let direction = if *from_server { "<-" } else { "->" };
+            format!("{:>6}  {}  mcp   {} {} {}  {} ms", record.seq, time, server, direction, method, duration_ms)
+        }
+    }
+}
+
+fn parse_since(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|date| date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc())
+        .map_err(|_| format!("'{}' is not a date (YYYY-MM-DD) or RFC 3339 time", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_since() {
+        assert_eq!(parse_since("2024-03-01").unwrap().to_rfc3339(), "2024-03-01T00:00:00+00:00");
+        assert_eq!(parse_since("2024-03-01T12:00:00+02:00").unwrap().to_rfc3339(), "2024-03-01T10:00:00+00:00");
+        assert!(parse_since("last week").is_err());
+    }
+}
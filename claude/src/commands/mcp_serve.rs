@@ -0,0 +1,367 @@
+use anyhow::{anyhow, Context, Result};
+use claude_common::{Config, ClaudeClient};
+use claude_common::api::Message;
+use claude_common::sessions::SessionStore;
+use claude_common::types::Session;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+const PROTOCOL_VERSION: &str = "2024-11-05";
+const SESSION_SCHEME: &str = "session://";
+const DEFAULT_SEARCH_LIMIT: usize = 20;
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+const RESOURCE_NOT_FOUND: i64 = -32002;
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+}
+
+/// Answers MCP requests from another client using this install's
+/// configuration and saved sessions.
+pub struct McpServe {
+    config: Config,
+    sessions: SessionStore,
+}
+
+/// Serve MCP over stdin/stdout until the client closes stdin.
+pub async fn run(config: Config) -> Result<()> {
+    let server = McpServe::new(config);
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut stdout = tokio::io::stdout();
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Value>(&line) {
+            Ok(message) => server.handle(message).await,
+            Err(e) => Some(error_response(Value::Null, RpcError::new(PARSE_ERROR, e.to_string()))),
+        };
+        if let Some(response) = response {
+            let mut out = serde_json::to_string(&response)?;
+            out.push('\n');
+            stdout.write_all(out.as_bytes()).await?;
+            stdout.flush().await?;
+        }
+    }
+    Ok(())
+}
+
+impl McpServe {
+    pub fn new(config: Config) -> Self {
+        let sessions = SessionStore::new(&config.config_dir);
+        Self { config, sessions }
+    }
+
+    /// Handle one incoming message; notifications get no response.
+    pub async fn handle(&self, message: Value) -> Option<Value> {
+        let id = message.get("id").cloned();
+        let Some(method) = message.get("method").and_then(Value::as_str) else {
+            return id.map(|id| error_response(id, RpcError::new(INVALID_REQUEST, "Missing method")));
+        };
+        let id = id?;
+
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+        let result = match method {
+            "initialize" => Ok(self.initialize()),
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(json!({ "tools": self.tool_definitions() })),
+            "tools/call" => self.call_tool(&params).await,
+            "resources/list" => self.list_resources(),
+            "resources/read" => self.read_resource(&params),
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("Unknown method: {}", method))),
+        };
+
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(error) => error_response(id, error),
+        })
+    }
+
+    fn initialize(&self) -> Value {
+        json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": {
+                "tools": {},
+                "resources": {},
+            },
+            "serverInfo": {
+                "name": "claude-cli",
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+        })
+    }
+
+    fn tool_definitions(&self) -> Value {
+        let mut profile = json!({ "type": "string", "description": "Configured profile to use" });
+        if !self.config.profiles.is_empty() {
+            let mut names: Vec<&String> = self.config.profiles.keys().collect();
+            names.sort();
+            profile["enum"] = json!(names);
+        }
+
+        json!([
+            {
+                "name": "ask_claude",
+                "description": "Send a prompt to Claude and return the reply",
+                "inputSchema": {
+                    "type": "object",
+                    "properties": {
+                        "prompt": { "type": "string" },
+                        "model": { "type": "string", "description": "Model to use instead of the default" },
+                        "profile": profile,
+                        "session": { "type": "string", "description": "Saved session to use as prior context" },
+                    },
+                    "required": ["prompt"],
+                },
+            },
+            {
+                "name": "list_sessions",
+                "description": "List saved sessions, most recent first",
+                "inputSchema": { "type": "object", "properties": {} },
+            },
+            {
+                "name": "read_session",
+                "description": "Return the transcript of a saved session",
+                "inputSchema": {
+                    "type": "object",
+                    "properties": { "name": { "type": "string" } },
+                    "required": ["name"],
+                },
+            },
+            {
+                "name": "search_history",
+                "description": "Find messages on any branch of saved sessions containing some text",
+                "inputSchema": {
+                    "type": "object",
+                    "properties": {
+                        "query": { "type": "string" },
+                        "limit": { "type": "integer", "minimum": 1 },
+                    },
+                    "required": ["query"],
+                },
+            },
+        ])
+    }
+
+    async fn call_tool(&self, params: &Value) -> Result<Value, RpcError> {
+        let name = params["name"].as_str()
+            .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Missing tool name"))?;
+        let args = &params["arguments"];
+
+        let output = match name {
+            "ask_claude" => self.ask_claude(args).await,
+            "list_sessions" => self.list_sessions(),
+            "read_session" => string_arg(args, "name")
+                .and_then(|name| Ok(transcript(&self.sessions.read(name)?))),
+            "search_history" => self.search_history(args),
+            _ => return Err(RpcError::new(INVALID_PARAMS, format!("Unknown tool: {}", name))),
+        };
+
+        // Tool failures are results the caller's model can see, not protocol errors
+        Ok(match output {
+            Ok(text) => json!({ "content": [{ "type": "text", "text": text }] }),
+            Err(e) => json!({
+                "content": [{ "type": "text", "text": format!("{:#}", e) }],
+                "isError": true,
+            }),
+        })
+    }
+
+    async fn ask_claude(&self, args: &Value) -> Result<String> {
+        let prompt = string_arg(args, "prompt")?;
+        let profile = match args["profile"].as_str() {
+            Some(name) => Some(self.config.profile(name)?),
+            None => None,
+        };
+        let model = args["model"].as_str()
+            .or_else(|| profile.and_then(|p| p.model.as_deref()))
+            .unwrap_or(&self.config.default_model);
+
+        let mut messages = match args["session"].as_str() {
+            Some(name) => self.sessions.load(name)?
+                .conversation.messages().into_iter()
+                .filter(|m| m.is_conversation())
+                .map(|m| m.api_message())
+                .collect(),
+            None => Vec::new(),
+        };
+        messages.push(Message::user(prompt));
+
+        let client = ClaudeClient::new(self.config.api_key.clone())
+            .with_system(profile.and_then(|p| p.system.clone()));
+        let response = client.send(model, &messages, &[]).await?;
+        Ok(response.text())
+    }
+
+    fn list_sessions(&self) -> Result<String> {
+        let sessions = self.sessions.list()?;
+        if sessions.is_empty() {
+            return Ok(String::from("No saved sessions"));
+        }
+        Ok(sessions.iter()
+            .map(|s| format!(
+                "{}\t{}\t{} messages\tupdated {}",
+                s.id,
+                s.model,
+                s.conversation.len(),
+                s.updated_at.format("%Y-%m-%d %H:%M")
+            ))
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    fn search_history(&self, args: &Value) -> Result<String> {
+        let query = string_arg(args, "query")?;
+        let limit = args["limit"].as_u64().map_or(DEFAULT_SEARCH_LIMIT, |l| l as usize);
+
+        let hits = self.sessions.search(query, limit)?;
+        if hits.is_empty() {
+            return Ok(format!("No messages matching '{}'", query));
+        }
+        Ok(hits.iter()
+            .map(|hit| {
+                let on = match hit.branches.as_slice() {
+                    [main] if main == "main" => String::new(),
+                    branches => format!(" (on {})", branches.join(", ")),
+                };
+                format!("{} #{}{} {}: {}", hit.session, hit.index + 1, on, hit.role, hit.content)
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n"))
+    }
+
+    fn list_resources(&self) -> Result<Value, RpcError> {
+        let sessions = self.sessions.list()
+            .map_err(|e| RpcError::new(INTERNAL_ERROR, format!("{:#}", e)))?;
+        let resources: Vec<Value> = sessions.iter()
+            .map(|s| json!({
+                "uri": format!("{}{}", SESSION_SCHEME, s.id),
+                "name": s.id,
+                "description": format!("Saved session ({}, {} messages)", s.model, s.conversation.len()),
+                "mimeType": "application/json",
+            }))
+            .collect();
+        Ok(json!({ "resources": resources }))
+    }
+
+    fn read_resource(&self, params: &Value) -> Result<Value, RpcError> {
+        let uri = params["uri"].as_str()
+            .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Missing uri"))?;
+        let session = uri.strip_prefix(SESSION_SCHEME)
+            .ok_or_else(|| anyhow!("Unknown resource: {}", uri))
+            .and_then(|name| self.sessions.read(name))
+            .map_err(|e| RpcError::new(RESOURCE_NOT_FOUND, format!("{:#}", e)))?;
+
+        let text = serde_json::to_string_pretty(&session)
+            .map_err(|e| RpcError::new(RESOURCE_NOT_FOUND, e.to_string()))?;
+        Ok(json!({
+            "contents": [{ "uri": uri, "mimeType": "application/json", "text": text }],
+        }))
+    }
+}
+
+fn string_arg<'a>(args: &'a Value, name: &str) -> Result<&'a str> {
+    args[name].as_str()
+        .with_context(|| format!("Missing string argument '{}'", name))
+}
+
+fn transcript(session: &Session) -> String {
+    session.conversation.messages().into_iter()
+        .map(|m| format!("{}: {}", m.role, m.content))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": error.code, "message": error.message },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use claude_common::conversation::Conversation;
+
+    fn server(dir: &std::path::Path) -> McpServe {
+        McpServe::new(Config {
+            config_dir: dir.to_path_buf(),
+            ..Config::default()
+        })
+    }
+
+    fn request(method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params })
+    }
+
+    #[tokio::test]
+    async fn test_handshake_and_tools() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = server(dir.path());
+
+        let init = server.handle(request("initialize", json!({}))).await.unwrap();
+        assert_eq!(init["result"]["serverInfo"]["name"], "claude-cli");
+        assert!(server.handle(json!({ "jsonrpc": "2.0", "method": "notifications/initialized" })).await.is_none());
+
+        let tools = server.handle(request("tools/list", json!({}))).await.unwrap();
+        let names: Vec<&str> = tools["result"]["tools"].as_array().unwrap().iter()
+            .map(|t| t["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["ask_claude", "list_sessions", "read_session", "search_history"]);
+
+        let unknown = server.handle(request("sampling/createMessage", json!({}))).await.unwrap();
+        assert_eq!(unknown["error"]["code"], METHOD_NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_sessions_as_tools_and_resources() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = server(dir.path());
+        server.sessions.save(&Session {
+            id: "recon".to_string(),
+            model: "claude-3-sonnet".to_string(),
+            conversation: Conversation::from_messages(vec![claude_common::types::Message {
+                role: "user".to_string(),
+                content: "Scan 10.0.0.1".to_string(),
+                timestamp: Utc::now(),
+                interrupted: false,
+                attachments: Vec::new(),
+            }]),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }).unwrap();
+
+        let call = |name: &str, arguments: Value| request("tools/call", json!({ "name": name, "arguments": arguments }));
+
+        let read = server.handle(call("read_session", json!({ "name": "recon" }))).await.unwrap();
+        assert_eq!(read["result"]["content"][0]["text"], "user: Scan 10.0.0.1");
+
+        let search = server.handle(call("search_history", json!({ "query": "10.0.0" }))).await.unwrap();
+        assert_eq!(search["result"]["content"][0]["text"], "recon #1 user: Scan 10.0.0.1");
+
+        let missing = server.handle(call("read_session", json!({ "name": "nope" }))).await.unwrap();
+        assert_eq!(missing["result"]["isError"], true);
+
+        let resources = server.handle(request("resources/list", json!({}))).await.unwrap();
+        assert_eq!(resources["result"]["resources"][0]["uri"], "session://recon");
+        let contents = server.handle(request("resources/read", json!({ "uri": "session://recon" }))).await.unwrap();
+        assert!(contents["result"]["contents"][0]["text"].as_str().unwrap().contains("Scan 10.0.0.1"));
+    }
+}
@@ -0,0 +1,3 @@
+pub mod audit;
+pub mod mcp_serve;
+pub mod single;
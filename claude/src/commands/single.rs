@@ -0,0 +1,30 @@
+use anyhow::Result;
+use chrono::Utc;
+use claude_common::{Config, ClaudeClient};
+use claude_common::tools::{Approver, ToolRegistry};
+use claude_common::types::{Attachment, Message};
+
+/// Send one message with any attached files, answering tool calls, and
+/// print the reply.
+pub async fn run(
+    client: &ClaudeClient,
+    config: &Config,
+    mut tools: ToolRegistry,
+    mut approver: Box<dyn Approver>,
+    message: &str,
+    attachments: Vec<Attachment>,
+) -> Result<()> {
+    let message = Message {
+        role: "user".to_string(),
+        content: message.to_string(),
+        timestamp: Utc::now(),
+        interrupted: false,
+        attachments,
+    };
+    let mut messages = vec![message.api_message()];
+    let response = tools
+        .converse(client, &config.default_model, &mut messages, approver.as_mut())
+        .await?;
+    println!("{}", response);
+    Ok(())
+}
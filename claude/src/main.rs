@@ -1,7 +1,185 @@
 use anyhow::Result;
+use clap::{Parser, Subcommand};
+use claude_common::{Config, ClaudeClient};
+use claude_common::attachments::{self, Budget};
+use claude_common::audit::AuditLog;
+use claude_common::config::mcp::{find_project_file, McpConfig};
+use claude_common::config::permissions::PermissionPolicy;
+use claude_common::mcp::{ClientHost, Root, Sampler};
+use claude_common::tools::{Approver, BuiltinTools, NonInteractive, Shell, ToolRegistry};
+use claude_common::types::Attachment;
+use std::io::IsTerminal;
+use std::path::Path;
+use std::rc::Rc;
+
+mod commands;
+mod repl;
+
+use repl::approval::PromptApprover;
+use repl::session::ReplSession;
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    /// Message to send; starts an interactive session when omitted
+    message: Option<String>,
+
+    /// Model to use instead of the configured default
+    #[arg(short, long)]
+    model: Option<String>,
+
+    /// Configured profile supplying the model and system prompt
+    #[arg(short, long)]
+    profile: Option<String>,
+
+    /// Attach a file, image or PDF, a directory's files or a glob's matches
+    /// to the message; may be repeated
+    #[arg(short, long = "file", value_name = "PATH")]
+    files: Vec<String>,
+
+    /// Deny any tool call not explicitly allowed instead of prompting
+    #[arg(long)]
+    non_interactive: bool,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Serve saved sessions and Claude itself to other MCP clients over stdio
+    McpServe,
+    /// List or verify the audit log of tool calls and MCP traffic
+    Audit(commands::audit::AuditArgs),
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    println!("Claude CLI");
-    Ok(())
-}
\ No newline at end of file
+    let cli = Cli::parse();
+
+    let mut config = Config::load()?;
+    match &cli.command {
+        Some(Commands::McpServe) => return commands::mcp_serve::run(config).await,
+        Some(Commands::Audit(args)) => return commands::audit::run(&config, args),
+        None => {}
+    }
+
+    let system = match &cli.profile {
+        Some(name) => {
+            let profile = config.profile(name)?.clone();
+            if let Some(model) = profile.model {
+                config.default_model = model;
+            }
+            profile.system
+        }
+        None => None,
+    };
+    if let Some(model) = cli.model {
+        config.default_model = model;
+    }
+
+    let client = ClaudeClient::new(config.api_key.clone()).with_system(system);
+    let cwd = std::env::current_dir()?;
+    let files = attach_files(&cli.files, &cwd)?;
+    let policy = PermissionPolicy::load(&config.config_dir)?;
+
+    // Without a terminal there is nobody to answer a prompt
+    let interactive = !cli.non_interactive && std::io::stdin().is_terminal();
+    let mcp_config = load_mcp_config(&config, &cwd, interactive)?;
+    let approver: Box<dyn Approver> = if interactive {
+        Box::new(PromptApprover)
+    } else {
+        Box::new(NonInteractive)
+    };
+
+    let mut host = ClientHost {
+        roots: vec![Root::directory(&cwd)],
+        sampler: None,
+        audit: Some(Rc::new(AuditLog::open(&config.log_dir)?)),
+    };
+    if let Some(project) = find_project_file(&cwd).as_deref().and_then(Path::parent) {
+        if project != cwd {
+            host.roots.push(Root::directory(project));
+        }
+    }
+    if interactive && config.sampling_max_tokens > 0 {
+        host.sampler = Some(Rc::new(Sampler::new(
+            ClaudeClient::new(config.api_key.clone()),
+            config.default_model.clone(),
+            config.sampling_max_tokens,
+            Box::new(PromptApprover),
+        )));
+    }
+    let mut tools = ToolRegistry::connect(&mcp_config.get_enabled_servers(), policy, host).await;
+    if config.builtin_tools {
+        let mut builtin = BuiltinTools::new(&cwd)?;
+        if config.shell.enabled {
+            builtin = builtin.with_shell(Shell::new(config.shell.clone()));
+        }
+        tools.add_builtin(builtin);
+    }
+
+    // Whatever happens from here, the terminal is left as it was found
+    let terminal = repl::terminal::TerminalGuard::install();
+    let run = async {
+        match cli.message {
+            Some(message) => commands::single::run(&client, &config, tools, approver, &message, files).await,
+            None => {
+                let mut session = ReplSession::new(client, config, tools, approver);
+                session.attach(files);
+                session.run().await
+            }
+        }
+    };
+    let result = tokio::select! {
+        result = run => result,
+        _ = repl::terminal::terminated() => Ok(()),
+    };
+    // The session is dropped by now, taking its MCP servers and audit log with it
+    if let Some(terminal) = terminal {
+        terminal.exit_if_terminated();
+    }
+    result
+}
+
+/// Read the `--file` arguments, noting on stderr any files passed over.
+fn attach_files(patterns: &[String], cwd: &Path) -> Result<Vec<Attachment>> {
+    let mut files = Vec::new();
+    for pattern in patterns {
+        let collected = attachments::collect(pattern, cwd, Budget::after(&files))?;
+        for note in collected.skipped {
+            eprintln!("Skipped: {}", note);
+        }
+        files.extend(collected.attachments);
+    }
+    Ok(files)
+}
+
+/// User-level MCP servers plus those of the project's `.mcp.json`, which only
+/// start once the user has trusted the file as it is now.
+fn load_mcp_config(config: &Config, cwd: &Path, interactive: bool) -> Result<McpConfig> {
+    let mut mcp_config = McpConfig::load(&config.config_dir)?;
+    if let Some(project) = mcp_config.merge_project(cwd)?.filter(|p| !p.trusted).cloned() {
+        let servers = mcp_config.untrusted_servers().to_vec();
+        if interactive {
+            println!("{} defines MCP servers that would run on this machine:", project.path.display());
+            for server in &servers {
+                println!("  {}  {}", server.name, server.transport());
+            }
+        }
+        if interactive && repl::approval::confirm("Trust this file and start them?")? {
+            mcp_config.trust_project()?;
+        } else {
+            let names: Vec<&str> = servers.iter().map(|s| s.name.as_str()).collect();
+            eprintln!(
+                "Note: MCP servers {} in {} were found but not trusted; `claude-config mcp trust` starts them",
+                names.join(", "),
+                project.path.display()
+            );
+        }
+    }
+    for name in mcp_config.project_overrides() {
+        eprintln!("Note: project MCP server '{}' overrides the user-level definition", name);
+    }
+    Ok(mcp_config)
+}
@@ -0,0 +1,141 @@
+use anyhow::Result;
+use claude_common::mcp::SamplingRequest;
+use claude_common::tools::{Approval, Approver, ToolCall};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, is_raw_mode_enabled};
+use std::io::{self, Write};
+
+use super::terminal::read_event;
+
+/// Asks on the terminal before running a tool the policy has no answer for.
+pub struct PromptApprover;
+
+impl Approver for PromptApprover {
+    fn approve(&mut self, call: &ToolCall) -> Result<Approval> {
+        let details = match &call.preview {
+            Some(preview) => preview.clone(),
+            None => serde_json::to_string_pretty(&call.arguments)?,
+        };
+        print!("\r\nTool call: {}.{}\r\n", call.server, call.tool);
+        for line in details.lines() {
+            print!("  {}\r\n", line);
+        }
+        // An ask rule, or a tool that always asks, would outrank an allow rule
+        if call.always {
+            print!("Allow? [y]es / [n]o / [a]lways: ");
+        } else {
+            print!("Allow? [y]es / [n]o: ");
+        }
+        io::stdout().flush()?;
+
+        let answer = with_raw_mode(|| read_answer(call.always))?;
+        let label = match answer {
+            Approval::Once => "yes",
+            Approval::Always => "always",
+            Approval::Deny => "no",
+        };
+        print!("{}\r\n", label);
+        io::stdout().flush()?;
+        Ok(answer)
+    }
+
+    fn approve_sampling(&mut self, server: &str, request: &SamplingRequest, max_tokens: u32) -> Result<bool> {
+        print!("\r\nMCP server '{}' asks for a completion (up to {} tokens)\r\n", server, max_tokens);
+        if let Some(system) = &request.system_prompt {
+            print!("  system: {}\r\n", preview(system));
+        }
+        for message in &request.messages {
+            let text = message.text().unwrap_or("[non-text content]");
+            print!("  {}: {}\r\n", message.role, preview(text));
+        }
+        // Only `y` allows: every completion is billed, so each one is
+        // confirmed and there is no "always"
+        confirm("Allow?")
+    }
+}
+
+/// Ask a yes/no question; anything but `y` is no.
+pub fn confirm(question: &str) -> Result<bool> {
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+    let yes = with_raw_mode(|| loop {
+        if let Event::Key(KeyEvent { code, kind, .. }) = read_event()? {
+            if kind != KeyEventKind::Release {
+                return Ok(matches!(code, KeyCode::Char('y') | KeyCode::Char('Y')));
+            }
+        }
+    })?;
+    print!("{}\r\n", if yes { "yes" } else { "no" });
+    io::stdout().flush()?;
+    Ok(yes)
+}
+
+/// First line of `text`, shortened for a one-line summary.
+fn preview(text: &str) -> String {
+    let line = text.lines().next().unwrap_or_default();
+    let mut preview: String = line.chars().take(120).collect();
+    if preview.len() < text.len() {
+        preview.push_str(" ...");
+    }
+    preview
+}
+
+/// Single-message mode runs in cooked mode; only a keypress is needed here.
+fn with_raw_mode<T>(read: impl FnOnce() -> Result<T>) -> Result<T> {
+    let was_raw = is_raw_mode_enabled()?;
+    if !was_raw {
+        enable_raw_mode()?;
+    }
+    let result = read();
+    if !was_raw {
+        disable_raw_mode()?;
+    }
+    result
+}
+
+fn read_answer(always: bool) -> Result<Approval> {
+    loop {
+        if let Event::Key(key) = read_event()? {
+            if let Some(answer) = answer(key, always) {
+                return Ok(answer);
+            }
+        }
+    }
+}
+
+/// The answer a key gives, if any. Raw mode turns Ctrl-C into a key, which
+/// denies the call as Esc does.
+fn answer(key: KeyEvent, always: bool) -> Option<Approval> {
+    if key.kind == KeyEventKind::Release {
+        return None;
+    }
+    match key.code {
+        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => Some(Approval::Deny),
+        KeyCode::Char('y') | KeyCode::Char('Y') => Some(Approval::Once),
+        KeyCode::Char('a') | KeyCode::Char('A') if always => Some(Approval::Always),
+        KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => Some(Approval::Deny),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_answer_keys() {
+        let press = |code, modifiers| KeyEvent::new(code, modifiers);
+        let none = KeyModifiers::NONE;
+        assert_eq!(answer(press(KeyCode::Char('c'), KeyModifiers::CONTROL), true), Some(Approval::Deny));
+        assert_eq!(answer(press(KeyCode::Esc, none), true), Some(Approval::Deny));
+        assert_eq!(answer(press(KeyCode::Char('y'), none), false), Some(Approval::Once));
+        assert_eq!(answer(press(KeyCode::Char('a'), none), true), Some(Approval::Always));
+        assert_eq!(answer(press(KeyCode::Char('a'), none), false), None);
+        assert_eq!(answer(press(KeyCode::Char('c'), none), true), None);
+
+        // A release follows the press the answer was taken from
+        let mut release = press(KeyCode::Char('y'), none);
+        release.kind = KeyEventKind::Release;
+        assert_eq!(answer(release, true), None);
+    }
+}
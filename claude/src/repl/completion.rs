@@ -0,0 +1,199 @@
+use claude_common::sessions::SessionStore;
+use std::path::{Path, PathBuf};
+
+/// What the first argument of a command names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Argument {
+    None,
+    Session,
+    Model,
+    Profile,
+    /// An MCP server or one of its tools
+    Tool,
+    /// A file or directory
+    Path,
+    Prompt,
+    Branch,
+}
+
+/// Every command `parse_command` accepts, with what its argument completes to.
+pub(super) const COMMANDS: &[(&str, Argument)] = &[
+    ("help", Argument::None),
+    ("q", Argument::None),
+    ("quit", Argument::None),
+    ("list", Argument::None),
+    ("save", Argument::Session),
+    ("load", Argument::Session),
+    ("model", Argument::Model),
+    ("profile", Argument::Profile),
+    ("clear", Argument::None),
+    ("tools", Argument::Tool),
+    ("resources", Argument::None),
+    ("resource", Argument::None),
+    ("subscribe", Argument::None),
+    ("unsubscribe", Argument::None),
+    ("prompts", Argument::None),
+    ("prompt", Argument::Prompt),
+    ("blocks", Argument::None),
+    ("yank", Argument::None),
+    ("w", Argument::Path),
+    ("w!", Argument::Path),
+    ("copy", Argument::None),
+    ("read", Argument::Path),
+    ("attach", Argument::Path),
+    ("edit", Argument::None),
+    ("view", Argument::None),
+    ("retry", Argument::Model),
+    ("undo", Argument::None),
+    ("edit-last", Argument::None),
+    ("fork", Argument::None),
+    ("branches", Argument::None),
+    ("checkout", Argument::Branch),
+];
+
+/// Suggested for `:model` along with the configured ones; any model the API
+/// accepts can still be typed.
+const KNOWN_MODELS: &[&str] = &["claude-3-opus", "claude-3-sonnet", "claude-3-haiku"];
+
+/// Candidates for Tab in command mode, gathered from the session when the
+/// command line opens. Saved sessions and files are read at each Tab.
+#[derive(Debug, Default)]
+pub struct Completer {
+    pub config_dir: PathBuf,
+    pub models: Vec<String>,
+    pub profiles: Vec<String>,
+    pub servers: Vec<String>,
+    pub tools: Vec<String>,
+    /// Names from the last `:prompts`
+    pub prompts: Vec<String>,
+    pub branches: Vec<String>,
+}
+
+impl Completer {
+    /// Where the word ending `line` starts, and what could replace it: command
+    /// names for the first word, then names fitting the command's argument.
+    /// Any word that looks like a path completes to files.
+    pub fn complete(&self, line: &str) -> (usize, Vec<String>) {
+        let start = line.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let word = &line[start..];
+        let mut before = line[..start].split_whitespace();
+
+        let Some(command) = before.next() else {
+            let colon = usize::from(word.starts_with(':'));
+            let names = COMMANDS.iter().map(|(name, _)| name.to_string()).collect();
+            return (start + colon, matching(names, &word[colon..]));
+        };
+        if ["./", "../", "/", "~/"].iter().any(|p| word.starts_with(p)) {
+            return (start, paths(word));
+        }
+
+        let command = command.strip_prefix(':').unwrap_or(command);
+        let argument = match before.next() {
+            // `:w` may take a block number before its path
+            Some(n) if matches!(command, "w" | "w!") && n.parse::<usize>().is_ok() && before.next().is_none() => Argument::Path,
+            Some(_) => Argument::None,
+            None => COMMANDS.iter().find(|(name, _)| *name == command).map_or(Argument::None, |c| c.1),
+        };
+        let names = match argument {
+            Argument::None => Vec::new(),
+            Argument::Session => SessionStore::new(&self.config_dir).names().unwrap_or_default(),
+            Argument::Model => KNOWN_MODELS.iter().map(|m| m.to_string()).chain(self.models.iter().cloned()).collect(),
+            Argument::Profile => self.profiles.clone(),
+            Argument::Tool => self.servers.iter().chain(&self.tools).cloned().collect(),
+            Argument::Prompt => self.prompts.clone(),
+            Argument::Branch => self.branches.clone(),
+            Argument::Path => return (start, paths(word)),
+        };
+        (start, matching(names, word))
+    }
+}
+
+/// `names` starting with `prefix`, sorted and without repeats.
+fn matching(mut names: Vec<String>, prefix: &str) -> Vec<String> {
+    names.retain(|n| n.starts_with(prefix));
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Files and directories completing `word`, as typed: `~/` stays `~/` and
+/// directories end in `/`. Hidden files only show once a `.` is typed.
+fn paths(word: &str) -> Vec<String> {
+    let (dir, name) = match word.rfind('/') {
+        Some(i) => word.split_at(i + 1),
+        None => ("", word),
+    };
+    let listed = match dir.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir().unwrap_or_default().join(rest),
+        None if dir.is_empty() => PathBuf::from("."),
+        None => PathBuf::from(dir),
+    };
+    let Ok(entries) = std::fs::read_dir(&listed) else {
+        return Vec::new();
+    };
+
+    let mut found: Vec<String> = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let file = e.file_name().into_string().ok()?;
+            if !file.starts_with(name) || (file.starts_with('.') && !name.starts_with('.')) {
+                return None;
+            }
+            let slash = if is_dir(&e.path()) { "/" } else { "" };
+            Some(format!("{}{}{}", dir, file, slash))
+        })
+        .collect();
+    found.sort();
+    found
+}
+
+fn is_dir(path: &Path) -> bool {
+    std::fs::metadata(path).is_ok_and(|m| m.is_dir())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_commands_and_arguments() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("sessions"))?;
+        for name in ["scan-notes", "scan-report", "triage"] {
+            std::fs::write(dir.path().join("sessions").join(format!("{}.json", name)), "{}")?;
+        }
+        let completer = Completer {
+            config_dir: dir.path().to_path_buf(),
+            profiles: vec!["reviewer".to_string()],
+            servers: vec!["nmap".to_string()],
+            tools: vec!["nmap__scan".to_string(), "read_file".to_string()],
+            ..Completer::default()
+        };
+
+        assert_eq!(completer.complete(":pro"), (1, vec!["profile".to_string(), "prompt".to_string(), "prompts".to_string()]));
+        assert_eq!(completer.complete("load scan-").1, ["scan-notes", "scan-report"]);
+        assert_eq!(completer.complete("model claude-3-h"), (6, vec!["claude-3-haiku".to_string()]));
+        assert_eq!(completer.complete("profile r").1, ["reviewer"]);
+        assert_eq!(completer.complete("tools nm").1, ["nmap", "nmap__scan"]);
+        assert!(completer.complete("clear x").1.is_empty());
+        // Tests run in the package directory
+        assert_eq!(completer.complete("w Cargo.t").1, ["Cargo.toml"]);
+        assert_eq!(completer.complete(":w! 2 Cargo.t"), (6, vec!["Cargo.toml".to_string()]));
+        assert!(completer.complete("w 2 3 Cargo.t").1.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_paths() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("src"))?;
+        std::fs::write(dir.path().join("session.rs"), "")?;
+        std::fs::write(dir.path().join(".hidden"), "")?;
+        let base = format!("{}/", dir.path().display());
+
+        assert_eq!(paths(&base), [format!("{}session.rs", base), format!("{}src/", base)]);
+        assert_eq!(paths(&format!("{}.", base)), [format!("{}.hidden", base)]);
+        Ok(())
+    }
+}
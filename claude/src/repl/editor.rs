@@ -0,0 +1,928 @@
+use anyhow::Result;
+use claude_common::config::{Keymap, SubmitKey};
+use crossterm::cursor::{MoveToColumn, MoveUp, SetCursorStyle};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, SetAttribute};
+use crossterm::queue;
+use crossterm::terminal::{self, Clear, ClearType};
+use std::io::{self, Write};
+use std::ops::Range;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+mod vi;
+
+use super::completion::Completer;
+use super::terminal::{self as guard, RawMode};
+use vi::{Vi, ViMode};
+
+/// Opens and closes a block in which Enter adds lines instead of sending.
+pub(super) const HEREDOC: &str = "\"\"\"";
+
+/// What ended a `read_line`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Input {
+    /// The submit key was pressed; the editor is left empty
+    Line(String),
+    /// Esc, or `:` in Vi Normal mode, was pressed; the text stays for the
+    /// next `read_line`
+    Escape,
+    /// Ctrl-C was pressed with nothing typed
+    Interrupt,
+}
+
+/// Effect of one key on the editor.
+#[derive(Debug, PartialEq, Eq)]
+enum Action {
+    Redraw,
+    Submit,
+    Escape,
+    Interrupt,
+    Ignore,
+}
+
+/// Ctrl-R state: what has been typed and the history entry it found.
+struct Search {
+    query: String,
+    found: Option<usize>,
+    failed: bool,
+    /// Buffer and cursor to go back to if the search is cancelled
+    original: (String, usize),
+}
+
+/// Candidates offered by Tab for the word that starts at `start`.
+struct Completion {
+    start: usize,
+    candidates: Vec<String>,
+    /// The candidate in the buffer once Tab cycles through them
+    index: Option<usize>,
+}
+
+/// Editor for one or more lines: Emacs-style cursor and word motions, kill
+/// and yank, optional Vi modes, history recall and search, and bracketed
+/// paste, measuring text in graphemes and terminal columns so wide and
+/// combining characters edit and wrap correctly.
+#[derive(Default)]
+pub struct LineEditor {
+    buffer: String,
+    /// Byte offset into `buffer`, always on a grapheme boundary
+    cursor: usize,
+    submit_key: SubmitKey,
+    /// Set for the Vi keymap
+    vi: Option<Vi>,
+    /// Text removed by the last run of kill commands, for Ctrl-Y
+    killed: String,
+    /// The previous key killed text, so the next kill adds to `killed`
+    killing: bool,
+    /// Rows between the prompt's first row and the cursor, as last drawn
+    cursor_row: u16,
+    /// Earlier input, oldest first
+    history: Vec<String>,
+    /// The entry shown by Up and Down, if any
+    browsing: Option<usize>,
+    /// What was being typed before Up was first pressed
+    draft: String,
+    search: Option<Search>,
+    /// Set for the command line, where Tab completes
+    completer: Option<Completer>,
+    completion: Option<Completion>,
+}
+
+impl LineEditor {
+    pub fn new(submit_key: SubmitKey, keymap: Keymap) -> Self {
+        Self {
+            submit_key,
+            vi: (keymap == Keymap::Vi).then(Vi::new),
+            ..Self::default()
+        }
+    }
+
+    /// Edit text after `prompt` until it is submitted or Esc is pressed. The
+    /// terminal is in raw mode only while reading, so output in between is
+    /// printed normally.
+    pub fn read_line(&mut self, base_prompt: &str) -> Result<Input> {
+        let _raw = RawMode::enable(self.vi.is_some())?;
+        let mut out = io::stdout();
+        self.cursor_row = 0;
+        self.refresh(&self.prompt(base_prompt), true, &mut out)?;
+
+        loop {
+            let action = match guard::read_event()? {
+                Event::Key(key) if key.kind != KeyEventKind::Release => self.apply(key),
+                Event::Paste(text) => {
+                    self.insert(&clean_paste(&text));
+                    Action::Redraw
+                }
+                Event::Resize(width, _) => {
+                    let width = Some(width).filter(|&w| w > 0).unwrap_or_else(terminal_width);
+                    self.cursor_row = self.cursor_position(&self.prompt(base_prompt), width).0;
+                    Action::Redraw
+                }
+                _ => Action::Ignore,
+            };
+            let prompt = self.prompt(base_prompt);
+            let prompt = prompt.as_str();
+
+            match action {
+                Action::Redraw => self.refresh(prompt, true, &mut out)?,
+                Action::Ignore => {}
+                Action::Submit | Action::Escape | Action::Interrupt => {
+                    // Leave the terminal cursor on a fresh line below the input
+                    let cursor = std::mem::replace(&mut self.cursor, self.buffer.len());
+                    self.refresh(prompt, false, &mut out)?;
+                    write!(out, "\r\n")?;
+                    out.flush()?;
+
+                    if action == Action::Escape {
+                        self.cursor = cursor;
+                        return Ok(Input::Escape);
+                    }
+                    if action == Action::Interrupt {
+                        return Ok(Input::Interrupt);
+                    }
+                    self.cursor = 0;
+                    self.browsing = None;
+                    if let Some(vi) = &mut self.vi {
+                        vi.reset();
+                    }
+                    let text = std::mem::take(&mut self.buffer);
+                    return Ok(Input::Line(strip_heredoc(&text).unwrap_or(text)));
+                }
+            }
+        }
+    }
+
+    /// What has been typed and not yet submitted.
+    pub fn text(&self) -> &str {
+        &self.buffer
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+        self.cursor = 0;
+        self.browsing = None;
+    }
+
+    /// Entries for Up, Down and Ctrl-R to recall, oldest first.
+    pub fn set_history(&mut self, history: Vec<String>) {
+        self.history = history;
+        self.browsing = None;
+    }
+
+    /// Make `text` the next paste: into a Vi register (the unnamed one when
+    /// `register` is `None`), or for Ctrl-Y with the Emacs keys.
+    pub fn yank(&mut self, register: Option<char>, text: &str) {
+        match &mut self.vi {
+            Some(vi) => vi.yank(register, text),
+            None => self.killed = text.to_string(),
+        }
+    }
+
+    /// Names and paths for Tab to offer.
+    pub fn set_completer(&mut self, completer: Completer) {
+        self.completer = Some(completer);
+        self.completion = None;
+    }
+
+    /// `prompt` with the Vi mode in front of it, or the search being typed.
+    fn prompt(&self, prompt: &str) -> String {
+        match (&self.search, &self.vi) {
+            (Some(search), _) => format!(
+                "({}reverse-i-search)'{}': ",
+                if search.failed { "failed " } else { "" },
+                search.query,
+            ),
+            (None, Some(vi)) => format!("{} {}", vi.mode.tag(), prompt),
+            (None, None) => prompt.to_string(),
+        }
+    }
+
+    fn apply(&mut self, key: KeyEvent) -> Action {
+        if let Some(action) = self.apply_search(key) {
+            return action;
+        }
+        // Ctrl-Z suspends even in Vi Normal mode; the prompt is redrawn on
+        // a new line when the shell continues the process
+        if key.code == KeyCode::Char('z') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            guard::suspend();
+            self.cursor_row = 0;
+            return Action::Redraw;
+        }
+        // Ctrl-C drops what was typed, and says so when there is nothing
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            if self.buffer.is_empty() {
+                return Action::Interrupt;
+            }
+            self.clear();
+            if let Some(vi) = &mut self.vi {
+                vi.reset();
+            }
+            return Action::Redraw;
+        }
+        match key.code {
+            KeyCode::Tab => return self.complete(true),
+            KeyCode::BackTab => return self.complete(false),
+            _ => self.completion = None,
+        }
+        // Up on the first line and Down on the last go through the history
+        match key.code {
+            KeyCode::Up if key.modifiers.is_empty() && self.line_start() == 0 => {
+                self.recall(-1);
+                return Action::Redraw;
+            }
+            KeyCode::Down if key.modifiers.is_empty() && self.line_end() == self.buffer.len() => {
+                self.recall(1);
+                return Action::Redraw;
+            }
+            _ => {}
+        }
+        if let Some(vi) = &mut self.vi {
+            if let Some(action) = vi.apply(key, &mut self.buffer, &mut self.cursor) {
+                return action;
+            }
+        }
+        let continuing_kill = std::mem::take(&mut self.killing);
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+        let shift = key.modifiers.contains(KeyModifiers::SHIFT);
+        // Ctrl-J is a newline key every terminal can send
+        let other_enter = match key.code {
+            KeyCode::Enter => alt || shift || ctrl,
+            KeyCode::Char('j') => ctrl,
+            _ => false,
+        };
+
+        match key.code {
+            _ if other_enter => match self.submit_key {
+                SubmitKey::Enter => self.insert("\n"),
+                SubmitKey::AltEnter => return Action::Submit,
+            },
+            KeyCode::Enter => match self.submit_key {
+                SubmitKey::Enter if !in_heredoc(&self.buffer) => return Action::Submit,
+                _ => self.insert("\n"),
+            },
+            KeyCode::Esc => return Action::Escape,
+            KeyCode::Left if ctrl || alt => self.cursor = self.word_start(is_word),
+            KeyCode::Right if ctrl || alt => self.cursor = self.word_end(),
+            KeyCode::Left => self.cursor = self.previous_boundary(),
+            KeyCode::Right => self.cursor = self.next_boundary(),
+            KeyCode::Up => self.cursor = self.vertical(-1),
+            KeyCode::Down => self.cursor = self.vertical(1),
+            KeyCode::Home => self.cursor = self.line_start(),
+            KeyCode::End => self.cursor = self.line_end(),
+            KeyCode::Backspace if ctrl || alt => self.kill(self.word_start(is_word)..self.cursor, continuing_kill),
+            KeyCode::Backspace => self.remove(self.previous_boundary()..self.cursor),
+            KeyCode::Delete => self.remove(self.cursor..self.next_boundary()),
+            KeyCode::Char(c) if ctrl => match c {
+                'a' => self.cursor = self.line_start(),
+                'e' => self.cursor = self.line_end(),
+                'b' => self.cursor = self.previous_boundary(),
+                'f' => self.cursor = self.next_boundary(),
+                'p' if self.line_start() == 0 => self.recall(-1),
+                'n' if self.line_end() == self.buffer.len() => self.recall(1),
+                'p' => self.cursor = self.vertical(-1),
+                'n' => self.cursor = self.vertical(1),
+                'r' => self.start_search(),
+                'h' => self.remove(self.previous_boundary()..self.cursor),
+                'd' => self.remove(self.cursor..self.next_boundary()),
+                // At the end of a line, Ctrl-K joins it with the next one
+                'k' if self.cursor == self.line_end() => self.kill(self.cursor..self.next_boundary(), continuing_kill),
+                'k' => self.kill(self.cursor..self.line_end(), continuing_kill),
+                'u' => self.kill(self.line_start()..self.cursor, continuing_kill),
+                'w' => self.kill(self.word_start(is_not_space)..self.cursor, continuing_kill),
+                'y' => self.insert(&self.killed.clone()),
+                _ => return Action::Ignore,
+            },
+            KeyCode::Char(c) if alt => match c {
+                'b' => self.cursor = self.word_start(is_word),
+                'f' => self.cursor = self.word_end(),
+                'd' => self.kill(self.cursor..self.word_end(), continuing_kill),
+                _ => return Action::Ignore,
+            },
+            KeyCode::Char(c) => self.insert(c.encode_utf8(&mut [0; 4])),
+            _ => return Action::Ignore,
+        }
+        Action::Redraw
+    }
+
+    /// Show the previous (-1) or next (1) history entry. Going past the
+    /// newest brings back what was being typed.
+    fn recall(&mut self, direction: i8) {
+        let index = match (direction, self.browsing) {
+            (-1, None) if !self.history.is_empty() => self.history.len() - 1,
+            (-1, Some(i)) => i.saturating_sub(1),
+            (1, Some(i)) if i + 1 < self.history.len() => i + 1,
+            (1, Some(_)) => {
+                self.browsing = None;
+                self.buffer = std::mem::take(&mut self.draft);
+                self.cursor = self.buffer.len();
+                return;
+            }
+            _ => return,
+        };
+        if self.browsing.is_none() {
+            self.draft = self.buffer.clone();
+        }
+        self.browsing = Some(index);
+        self.buffer = self.history[index].clone();
+        self.cursor = match &self.vi {
+            Some(vi) if vi.mode != ViMode::Insert => 0,
+            _ => self.buffer.len(),
+        };
+    }
+
+    /// Complete the word before the cursor: a single candidate is filled
+    /// in, several as far as they agree. Pressing Tab again cycles through
+    /// them, Shift-Tab backwards.
+    fn complete(&mut self, forward: bool) -> Action {
+        let Some(completer) = &self.completer else {
+            return Action::Ignore;
+        };
+        if let Some(completion) = &mut self.completion {
+            let count = completion.candidates.len();
+            let index = match (completion.index, forward) {
+                (None, true) => 0,
+                (None, false) => count - 1,
+                (Some(i), true) => (i + 1) % count,
+                (Some(i), false) => (i + count - 1) % count,
+            };
+            completion.index = Some(index);
+            let start = completion.start;
+            let candidate = completion.candidates[index].clone();
+            self.buffer.replace_range(start..self.cursor, &candidate);
+            self.cursor = start + candidate.len();
+            return Action::Redraw;
+        }
+
+        let (start, candidates) = completer.complete(&self.buffer[..self.cursor]);
+        let text = match candidates.as_slice() {
+            [] => return Action::Ignore,
+            [only] if only.ends_with('/') => only.clone(),
+            [only] => format!("{} ", only),
+            _ => common_prefix(&candidates).to_string(),
+        };
+        self.buffer.replace_range(start..self.cursor, &text);
+        self.cursor = start + text.len();
+        if candidates.len() > 1 {
+            self.completion = Some(Completion { start, candidates, index: None });
+        }
+        Action::Redraw
+    }
+
+    fn start_search(&mut self) {
+        self.search = Some(Search {
+            query: String::new(),
+            found: None,
+            failed: false,
+            original: (self.buffer.clone(), self.cursor),
+        });
+    }
+
+    /// Keys typed during a Ctrl-R search. Any key that is not part of the
+    /// search ends it, keeping the entry found, and then acts as usual.
+    fn apply_search(&mut self, key: KeyEvent) -> Option<Action> {
+        let search = self.search.as_mut()?;
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+
+        match key.code {
+            KeyCode::Char('r') if ctrl => {
+                let before = search.found.unwrap_or(self.history.len());
+                self.find(before);
+            }
+            KeyCode::Char('g' | 'c') if ctrl => self.cancel_search(),
+            KeyCode::Esc => self.cancel_search(),
+            KeyCode::Backspace => {
+                search.query.pop();
+                self.find(self.history.len());
+            }
+            KeyCode::Char(c) if !ctrl && !alt => {
+                search.query.push(c);
+                // The entry found so far may still match
+                let before = search.found.map_or(self.history.len(), |i| i + 1);
+                self.find(before);
+            }
+            _ => {
+                self.search = None;
+                return None;
+            }
+        }
+        Some(Action::Redraw)
+    }
+
+    /// Show the newest entry before `before` that contains the query.
+    fn find(&mut self, before: usize) {
+        let Some(search) = &mut self.search else {
+            return;
+        };
+        let found = self.history[..before].iter().rposition(|entry| entry.contains(&search.query));
+        search.failed = found.is_none();
+        if let Some(i) = found {
+            search.found = Some(i);
+            self.buffer = self.history[i].clone();
+            self.cursor = self.buffer.find(&search.query).unwrap_or(0);
+        }
+    }
+
+    fn cancel_search(&mut self) {
+        if let Some(search) = self.search.take() {
+            (self.buffer, self.cursor) = search.original;
+        }
+    }
+
+    fn insert(&mut self, text: &str) {
+        self.buffer.insert_str(self.cursor, text);
+        self.cursor += text.len();
+    }
+
+    fn remove(&mut self, range: Range<usize>) {
+        self.cursor = range.start;
+        self.buffer.replace_range(range, "");
+    }
+
+    /// Remove `range` into the kill buffer. Consecutive kills collect into
+    /// one, so Ctrl-W Ctrl-W then Ctrl-Y restores both words.
+    fn kill(&mut self, range: Range<usize>, continuing: bool) {
+        if range.is_empty() {
+            self.killing = continuing;
+            return;
+        }
+        let text = &self.buffer[range.clone()];
+        if !continuing {
+            self.killed.clear();
+        }
+        if range.end <= self.cursor {
+            self.killed.insert_str(0, text);
+        } else {
+            self.killed.push_str(text);
+        }
+        self.remove(range);
+        self.killing = true;
+    }
+
+    fn previous_boundary(&self) -> usize {
+        previous_boundary(&self.buffer, self.cursor)
+    }
+
+    fn next_boundary(&self) -> usize {
+        next_boundary(&self.buffer, self.cursor)
+    }
+
+    fn line_start(&self) -> usize {
+        line_start(&self.buffer, self.cursor)
+    }
+
+    fn line_end(&self) -> usize {
+        line_end(&self.buffer, self.cursor)
+    }
+
+    fn vertical(&self, direction: i8) -> usize {
+        vertical(&self.buffer, self.cursor, direction)
+    }
+
+    /// Start of the word before the cursor, skipping separators first.
+    fn word_start(&self, in_word: fn(&str) -> bool) -> usize {
+        let mut start = self.cursor;
+        let mut seen_word = false;
+        for (i, grapheme) in self.buffer[..self.cursor].grapheme_indices(true).rev() {
+            if in_word(grapheme) {
+                seen_word = true;
+            } else if seen_word {
+                break;
+            }
+            start = i;
+        }
+        start
+    }
+
+    /// End of the word after the cursor, skipping separators first.
+    fn word_end(&self) -> usize {
+        let mut seen_word = false;
+        for (i, grapheme) in self.buffer[self.cursor..].grapheme_indices(true) {
+            if is_word(grapheme) {
+                seen_word = true;
+            } else if seen_word {
+                return self.cursor + i;
+            }
+        }
+        self.buffer.len()
+    }
+
+    /// Each line of the buffer after its prompt: `prompt` for the first and a
+    /// line number for the rest. Also the line the cursor is on and its byte
+    /// offset within that display line.
+    fn display_lines(&self, prompt: &str) -> (Vec<String>, usize, usize) {
+        let number_width = prompt.width().max(3) - 2;
+        let mut lines = Vec::new();
+        let mut cursor = (0, 0);
+        let mut offset = 0;
+
+        for (i, line) in self.buffer.split('\n').enumerate() {
+            let prefix = match i {
+                0 => prompt.to_string(),
+                _ => format!("{:>width$}| ", i + 1, width = number_width),
+            };
+            if (offset..=offset + line.len()).contains(&self.cursor) {
+                cursor = (i, prefix.len() + self.cursor - offset);
+            }
+            offset += line.len() + 1;
+            lines.push(prefix + line);
+        }
+        (lines, cursor.0, cursor.1)
+    }
+
+    fn cursor_position(&self, prompt: &str, width: u16) -> (u16, u16) {
+        let (lines, line, column) = self.display_lines(prompt);
+        let rows = line_rows(&lines, width);
+        let (row, column) = position(&lines[line][..column], width);
+        (rows[line] + row, column)
+    }
+
+    /// Below multi-line input: how many lines there are and how to send them.
+    fn status(&self) -> Option<String> {
+        let lines = self.buffer.lines().count() + usize::from(self.buffer.ends_with('\n'));
+        if lines < 2 {
+            return None;
+        }
+        let finish = match self.submit_key {
+            SubmitKey::Enter if in_heredoc(&self.buffer) => format!("{} on a line of its own to send", HEREDOC),
+            SubmitKey::Enter => String::from("Enter to send, Alt-Enter for a new line"),
+            SubmitKey::AltEnter => String::from("Alt-Enter to send"),
+        };
+        Some(format!("[{} lines, {}]", lines, finish))
+    }
+
+    /// Redraw the prompt and buffer in place, wrapping at the terminal width.
+    fn refresh(&mut self, prompt: &str, with_status: bool, out: &mut impl Write) -> Result<()> {
+        let width = terminal_width();
+        let (lines, _, _) = self.display_lines(prompt);
+        let rows = line_rows(&lines, width);
+        let cursor = self.cursor_position(prompt, width);
+
+        if self.cursor_row > 0 {
+            queue!(out, MoveUp(self.cursor_row))?;
+        }
+        queue!(out, MoveToColumn(0), Clear(ClearType::FromCursorDown))?;
+        let selection = self.vi.as_ref().and_then(|vi| vi.selection(&self.buffer, self.cursor));
+        let mut offset = 0;
+        for (i, (line, text)) in lines.iter().zip(self.buffer.split('\n')).enumerate() {
+            if i > 0 {
+                write!(out, "\r\n")?;
+            }
+            match &selection {
+                Some(selected) if selected.start <= offset + text.len() && selected.end > offset => {
+                    let start = selected.start.saturating_sub(offset);
+                    let end = (selected.end - offset).min(text.len());
+                    write!(out, "{}{}", &line[..line.len() - text.len()], &text[..start])?;
+                    queue!(out, SetAttribute(Attribute::Reverse))?;
+                    write!(out, "{}", &text[start..end])?;
+                    queue!(out, SetAttribute(Attribute::NoReverse))?;
+                    write!(out, "{}", &text[end..])?;
+                }
+                _ => write!(out, "{}", line)?,
+            }
+            offset += text.len() + 1;
+        }
+
+        // The row the terminal cursor is on. A line filled exactly leaves it in
+        // the last column rather than below, so move down explicitly.
+        let mut bottom = rows[lines.len()] - 1;
+        let menu = self.completion.as_ref().filter(|_| with_status);
+        let status = self.status().filter(|_| with_status && menu.is_none());
+        if status.is_some() || menu.is_some() || position(&lines[lines.len() - 1], width).1 == 0 {
+            write!(out, "\r\n")?;
+            bottom += 1;
+        }
+        if let Some(status) = status {
+            let fitting: String = status.chars().take(width as usize - 1).collect();
+            write!(out, "{}", fitting)?;
+        }
+        if let Some(menu) = menu {
+            write_menu(menu, width, out)?;
+        }
+
+        if bottom > cursor.0 {
+            queue!(out, MoveUp(bottom - cursor.0))?;
+        }
+        queue!(out, MoveToColumn(cursor.1))?;
+        if let Some(vi) = &self.vi {
+            let shape = match vi.mode {
+                ViMode::Insert => SetCursorStyle::SteadyBar,
+                _ => SetCursorStyle::SteadyBlock,
+            };
+            queue!(out, shape)?;
+        }
+        out.flush()?;
+
+        self.cursor_row = cursor.0;
+        Ok(())
+    }
+}
+
+/// The candidates on one row, the one in the buffer highlighted. Paths show
+/// only their last part.
+fn write_menu(menu: &Completion, width: u16, out: &mut impl Write) -> Result<()> {
+    let mut used = 0;
+    for (i, candidate) in menu.candidates.iter().enumerate() {
+        let label = match candidate.trim_end_matches('/').rfind('/') {
+            Some(slash) => &candidate[slash + 1..],
+            None => candidate.as_str(),
+        };
+        if used + label.width() + 2 > width as usize {
+            write!(out, "…")?;
+            break;
+        }
+        if menu.index == Some(i) {
+            queue!(out, SetAttribute(Attribute::Reverse))?;
+            write!(out, "{}", label)?;
+            queue!(out, SetAttribute(Attribute::NoReverse))?;
+        } else {
+            write!(out, "{}", label)?;
+        }
+        write!(out, "  ")?;
+        used += label.width() + 2;
+    }
+    Ok(())
+}
+
+/// The longest start all of `candidates` share.
+fn common_prefix(candidates: &[String]) -> &str {
+    let first = &candidates[0];
+    let mut end = first.len();
+    for other in &candidates[1..] {
+        end = first.char_indices()
+            .zip(other.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(end.min(other.len()), |((i, _), _)| i.min(end));
+    }
+    &first[..end]
+}
+
+/// Columns in the terminal. Some ptys report a size of zero.
+fn terminal_width() -> u16 {
+    terminal::size().ok().map(|(columns, _)| columns).filter(|&c| c > 0).unwrap_or(80)
+}
+
+fn previous_boundary(text: &str, position: usize) -> usize {
+    text[..position].grapheme_indices(true)
+        .next_back()
+        .map_or(0, |(i, _)| i)
+}
+
+fn next_boundary(text: &str, position: usize) -> usize {
+    text[position..].graphemes(true)
+        .next()
+        .map_or(position, |g| position + g.len())
+}
+
+fn line_start(text: &str, position: usize) -> usize {
+    text[..position].rfind('\n').map_or(0, |i| i + 1)
+}
+
+fn line_end(text: &str, position: usize) -> usize {
+    text[position..].find('\n').map_or(text.len(), |i| position + i)
+}
+
+/// `position` moved `direction` lines up (-1) or down (1), keeping its
+/// column where the other line is long enough.
+fn vertical(text: &str, position: usize, direction: i8) -> usize {
+    let start = line_start(text, position);
+    let column = text[start..position].width();
+    let target = match direction {
+        -1 if start > 0 => line_start(text, start - 1),
+        1 if line_end(text, position) < text.len() => line_end(text, position) + 1,
+        _ => return position,
+    };
+
+    let mut width = 0;
+    for (i, grapheme) in text[target..].grapheme_indices(true) {
+        if grapheme == "\n" || width + grapheme.width() > column {
+            return target + i;
+        }
+        width += grapheme.width();
+    }
+    text.len()
+}
+
+fn is_word(grapheme: &str) -> bool {
+    grapheme.chars().next().is_some_and(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn is_not_space(grapheme: &str) -> bool {
+    !grapheme.chars().all(char::is_whitespace)
+}
+
+/// Pasted text with Unix line endings, tabs as four spaces (the editor does
+/// not track tab stops) and no other control characters.
+fn clean_paste(text: &str) -> String {
+    text.replace("\r\n", "\n")
+        .replace('\r', "\n")
+        .replace('\t', "    ")
+        .chars()
+        .filter(|&c| c == '\n' || !c.is_control())
+        .collect()
+}
+
+/// Whether `text` opens a `"""` block that has not been closed yet.
+pub(super) fn in_heredoc(text: &str) -> bool {
+    text.trim_start().starts_with(HEREDOC) && strip_heredoc(text).is_none()
+}
+
+/// The contents of a complete `"""` block: any text after the opening quotes
+/// and the lines up to the closing ones.
+pub(super) fn strip_heredoc(text: &str) -> Option<String> {
+    let (first, rest) = text.trim_start().split_once('\n')?;
+    let opening = first.strip_prefix(HEREDOC)?.trim();
+    let (body, closing) = rest.rsplit_once('\n').unwrap_or(("", rest));
+    if closing.trim() != HEREDOC {
+        return None;
+    }
+    Some(match (opening.is_empty(), body.is_empty()) {
+        (true, _) => body.to_string(),
+        (false, true) => opening.to_string(),
+        (false, false) => format!("{}\n{}", opening, body),
+    })
+}
+
+/// Row and column of the cursor after printing `text` from the start of a
+/// row in a terminal `width` columns wide. Wide characters that do not fit
+/// at the end of a row move to the next one, as terminals do.
+fn position(text: &str, width: u16) -> (u16, u16) {
+    let width = width.max(1) as usize;
+    let (mut row, mut column) = (0, 0);
+    for grapheme in text.graphemes(true) {
+        let columns = grapheme.width();
+        if column + columns > width {
+            row += 1;
+            column = 0;
+        }
+        column += columns;
+        if column == width {
+            row += 1;
+            column = 0;
+        }
+    }
+    (row, column as u16)
+}
+
+/// First row of each display line, followed by the row after the last.
+fn line_rows(lines: &[String], width: u16) -> Vec<u16> {
+    let mut rows = vec![0];
+    for line in lines {
+        let (row, column) = position(line, width);
+        let start = rows[rows.len() - 1];
+        // An exactly filled line already counts the row after it
+        rows.push(start + row + u16::from(column > 0 || row == 0));
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(editor: &mut LineEditor, code: KeyCode, modifiers: KeyModifiers) -> Action {
+        editor.apply(KeyEvent::new(code, modifiers))
+    }
+
+    fn typed(text: &str) -> LineEditor {
+        let mut editor = LineEditor::new(SubmitKey::Enter, Keymap::Emacs);
+        for c in text.chars() {
+            match c {
+                '\n' => press(&mut editor, KeyCode::Enter, KeyModifiers::ALT),
+                c => press(&mut editor, KeyCode::Char(c), KeyModifiers::NONE),
+            };
+        }
+        editor
+    }
+
+    #[test]
+    fn test_graphemes_move_and_delete_whole() {
+        // "e" + combining acute, then a family emoji made of several code points
+        let mut editor = typed("cafe\u{301} 👨‍👩‍👧!");
+        press(&mut editor, KeyCode::Left, KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Backspace, KeyModifiers::NONE);
+        assert_eq!(editor.buffer, "cafe\u{301} !");
+
+        press(&mut editor, KeyCode::Left, KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Backspace, KeyModifiers::NONE);
+        assert_eq!(editor.buffer, "caf !");
+    }
+
+    #[test]
+    fn test_kill_and_yank() {
+        let mut editor = typed("scan the host now");
+        press(&mut editor, KeyCode::Char('w'), KeyModifiers::CONTROL);
+        press(&mut editor, KeyCode::Char('w'), KeyModifiers::CONTROL);
+        assert_eq!(editor.buffer, "scan the ");
+        press(&mut editor, KeyCode::Char('a'), KeyModifiers::CONTROL);
+        press(&mut editor, KeyCode::Char('y'), KeyModifiers::CONTROL);
+        assert_eq!(editor.buffer, "host nowscan the ");
+
+        press(&mut editor, KeyCode::Home, KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Char('f'), KeyModifiers::ALT);
+        press(&mut editor, KeyCode::Char('k'), KeyModifiers::CONTROL);
+        assert_eq!(editor.buffer, "host");
+        assert_eq!(editor.killed, " nowscan the ");
+    }
+
+    #[test]
+    fn test_multiline_editing_and_submit_keys() {
+        let mut editor = typed("fn main() {\n}");
+        press(&mut editor, KeyCode::Up, KeyModifiers::NONE);
+        press(&mut editor, KeyCode::End, KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Char('j'), KeyModifiers::CONTROL);
+        assert_eq!(editor.buffer, "fn main() {\n\n}");
+        assert_eq!(editor.status().unwrap(), "[3 lines, Enter to send, Alt-Enter for a new line]");
+        assert_eq!(press(&mut editor, KeyCode::Enter, KeyModifiers::NONE), Action::Submit);
+
+        editor.submit_key = SubmitKey::AltEnter;
+        assert_eq!(press(&mut editor, KeyCode::Enter, KeyModifiers::NONE), Action::Redraw);
+        assert_eq!(press(&mut editor, KeyCode::Enter, KeyModifiers::ALT), Action::Submit);
+    }
+
+    #[test]
+    fn test_heredoc_block() {
+        let mut editor = typed("\"\"\" Review this:");
+        assert_eq!(press(&mut editor, KeyCode::Enter, KeyModifiers::NONE), Action::Redraw);
+        for c in "let x = 1;".chars() {
+            press(&mut editor, KeyCode::Char(c), KeyModifiers::NONE);
+        }
+        press(&mut editor, KeyCode::Enter, KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Char('"'), KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Char('"'), KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Char('"'), KeyModifiers::NONE);
+        assert_eq!(press(&mut editor, KeyCode::Enter, KeyModifiers::NONE), Action::Submit);
+        assert_eq!(strip_heredoc(&editor.buffer).unwrap(), "Review this:\nlet x = 1;");
+    }
+
+    #[test]
+    fn test_history_recall_and_search() {
+        let mut editor = typed("draft");
+        editor.set_history(vec!["scan 10.0.0.1".to_string(), "explain".to_string(), "scan 10.0.0.2".to_string()]);
+        press(&mut editor, KeyCode::Up, KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Up, KeyModifiers::NONE);
+        assert_eq!(editor.buffer, "explain");
+        press(&mut editor, KeyCode::Down, KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Down, KeyModifiers::NONE);
+        assert_eq!(editor.buffer, "draft");
+
+        press(&mut editor, KeyCode::Char('r'), KeyModifiers::CONTROL);
+        for c in "scan".chars() {
+            press(&mut editor, KeyCode::Char(c), KeyModifiers::NONE);
+        }
+        assert_eq!(editor.buffer, "scan 10.0.0.2");
+        press(&mut editor, KeyCode::Char('r'), KeyModifiers::CONTROL);
+        assert_eq!(editor.buffer, "scan 10.0.0.1");
+        press(&mut editor, KeyCode::Char('r'), KeyModifiers::CONTROL);
+        assert_eq!(editor.prompt("chat> "), "(failed reverse-i-search)'scan': ");
+        press(&mut editor, KeyCode::Esc, KeyModifiers::NONE);
+        assert_eq!(editor.buffer, "draft");
+
+        press(&mut editor, KeyCode::Char('r'), KeyModifiers::CONTROL);
+        press(&mut editor, KeyCode::Char('x'), KeyModifiers::NONE);
+        assert_eq!(press(&mut editor, KeyCode::Enter, KeyModifiers::NONE), Action::Submit);
+        assert_eq!(editor.buffer, "explain");
+    }
+
+    #[test]
+    fn test_ctrl_c_clears_then_interrupts() {
+        let mut editor = typed("half a thought");
+        assert_eq!(press(&mut editor, KeyCode::Char('c'), KeyModifiers::CONTROL), Action::Redraw);
+        assert_eq!(editor.buffer, "");
+        assert_eq!(press(&mut editor, KeyCode::Char('c'), KeyModifiers::CONTROL), Action::Interrupt);
+    }
+
+    #[test]
+    fn test_tab_completes_then_cycles() {
+        let mut editor = typed("mo");
+        editor.set_completer(Completer::default());
+        press(&mut editor, KeyCode::Tab, KeyModifiers::NONE);
+        assert_eq!(editor.buffer, "model ");
+        press(&mut editor, KeyCode::Char('c'), KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Tab, KeyModifiers::NONE);
+        assert_eq!(editor.buffer, "model claude-3-");
+        press(&mut editor, KeyCode::Tab, KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Tab, KeyModifiers::NONE);
+        assert_eq!(editor.buffer, "model claude-3-opus");
+        press(&mut editor, KeyCode::BackTab, KeyModifiers::NONE);
+        assert_eq!(editor.buffer, "model claude-3-haiku");
+        assert_eq!(common_prefix(&["日本語".to_string(), "日本".to_string()]), "日本");
+    }
+
+    #[test]
+    fn test_layout_wraps_wide_characters_and_lines() {
+        assert_eq!(position("chat> hi", 80), (0, 8));
+        assert_eq!(position("12345", 5), (1, 0));
+        // A two-column character does not fit in the last column
+        assert_eq!(position("1234日本", 5), (1, 4));
+        assert_eq!(position("e\u{301}", 5), (0, 1));
+
+        let editor = typed("0123456789\nab");
+        let (lines, line, column) = editor.display_lines("chat> ");
+        assert_eq!(lines, ["chat> 0123456789", "   2| ab"]);
+        assert_eq!((line, column), (1, 8));
+        assert_eq!(line_rows(&lines, 8), [0, 2, 3]);
+        assert_eq!(editor.cursor_position("chat> ", 8), (3, 0));
+    }
+}
@@ -0,0 +1,842 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::collections::HashMap;
+use std::ops::Range;
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::{line_end, line_start, next_boundary, previous_boundary, vertical, Action};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViMode {
+    Normal,
+    Insert,
+    Visual,
+}
+
+impl ViMode {
+    /// Shown before the prompt.
+    pub fn tag(self) -> &'static str {
+        match self {
+            ViMode::Normal => "[N]",
+            ViMode::Insert => "[I]",
+            ViMode::Visual => "[V]",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Register {
+    text: String,
+    /// Holds whole lines, which paste as lines of their own
+    linewise: bool,
+}
+
+/// A command typed in Normal or Visual mode, with its register and count.
+#[derive(Debug, PartialEq)]
+struct Parsed {
+    register: Option<char>,
+    count: Option<usize>,
+    command: Command,
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Move(Motion),
+    /// `d`, `c` or `y`, with the count typed between it and its target
+    Operate(char, Option<usize>, Target),
+    /// `r` and the character to put under the cursor
+    Replace(char),
+    /// Any other single key: `x`, `p`, `u`, `i`, `v` and so on
+    Key(char),
+    /// `i` or `a` and an object in Visual mode
+    Select(Object),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Target {
+    Motion(Motion),
+    Object(Object),
+    /// The operator doubled, as in `dd`
+    Line,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Motion {
+    /// Single-key motions: `h l j k w W b B e E 0 ^ $ G ; ,`
+    Key(char),
+    /// `gg`
+    Top,
+    /// `f`, `F`, `t` or `T` and the character to find
+    Find(char, char),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Object {
+    /// `a` rather than `i`: include the surrounding space or delimiters
+    around: bool,
+    kind: char,
+}
+
+/// How much of the text between the cursor and a motion's target an
+/// operator covers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Exclusive,
+    Inclusive,
+    Linewise,
+}
+
+enum Parse<T> {
+    Incomplete,
+    Invalid,
+    Done(T),
+}
+
+/// Buffer and cursor before a change, for undo and redo.
+type Snapshot = (String, usize);
+
+/// Vi modes over the line editor's buffer.
+pub struct Vi {
+    pub mode: ViMode,
+    /// Keys of a command still being typed, such as `"a2d`
+    pending: Vec<char>,
+    registers: HashMap<char, Register>,
+    undo: Vec<Snapshot>,
+    redo: Vec<Snapshot>,
+    /// The last `f`, `F`, `t` or `T` and its character, for `;` and `,`
+    last_find: Option<(char, char)>,
+    /// Where Visual mode started; the selection runs from here to the cursor
+    anchor: usize,
+}
+
+impl Vi {
+    pub fn new() -> Self {
+        Self {
+            mode: ViMode::Insert,
+            pending: Vec::new(),
+            registers: HashMap::new(),
+            undo: Vec::new(),
+            redo: Vec::new(),
+            last_find: None,
+            anchor: 0,
+        }
+    }
+
+    /// Start over for a new message. Registers are kept.
+    pub fn reset(&mut self) {
+        self.mode = ViMode::Insert;
+        self.pending.clear();
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    /// The selected bytes in Visual mode.
+    pub fn selection(&self, text: &str, cursor: usize) -> Option<Range<usize>> {
+        if self.mode != ViMode::Visual {
+            return None;
+        }
+        let (start, end) = (self.anchor.min(cursor), self.anchor.max(cursor));
+        Some(start..next_boundary(text, end))
+    }
+
+    /// Handle `key`, or return `None` to let the line editor insert or edit
+    /// as usual, as it does for most keys in Insert mode.
+    pub(super) fn apply(&mut self, key: KeyEvent, text: &mut String, cursor: &mut usize) -> Option<Action> {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+
+        if self.mode == ViMode::Insert {
+            if key.code != KeyCode::Esc {
+                return None;
+            }
+            self.mode = ViMode::Normal;
+            // Entering and leaving Insert mode without typing is not a change
+            if self.undo.last().is_some_and(|(before, _)| before == text) {
+                self.undo.pop();
+            }
+            *cursor = previous_boundary(text, *cursor).max(line_start(text, *cursor));
+            return Some(Action::Redraw);
+        }
+
+        let key = match key.code {
+            KeyCode::Enter => return None,
+            KeyCode::Esc => {
+                self.pending.clear();
+                self.mode = ViMode::Normal;
+                return Some(Action::Redraw);
+            }
+            KeyCode::Char('r') if ctrl => {
+                self.pending.clear();
+                self.restore(true, text, cursor);
+                return Some(Action::Redraw);
+            }
+            KeyCode::Left | KeyCode::Backspace => 'h',
+            KeyCode::Right => 'l',
+            KeyCode::Up => 'k',
+            KeyCode::Down => 'j',
+            KeyCode::Home => '0',
+            KeyCode::End => '$',
+            KeyCode::Delete => 'x',
+            KeyCode::Char(c) if !ctrl && !alt => c,
+            _ => return Some(Action::Ignore),
+        };
+
+        self.pending.push(key);
+        match parse(&self.pending, self.mode == ViMode::Visual) {
+            Parse::Incomplete => Some(Action::Ignore),
+            Parse::Invalid => {
+                self.pending.clear();
+                Some(Action::Ignore)
+            }
+            Parse::Done(parsed) => {
+                self.pending.clear();
+                let action = self.execute(parsed, text, cursor);
+                if self.mode != ViMode::Insert && *cursor > line_start(text, *cursor) && *cursor == line_end(text, *cursor) {
+                    // Outside Insert mode the cursor sits on a character
+                    *cursor = previous_boundary(text, *cursor);
+                }
+                Some(action)
+            }
+        }
+    }
+
+    fn execute(&mut self, parsed: Parsed, text: &mut String, cursor: &mut usize) -> Action {
+        let count = parsed.count.unwrap_or(1);
+        let register = parsed.register;
+
+        match parsed.command {
+            Command::Move(motion) => {
+                if let Some((target, _)) = self.motion(motion, count, text, *cursor) {
+                    *cursor = target;
+                }
+            }
+            Command::Operate(operator, inner, target) => {
+                let count = count * inner.unwrap_or(1);
+                if let Some((range, linewise)) = self.target(operator, target, count, text, *cursor) {
+                    self.operate(operator, range, linewise, register, text, cursor);
+                }
+            }
+            Command::Select(object) => {
+                if let Some(range) = text_object(text, *cursor, object) {
+                    self.anchor = range.start;
+                    *cursor = previous_boundary(text, range.end).max(range.start);
+                }
+            }
+            Command::Replace(c) => {
+                let end = (0..count).fold(*cursor, |p, _| next_boundary(text, p));
+                if end > *cursor && end <= line_end(text, *cursor) {
+                    self.snapshot(text, *cursor);
+                    text.replace_range(*cursor..end, &c.to_string().repeat(count));
+                    *cursor += c.len_utf8() * (count - 1);
+                }
+            }
+            Command::Key(key) if self.mode == ViMode::Visual => {
+                let selection = self.selection(text, *cursor).unwrap_or(*cursor..*cursor);
+                match key {
+                    'd' | 'x' => self.operate('d', selection, false, register, text, cursor),
+                    'c' | 's' => self.operate('c', selection, false, register, text, cursor),
+                    'y' => self.operate('y', selection, false, register, text, cursor),
+                    'o' => *cursor = std::mem::replace(&mut self.anchor, *cursor),
+                    'v' => {}
+                    ':' => {
+                        self.mode = ViMode::Normal;
+                        return Action::Escape;
+                    }
+                    _ => return Action::Ignore,
+                }
+                if self.mode == ViMode::Visual {
+                    self.mode = ViMode::Normal;
+                }
+            }
+            Command::Key(key) => return self.key(key, count, register, text, cursor),
+        }
+        Action::Redraw
+    }
+
+    /// Single-key commands in Normal mode.
+    fn key(&mut self, key: char, count: usize, register: Option<char>, text: &mut String, cursor: &mut usize) -> Action {
+        let start = line_start(text, *cursor);
+        let end = line_end(text, *cursor);
+        let repeat = |from: usize, step: fn(&str, usize) -> usize| (0..count).fold(from, |p, _| step(text, p));
+
+        match key {
+            'x' => {
+                let range = *cursor..repeat(*cursor, next_boundary).min(end);
+                self.operate('d', range, false, register, text, cursor);
+            }
+            'X' => {
+                let range = repeat(*cursor, previous_boundary).max(start)..*cursor;
+                self.operate('d', range, false, register, text, cursor);
+            }
+            'D' => self.operate('d', *cursor..end, false, register, text, cursor),
+            'C' => self.operate('c', *cursor..end, false, register, text, cursor),
+            's' => {
+                let range = *cursor..repeat(*cursor, next_boundary).min(end);
+                self.operate('c', range, false, register, text, cursor);
+            }
+            'S' => self.operate('c', start..end, true, register, text, cursor),
+            'p' | 'P' => self.paste(key == 'p', count, register, text, cursor),
+            'u' => (0..count).for_each(|_| self.restore(false, text, cursor)),
+            '~' => {
+                let range = *cursor..repeat(*cursor, next_boundary).min(end);
+                if !range.is_empty() {
+                    self.snapshot(text, *cursor);
+                    let toggled: String = text[range.clone()].chars()
+                        .map(|c| if c.is_uppercase() { c.to_lowercase().to_string() } else { c.to_uppercase().to_string() })
+                        .collect();
+                    text.replace_range(range.clone(), &toggled);
+                    *cursor = range.start + toggled.len();
+                }
+            }
+            'i' | 'a' | 'I' | 'A' | 'o' | 'O' => {
+                self.snapshot(text, *cursor);
+                match key {
+                    'a' => *cursor = next_boundary(text, *cursor).min(end),
+                    'I' => *cursor = first_non_blank(text, start),
+                    'A' => *cursor = end,
+                    'o' => {
+                        text.insert(end, '\n');
+                        *cursor = end + 1;
+                    }
+                    'O' => {
+                        text.insert(start, '\n');
+                        *cursor = start;
+                    }
+                    _ => {}
+                }
+                self.mode = ViMode::Insert;
+            }
+            'v' => {
+                self.anchor = *cursor;
+                self.mode = ViMode::Visual;
+            }
+            ':' => return Action::Escape,
+            _ => return Action::Ignore,
+        }
+        Action::Redraw
+    }
+
+    /// Where `motion` goes from `from`, and how an operator treats it.
+    fn motion(&mut self, motion: Motion, count: usize, text: &str, from: usize) -> Option<(usize, Kind)> {
+        let (key, kind) = match motion {
+            Motion::Top => return Some((0, Kind::Linewise)),
+            Motion::Find(kind, target) => {
+                self.last_find = Some((kind, target));
+                return find(text, from, kind, target, count);
+            }
+            Motion::Key(';' | ',') => {
+                let (kind, target) = self.last_find?;
+                let kind = match (motion, kind) {
+                    (Motion::Key(','), 'f') => 'F',
+                    (Motion::Key(','), 'F') => 'f',
+                    (Motion::Key(','), 't') => 'T',
+                    (Motion::Key(','), 'T') => 't',
+                    _ => kind,
+                };
+                return find(text, from, kind, target, count);
+            }
+            Motion::Key(key) => (key, match key {
+                'e' | 'E' | '$' => Kind::Inclusive,
+                'j' | 'k' | 'G' => Kind::Linewise,
+                _ => Kind::Exclusive,
+            }),
+        };
+
+        let start = line_start(text, from);
+        let end = line_end(text, from);
+        let mut position = from;
+        for _ in 0..count {
+            let next = match key {
+                'h' => previous_boundary(text, position).max(start),
+                'l' => next_boundary(text, position).min(end),
+                'j' => vertical(text, position, 1),
+                'k' => vertical(text, position, -1),
+                'w' | 'W' => word_forward(text, position, key == 'W')?,
+                'b' | 'B' => word_backward(text, position, key == 'B')?,
+                'e' | 'E' => word_end(text, position, key == 'E')?,
+                '0' => start,
+                '^' => first_non_blank(text, start),
+                '$' => previous_boundary(text, end).max(start),
+                'G' => line_start(text, text.len()),
+                _ => return None,
+            };
+            if next == position {
+                break;
+            }
+            position = next;
+        }
+        (position != from || matches!(key, '0' | '^' | '$' | 'G')).then_some((position, kind))
+    }
+
+    /// The text an operator applies to, and whether it is whole lines.
+    fn target(&mut self, operator: char, target: Target, count: usize, text: &str, cursor: usize) -> Option<(Range<usize>, bool)> {
+        match target {
+            Target::Line => {
+                let last = (1..count).fold(cursor, |p, _| vertical(text, p, 1));
+                Some((line_start(text, cursor)..line_end(text, last), true))
+            }
+            Target::Object(object) => text_object(text, cursor, object).map(|range| (range, false)),
+            Target::Motion(Motion::Key(key @ ('w' | 'W'))) => {
+                let big = key == 'W';
+                let on_word = text[cursor..].graphemes(true).next().is_some_and(|g| class(g, big) != 0);
+                let end = if operator == 'c' && on_word {
+                    // `cw` changes to the end of the word, leaving the space after it
+                    (0..count).try_fold(cursor, |p, i| {
+                        let p = if i == 0 { p } else { word_forward(text, p, big)? };
+                        Some(run_end(text, p, big))
+                    })?
+                } else {
+                    let end = self.motion(Motion::Key(key), count, text, cursor)?.0;
+                    // A word motion stops at the end of the line it started on
+                    if text[cursor..end].contains('\n') && line_end(text, cursor) > cursor {
+                        line_end(text, cursor)
+                    } else {
+                        end
+                    }
+                };
+                Some((cursor..end, false))
+            }
+            Target::Motion(motion) => {
+                let (position, kind) = self.motion(motion, count, text, cursor)?;
+                let (start, end) = (cursor.min(position), cursor.max(position));
+                Some(match kind {
+                    Kind::Exclusive => (start..end, false),
+                    Kind::Inclusive => (start..next_boundary(text, end), false),
+                    Kind::Linewise => (line_start(text, start)..line_end(text, end), true),
+                })
+            }
+        }
+    }
+
+    fn operate(&mut self, operator: char, range: Range<usize>, linewise: bool, register: Option<char>, text: &mut String, cursor: &mut usize) {
+        self.store(register, Register {
+            text: text[range.clone()].to_string(),
+            linewise,
+        });
+
+        match operator {
+            'y' => *cursor = range.start,
+            'c' => {
+                // Changing lines keeps them, emptied
+                self.snapshot(text, *cursor);
+                text.replace_range(range.clone(), "");
+                *cursor = range.start;
+                self.mode = ViMode::Insert;
+            }
+            _ => {
+                let range = if !linewise {
+                    range
+                } else if range.end < text.len() {
+                    range.start..range.end + 1
+                } else {
+                    range.start.saturating_sub(1)..range.end
+                };
+                self.snapshot(text, *cursor);
+                text.replace_range(range.clone(), "");
+                *cursor = range.start.min(text.len());
+                if linewise {
+                    *cursor = first_non_blank(text, line_start(text, *cursor));
+                }
+            }
+        }
+    }
+
+    /// Put `register`, or the unnamed one, after (`p`) or before (`P`) the cursor.
+    fn paste(&mut self, after: bool, count: usize, register: Option<char>, text: &mut String, cursor: &mut usize) {
+        let Some(register) = self.registers.get(&register.unwrap_or('"')).cloned() else {
+            return;
+        };
+        self.snapshot(text, *cursor);
+
+        if register.linewise {
+            let lines = vec![register.text.as_str(); count].join("\n");
+            if after {
+                let end = line_end(text, *cursor);
+                text.insert_str(end, &format!("\n{}", lines));
+                *cursor = end + 1;
+            } else {
+                let start = line_start(text, *cursor);
+                text.insert_str(start, &format!("{}\n", lines));
+                *cursor = start;
+            }
+        } else {
+            let pasted = register.text.repeat(count);
+            let at = if after { next_boundary(text, *cursor).min(line_end(text, *cursor).max(*cursor)) } else { *cursor };
+            text.insert_str(at, &pasted);
+            *cursor = previous_boundary(text, at + pasted.len()).max(at);
+        }
+    }
+
+    /// Put `text` in a register as whole lines, as `yy` would.
+    pub fn yank(&mut self, name: Option<char>, text: &str) {
+        self.store(name, Register { text: text.to_string(), linewise: true });
+    }
+
+    /// Save text to a named register (uppercase appends) and the unnamed one.
+    fn store(&mut self, name: Option<char>, register: Register) {
+        let register = match name {
+            Some('_') => return,
+            Some(name) if name.is_ascii_uppercase() => {
+                let existing = self.registers.entry(name.to_ascii_lowercase()).or_default();
+                if register.linewise && !existing.text.is_empty() {
+                    existing.text.push('\n');
+                }
+                existing.text.push_str(&register.text);
+                existing.linewise |= register.linewise;
+                existing.clone()
+            }
+            Some(name) if name != '"' => {
+                self.registers.insert(name, register.clone());
+                register
+            }
+            _ => register,
+        };
+        self.registers.insert('"', register);
+    }
+
+    fn snapshot(&mut self, text: &str, cursor: usize) {
+        self.undo.push((text.to_string(), cursor));
+        self.redo.clear();
+    }
+
+    /// Undo, or with `redo` redo, the last change.
+    fn restore(&mut self, redo: bool, text: &mut String, cursor: &mut usize) {
+        let (from, to) = if redo { (&mut self.redo, &mut self.undo) } else { (&mut self.undo, &mut self.redo) };
+        if let Some((previous, position)) = from.pop() {
+            to.push((std::mem::replace(text, previous), *cursor));
+            *cursor = position.min(text.len());
+        }
+    }
+}
+
+fn parse(keys: &[char], visual: bool) -> Parse<Parsed> {
+    let mut rest = keys;
+    let mut register = None;
+    if let Some(('"', tail)) = rest.split_first().map(|(c, tail)| (*c, tail)) {
+        let Some((&name, tail)) = tail.split_first() else {
+            return Parse::Incomplete;
+        };
+        register = Some(name);
+        rest = tail;
+    }
+    let (count, rest) = parse_count(rest);
+    let Some((&key, rest)) = rest.split_first() else {
+        return Parse::Incomplete;
+    };
+
+    let command = match key {
+        'd' | 'c' | 'y' if !visual => {
+            let (inner, rest) = parse_count(rest);
+            let Some((&next, rest)) = rest.split_first() else {
+                return Parse::Incomplete;
+            };
+            let target = if next == key {
+                Target::Line
+            } else if next == 'i' || next == 'a' {
+                match parse_object(next, rest) {
+                    Parse::Done(object) => Target::Object(object),
+                    Parse::Incomplete => return Parse::Incomplete,
+                    Parse::Invalid => return Parse::Invalid,
+                }
+            } else {
+                match parse_motion(next, rest) {
+                    Parse::Done(motion) => Target::Motion(motion),
+                    Parse::Incomplete => return Parse::Incomplete,
+                    Parse::Invalid => return Parse::Invalid,
+                }
+            };
+            Command::Operate(key, inner, target)
+        }
+        'i' | 'a' if visual => match parse_object(key, rest) {
+            Parse::Done(object) => Command::Select(object),
+            Parse::Incomplete => return Parse::Incomplete,
+            Parse::Invalid => return Parse::Invalid,
+        },
+        'r' => match rest.first() {
+            Some(&c) => Command::Replace(c),
+            None => return Parse::Incomplete,
+        },
+        'd' | 'c' | 'y' | 'x' | 'X' | 'D' | 'C' | 's' | 'S' | 'p' | 'P' | 'u' | '~'
+        | 'i' | 'a' | 'I' | 'A' | 'o' | 'O' | 'v' | ':' => Command::Key(key),
+        _ => match parse_motion(key, rest) {
+            Parse::Done(motion) => Command::Move(motion),
+            Parse::Incomplete => return Parse::Incomplete,
+            Parse::Invalid => return Parse::Invalid,
+        },
+    };
+    Parse::Done(Parsed { register, count, command })
+}
+
+/// A count, which cannot start with 0 since that is a motion.
+fn parse_count(keys: &[char]) -> (Option<usize>, &[char]) {
+    let digits = match keys.first() {
+        Some('1'..='9') => keys.iter().take_while(|c| c.is_ascii_digit()).count(),
+        _ => 0,
+    };
+    let count = keys[..digits].iter().collect::<String>().parse().ok();
+    (count, &keys[digits..])
+}
+
+fn parse_motion(key: char, rest: &[char]) -> Parse<Motion> {
+    match (key, rest.first()) {
+        ('h' | 'l' | 'j' | 'k' | 'w' | 'W' | 'b' | 'B' | 'e' | 'E' | '0' | '^' | '$' | 'G' | ';' | ',', _) => {
+            Parse::Done(Motion::Key(key))
+        }
+        ('g' | 'f' | 'F' | 't' | 'T', None) => Parse::Incomplete,
+        ('g', Some('g')) => Parse::Done(Motion::Top),
+        ('f' | 'F' | 't' | 'T', Some(&target)) => Parse::Done(Motion::Find(key, target)),
+        _ => Parse::Invalid,
+    }
+}
+
+fn parse_object(key: char, rest: &[char]) -> Parse<Object> {
+    match rest.first() {
+        None => Parse::Incomplete,
+        Some(&kind) if "wW\"'`()b[]{}B<>".contains(kind) => Parse::Done(Object { around: key == 'a', kind }),
+        Some(_) => Parse::Invalid,
+    }
+}
+
+/// 0 for space, 1 for word characters (or any non-space for WORDs), 2 for punctuation.
+fn class(grapheme: &str, big: bool) -> u8 {
+    match grapheme.chars().next() {
+        Some(c) if c.is_whitespace() => 0,
+        Some(c) if big || c.is_alphanumeric() || c == '_' => 1,
+        _ => 2,
+    }
+}
+
+fn graphemes(text: &str) -> Vec<(usize, &str)> {
+    text.grapheme_indices(true).collect()
+}
+
+/// Index in `graphemes` of the one starting at byte `position`.
+fn index(graphemes: &[(usize, &str)], position: usize) -> usize {
+    graphemes.partition_point(|(start, _)| *start < position)
+}
+
+fn byte(text: &str, graphemes: &[(usize, &str)], index: usize) -> usize {
+    graphemes.get(index).map_or(text.len(), |(start, _)| *start)
+}
+
+fn word_forward(text: &str, position: usize, big: bool) -> Option<usize> {
+    let g = graphemes(text);
+    let mut i = index(&g, position);
+    let start_class = class(g.get(i)?.1, big);
+    while start_class != 0 && i < g.len() && class(g[i].1, big) == start_class {
+        i += 1;
+    }
+    while i < g.len() && class(g[i].1, big) == 0 {
+        i += 1;
+    }
+    Some(byte(text, &g, i))
+}
+
+fn word_backward(text: &str, position: usize, big: bool) -> Option<usize> {
+    let g = graphemes(text);
+    let mut i = index(&g, position).checked_sub(1)?;
+    while i > 0 && class(g[i].1, big) == 0 {
+        i -= 1;
+    }
+    let word_class = class(g[i].1, big);
+    while i > 0 && class(g[i - 1].1, big) == word_class {
+        i -= 1;
+    }
+    Some(byte(text, &g, i))
+}
+
+fn word_end(text: &str, position: usize, big: bool) -> Option<usize> {
+    let g = graphemes(text);
+    let mut i = index(&g, position) + 1;
+    while i < g.len() && class(g[i].1, big) == 0 {
+        i += 1;
+    }
+    let word_class = class(g.get(i)?.1, big);
+    while i + 1 < g.len() && class(g[i + 1].1, big) == word_class {
+        i += 1;
+    }
+    Some(byte(text, &g, i))
+}
+
+/// End of the run of same-class characters starting at `position`.
+fn run_end(text: &str, position: usize, big: bool) -> usize {
+    let g = graphemes(text);
+    let mut i = index(&g, position);
+    let run_class = g.get(i).map_or(0, |(_, s)| class(s, big));
+    while i < g.len() && class(g[i].1, big) == run_class && g[i].1 != "\n" {
+        i += 1;
+    }
+    byte(text, &g, i)
+}
+
+fn first_non_blank(text: &str, start: usize) -> usize {
+    let end = line_end(text, start);
+    text[start..end].find(|c: char| !c.is_whitespace()).map_or(end, |i| start + i)
+}
+
+/// `f`/`t` forward and `F`/`T` backward to the `count`th `target` on the line.
+fn find(text: &str, from: usize, kind: char, target: char, count: usize) -> Option<(usize, Kind)> {
+    let start = line_start(text, from);
+    let end = line_end(text, from);
+    match kind {
+        'f' | 't' => {
+            let after = next_boundary(text, from);
+            let (found, _) = text[after..end].match_indices(target).nth(count - 1)?;
+            let found = after + found;
+            let position = if kind == 't' { previous_boundary(text, found) } else { found };
+            Some((position, Kind::Inclusive))
+        }
+        _ => {
+            let (found, _) = text[start..from].rmatch_indices(target).nth(count - 1)?;
+            let found = start + found;
+            let position = if kind == 'T' { next_boundary(text, found) } else { found };
+            Some((position, Kind::Exclusive))
+        }
+    }
+}
+
+/// The range of a text object around `cursor`: a word, quoted string or
+/// bracketed block, with or without its surroundings.
+fn text_object(text: &str, cursor: usize, object: Object) -> Option<Range<usize>> {
+    match object.kind {
+        'w' | 'W' => {
+            let big = object.kind == 'W';
+            let g = graphemes(text);
+            let i = index(&g, cursor);
+            let word_class = class(g.get(i)?.1, big);
+            let mut start = i;
+            while start > 0 && class(g[start - 1].1, big) == word_class && g[start - 1].1 != "\n" {
+                start -= 1;
+            }
+            let start = byte(text, &g, start);
+            let end = run_end(text, start, big);
+            if !object.around {
+                return Some(start..end);
+            }
+            // The space after the word, or failing that the space before it
+            let line_end = line_end(text, end);
+            let trailing = text[end..line_end].len() - text[end..line_end].trim_start().len();
+            if trailing > 0 {
+                return Some(start..end + trailing);
+            }
+            let line_start = line_start(text, start);
+            let leading = text[line_start..start].len() - text[line_start..start].trim_end().len();
+            Some(start - leading..end)
+        }
+        quote @ ('"' | '\'' | '`') => {
+            let start = line_start(text, cursor);
+            let end = line_end(text, cursor);
+            let quotes: Vec<usize> = text[start..end].match_indices(quote).map(|(i, _)| start + i).collect();
+            let (open, close) = quotes.chunks_exact(2)
+                .map(|pair| (pair[0], pair[1]))
+                .find(|&(_, close)| cursor <= close)?;
+            Some(if object.around { open..close + 1 } else { open + 1..close })
+        }
+        kind => {
+            let (open, close) = match kind {
+                '(' | ')' | 'b' => ('(', ')'),
+                '[' | ']' => ('[', ']'),
+                '{' | '}' | 'B' => ('{', '}'),
+                _ => ('<', '>'),
+            };
+            // Scan back for the unmatched opening bracket, then forward for its match
+            let mut depth = 0;
+            let mut opened = None;
+            for (i, c) in text[..next_boundary(text, cursor)].char_indices().rev() {
+                if c == close && i != cursor {
+                    depth += 1;
+                } else if c == open {
+                    if depth == 0 {
+                        opened = Some(i);
+                        break;
+                    }
+                    depth -= 1;
+                }
+            }
+            let opened = opened?;
+            let mut depth = 0;
+            let mut closed = None;
+            for (i, c) in text[opened + 1..].char_indices() {
+                if c == open {
+                    depth += 1;
+                } else if c == close {
+                    if depth == 0 {
+                        closed = Some(opened + 1 + i);
+                        break;
+                    }
+                    depth -= 1;
+                }
+            }
+            let closed = closed?;
+            Some(if object.around { opened..closed + 1 } else { opened + 1..closed })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run Normal-mode `keys` on `text` with the cursor at byte `cursor`.
+    fn normal(text: &str, cursor: usize, keys: &str) -> (Vi, String, usize) {
+        let mut vi = Vi::new();
+        vi.mode = ViMode::Normal;
+        let mut text = text.to_string();
+        let mut cursor = cursor;
+        for c in keys.chars() {
+            let key = match c {
+                '\x1b' => KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE),
+                '\x12' => KeyEvent::new(KeyCode::Char('r'), KeyModifiers::CONTROL),
+                c => KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE),
+            };
+            if vi.apply(key, &mut text, &mut cursor).is_none() {
+                text.insert(cursor, c);
+                cursor += c.len_utf8();
+            }
+        }
+        (vi, text, cursor)
+    }
+
+    #[test]
+    fn test_motions_and_operators_with_counts() {
+        let (_, text, cursor) = normal("scan the host now", 0, "2w");
+        assert_eq!((text.as_str(), cursor), ("scan the host now", 9));
+
+        assert_eq!(normal("scan the host now", 0, "d2w").1, "host now");
+        assert_eq!(normal("scan the host now", 0, "2dw").1, "host now");
+        assert_eq!(normal("scan the host now", 5, "cwa\x1b").1, "scan a host now");
+        assert_eq!(normal("scan the host now", 0, "dt ").1, " the host now");
+        assert_eq!(normal("scan the host now", 0, "df ").1, "the host now");
+        assert_eq!(normal("scan the host now", 6, "d$").1, "scan t");
+        assert_eq!(normal("one\ntwo\nthree", 4, "dd").1, "one\nthree");
+        assert_eq!(normal("one\ntwo\nthree", 0, "dj").1, "three");
+        assert_eq!(normal("a.b(c)", 0, "de").1, "b(c)");
+    }
+
+    #[test]
+    fn test_text_objects() {
+        assert_eq!(normal("scan the host", 6, "diw").1, "scan  host");
+        assert_eq!(normal("scan the host", 6, "daw").1, "scan host");
+        assert_eq!(normal("say \"hi there\" now", 7, "ci\"yo\x1b").1, "say \"yo\" now");
+        assert_eq!(normal("f(a, (b), c)", 3, "di(").1, "f()");
+        assert_eq!(normal("f(a, (b), c)", 6, "da(").1, "f(a, , c)");
+    }
+
+    #[test]
+    fn test_registers_undo_and_visual() {
+        let (vi, text, _) = normal("alpha beta", 0, "\"ayw\"byiwwve\"Ay");
+        assert_eq!(vi.registers[&'a'].text, "alpha beta");
+        assert_eq!(vi.registers[&'b'].text, "alpha");
+        assert_eq!(text, "alpha beta");
+
+        let (_, text, _) = normal("alpha beta", 0, "yiw$p");
+        assert_eq!(text, "alpha betaalpha");
+
+        let (_, text, cursor) = normal("alpha beta", 0, "dwxuu");
+        assert_eq!((text.as_str(), cursor), ("alpha beta", 0));
+        let (_, text, _) = normal("alpha beta", 0, "dwxuu\x12");
+        assert_eq!(text, "beta");
+
+        let (_, text, _) = normal("one\ntwo", 0, "yyjp");
+        assert_eq!(text, "one\ntwo\none");
+    }
+}
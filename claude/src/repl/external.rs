@@ -0,0 +1,161 @@
+use anyhow::{bail, Context, Result};
+use std::fs::OpenOptions;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::terminal::run_interactive;
+
+/// Open `draft` in the user's editor and return what they saved, or `None`
+/// when they quit without saving or left the file empty.
+pub fn compose(draft: &str) -> Result<Option<String>> {
+    compose_with(draft, edit)
+}
+
+fn compose_with(draft: &str, edit: impl FnOnce(&Path) -> Result<()>) -> Result<Option<String>> {
+    let file = TempFile::create("message", draft)?;
+    let read = || std::fs::read_to_string(&file.0)
+        .with_context(|| format!("Could not read {}", file.0.display()));
+    let (written, original) = (modified(&file.0)?, read()?);
+    edit(&file.0)?;
+
+    // A save in the same tick of a coarse clock leaves the time as it was,
+    // so changed content counts as saved too
+    let text = read()?;
+    if modified(&file.0)? == written && text == original {
+        return Ok(None);
+    }
+    let text = text.trim_end();
+    Ok((!text.trim().is_empty()).then(|| text.to_string()))
+}
+
+/// Show `text` in the user's editor from a read-only file.
+pub fn view(name: &str, text: &str) -> Result<()> {
+    let file = TempFile::create(name, text)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&file.0, std::fs::Permissions::from_mode(0o400))?;
+    }
+    edit(&file.0)
+}
+
+/// Run `$VISUAL`, `$EDITOR` or `vi` on `path`. The variable goes through the
+/// shell, as git does, so it may hold arguments such as `code --wait`.
+fn edit(path: &Path) -> Result<()> {
+    if !io::stdin().is_terminal() {
+        bail!("The editor needs a terminal");
+    }
+    let editor = ["VISUAL", "EDITOR"].iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(format!("{} \"$@\"", editor)).arg(&editor).arg(path);
+    let status = run_interactive(&mut command)
+        .with_context(|| format!("Could not run {}", editor))?;
+    if !status.success() {
+        bail!("{} exited with {}", editor, status);
+    }
+    Ok(())
+}
+
+fn modified(path: &Path) -> Result<SystemTime> {
+    Ok(std::fs::metadata(path)?.modified()?)
+}
+
+/// A Markdown file in the temp directory, only readable by the user and
+/// removed when dropped.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn create(name: &str, text: &str) -> Result<Self> {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().subsec_nanos();
+        let path = std::env::temp_dir().join(format!("claude-{}-{}-{}.md", name, std::process::id(), nanos));
+
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&path)
+            .with_context(|| format!("Could not create {}", path.display()))?;
+        let file_path = Self(path);
+        file.write_all(text.as_bytes())?;
+        if !text.is_empty() && !text.ends_with('\n') {
+            file.write_all(b"\n")?;
+        }
+        Ok(file_path)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_temp_file_is_private_and_removed() -> Result<()> {
+        let file = TempFile::create("test", "draft")?;
+        let path = file.0.clone();
+        assert_eq!(std::fs::read_to_string(&path)?, "draft\n");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+        }
+        drop(file);
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    /// Stands in for an editor that saves `text`.
+    fn save(text: &'static str) -> impl FnOnce(&Path) -> Result<()> {
+        move |path| Ok(std::fs::write(path, text)?)
+    }
+
+    /// Saves `text` within the same tick of a coarse clock, leaving the
+    /// modification time as it was.
+    fn save_in_same_tick(text: &'static str) -> impl FnOnce(&Path) -> Result<()> {
+        move |path| {
+            let before = modified(path)?;
+            std::fs::write(path, text)?;
+            OpenOptions::new().write(true).open(path)?.set_modified(before)?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_compose_returns_the_saved_text() -> Result<()> {
+        assert_eq!(compose_with("draft", save("Scan the /24\n\n"))?, Some("Scan the /24".to_string()));
+        assert_eq!(compose_with("draft", save_in_same_tick("Scan the /24"))?, Some("Scan the /24".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_compose_is_cancelled_by_quitting_without_saving() -> Result<()> {
+        assert_eq!(compose_with("draft", |_| Ok(()))?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compose_is_cancelled_by_saving_an_empty_file() -> Result<()> {
+        assert_eq!(compose_with("draft", save(""))?, None);
+        assert_eq!(compose_with("", save(" \n\n"))?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compose_stops_when_the_editor_fails() {
+        assert!(compose_with("draft", |_| bail!("vi exited with exit status: 1")).is_err());
+    }
+}
@@ -0,0 +1,152 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Entries kept of each kind; older ones are dropped when the file is loaded.
+const MAX_ENTRIES: usize = 1000;
+
+/// Which prompt an entry was typed at. Each is recalled on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryKind {
+    Chat,
+    Command,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    kind: HistoryKind,
+    timestamp: DateTime<Utc>,
+    text: String,
+}
+
+/// Submitted prompts and commands, oldest first, one JSON object per line of
+/// the history file. New entries are appended; repeats are dropped from the
+/// file the next time it is loaded.
+pub struct History {
+    path: PathBuf,
+    entries: Vec<Entry>,
+}
+
+impl History {
+    /// An empty history that saves to `path`.
+    pub fn new(path: &Path) -> Self {
+        Self { path: path.to_path_buf(), entries: Vec::new() }
+    }
+
+    /// Read the history file, keeping the latest of any repeated entries. A
+    /// missing file is an empty history; unreadable lines are skipped.
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut history = Self::new(path);
+        if !path.exists() {
+            return Ok(history);
+        }
+
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read {}", path.display()))?;
+        let lines = content.lines().filter(|l| !l.trim().is_empty()).count();
+        for entry in content.lines().filter_map(|l| serde_json::from_str::<Entry>(l).ok()) {
+            history.push(entry);
+        }
+        for kind in [HistoryKind::Chat, HistoryKind::Command] {
+            let count = history.entries.iter().filter(|e| e.kind == kind).count();
+            let mut excess = count.saturating_sub(MAX_ENTRIES);
+            history.entries.retain(|e| {
+                let drop = e.kind == kind && excess > 0;
+                excess -= usize::from(drop);
+                !drop
+            });
+        }
+
+        if history.entries.len() < lines {
+            history.rewrite()?;
+        }
+        Ok(history)
+    }
+
+    /// Texts of one kind, oldest first.
+    pub fn recall(&self, kind: HistoryKind) -> Vec<String> {
+        self.entries.iter()
+            .filter(|e| e.kind == kind)
+            .map(|e| e.text.clone())
+            .collect()
+    }
+
+    /// Record `text` as the newest entry of its kind and append it to the file.
+    pub fn add(&mut self, kind: HistoryKind, text: &str) -> Result<()> {
+        if text.trim().is_empty() {
+            return Ok(());
+        }
+        let entry = Entry { kind, timestamp: Utc::now(), text: text.to_string() };
+        let line = serde_json::to_string(&entry)?;
+        self.push(entry);
+
+        let mut file = open(&self.path, false)?;
+        writeln!(file, "{}", line)?;
+        Ok(())
+    }
+
+    fn push(&mut self, entry: Entry) {
+        self.entries.retain(|e| e.kind != entry.kind || e.text != entry.text);
+        self.entries.push(entry);
+    }
+
+    fn rewrite(&self) -> Result<()> {
+        let mut file = open(&self.path, true)?;
+        for entry in &self.entries {
+            writeln!(file, "{}", serde_json::to_string(entry)?)?;
+        }
+        Ok(())
+    }
+}
+
+/// Open the history file for appending or rewriting. It can hold anything
+/// typed at the prompt, so only the user may read it.
+fn open(path: &Path, truncate: bool) -> Result<std::fs::File> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut options = OpenOptions::new();
+    options.create(true).write(true);
+    if truncate {
+        options.truncate(true);
+    } else {
+        options.append(true);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path).with_context(|| format!("Could not write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kinds_are_separate_and_repeats_dropped() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("history.jsonl");
+
+        let mut history = History::load(&path)?;
+        history.add(HistoryKind::Chat, "scan the host")?;
+        history.add(HistoryKind::Command, "model claude-3-opus")?;
+        history.add(HistoryKind::Chat, "explain the output")?;
+        history.add(HistoryKind::Chat, "scan the host")?;
+        history.add(HistoryKind::Chat, "  ")?;
+        assert_eq!(history.recall(HistoryKind::Chat), ["explain the output", "scan the host"]);
+        assert_eq!(std::fs::read_to_string(&path)?.lines().count(), 4);
+
+        let reloaded = History::load(&path)?;
+        assert_eq!(reloaded.recall(HistoryKind::Chat), ["explain the output", "scan the host"]);
+        assert_eq!(reloaded.recall(HistoryKind::Command), ["model claude-3-opus"]);
+        // The repeat was compacted away on load
+        assert_eq!(std::fs::read_to_string(&path)?.lines().count(), 3);
+        Ok(())
+    }
+}
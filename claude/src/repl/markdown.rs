@@ -0,0 +1,564 @@
+use anyhow::{bail, Result};
+use claude_common::config::DisplayConfig;
+use crossterm::style::{Attribute, Color, ContentStyle};
+use crossterm::terminal;
+use pulldown_cmark::{Alignment, CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+use std::io::{self, IsTerminal};
+use std::sync::OnceLock;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::parsing::SyntaxSet;
+use syntect::util::{as_24_bit_terminal_escaped, LinesWithEndings};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Narrowest text is wrapped to, however deeply it is indented.
+const MIN_WIDTH: usize = 20;
+
+const BULLETS: &[&str] = &["•", "◦", "▪"];
+
+/// Turns replies into styled terminal text, or leaves them as they are when
+/// colour is unwanted.
+pub struct Renderer {
+    /// Syntax theme, or `None` to print Markdown unchanged
+    theme: Option<Theme>,
+    /// Fixed width for tests; otherwise the terminal's at each render
+    width: Option<usize>,
+}
+
+impl Renderer {
+    /// Format replies unless `config` turns it off, `NO_COLOR` is set or
+    /// stdout is not a terminal. Fails on a theme syntect does not bundle.
+    pub fn new(config: &DisplayConfig) -> Result<Self> {
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        if !config.markdown || no_color || !io::stdout().is_terminal() {
+            return Ok(Self::plain());
+        }
+        Ok(Self { theme: Some(theme(&config.theme)?), width: None })
+    }
+
+    /// Print everything as it was written.
+    pub fn plain() -> Self {
+        Self { theme: None, width: None }
+    }
+
+    /// Styled text for `markdown`, without a trailing newline.
+    pub fn render(&self, markdown: &str) -> String {
+        let Some(theme) = &self.theme else {
+            return markdown.trim_end().to_string();
+        };
+        let width = self.width.unwrap_or_else(|| match terminal::size() {
+            Ok((0, _)) | Err(_) => 80,
+            Ok((columns, _)) => usize::from(columns),
+        });
+
+        let mut writer = Writer::new(theme, width);
+        let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+        for event in Parser::new_ext(markdown, options) {
+            writer.event(event);
+        }
+        writer.flush();
+        writer.out.trim_end().to_string()
+    }
+}
+
+fn theme(name: &str) -> Result<Theme> {
+    let mut themes = ThemeSet::load_defaults().themes;
+    if let Some(theme) = themes.remove(name) {
+        return Ok(theme);
+    }
+    let mut names: Vec<_> = themes.into_keys().collect();
+    names.sort();
+    bail!("Unknown theme '{}' (available: {})", name, names.join(", "))
+}
+
+/// A fenced code block from a reply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeBlock {
+    /// First word of the fence's info string, or empty
+    pub language: String,
+    pub code: String,
+}
+
+/// The fenced code blocks in `markdown`, in order.
+pub fn code_blocks(markdown: &str) -> Vec<CodeBlock> {
+    let mut blocks = Vec::new();
+    let mut current: Option<CodeBlock> = None;
+    for event in Parser::new_ext(markdown, Options::empty()) {
+        match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) => {
+                let language = info.split_whitespace().next().unwrap_or("").to_string();
+                current = Some(CodeBlock { language, code: String::new() });
+            }
+            Event::Text(text) => {
+                if let Some(block) = &mut current {
+                    block.code.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::CodeBlock) => blocks.extend(current.take()),
+            _ => {}
+        }
+    }
+    blocks
+}
+
+/// Grammars are only loaded once a reply has a code block.
+fn syntaxes() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+/// Something the lines of a block are indented inside.
+enum Container {
+    Quote,
+    /// A list item, with its marker until the first line shows it
+    Item { marker: Option<String>, width: usize },
+}
+
+#[derive(Default)]
+struct Word {
+    parts: Vec<(String, ContentStyle)>,
+    width: usize,
+}
+
+impl Word {
+    fn push(&mut self, c: char, style: ContentStyle) {
+        match self.parts.last_mut() {
+            Some((text, last)) if *last == style => text.push(c),
+            _ => self.parts.push((c.to_string(), style)),
+        }
+        self.width += c.width().unwrap_or(0);
+    }
+}
+
+struct Writer<'a> {
+    theme: &'a Theme,
+    width: usize,
+    out: String,
+    /// An empty line is due before the next block
+    spaced: bool,
+    /// Text of the paragraph, heading or table cell being read
+    inline: Vec<(String, ContentStyle)>,
+    containers: Vec<Container>,
+    /// Next number of each open list, `None` for bullets
+    lists: Vec<Option<u64>>,
+    strong: usize,
+    emphasis: usize,
+    strikethrough: usize,
+    heading: Option<HeadingLevel>,
+    /// Open links' targets and where their text starts in `inline`
+    links: Vec<(String, usize)>,
+    /// Language and text of the code block being read
+    code: Option<(String, String)>,
+    table: Option<Table>,
+}
+
+#[derive(Default)]
+struct Table {
+    alignments: Vec<Alignment>,
+    /// Styled text and width of each cell, header first
+    rows: Vec<Vec<(String, usize)>>,
+    row: Vec<(String, usize)>,
+}
+
+impl<'a> Writer<'a> {
+    fn new(theme: &'a Theme, width: usize) -> Self {
+        Self {
+            theme,
+            width,
+            out: String::new(),
+            spaced: false,
+            inline: Vec::new(),
+            containers: Vec::new(),
+            lists: Vec::new(),
+            strong: 0,
+            emphasis: 0,
+            strikethrough: 0,
+            heading: None,
+            links: Vec::new(),
+            code: None,
+            table: None,
+        }
+    }
+
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) => match &mut self.code {
+                Some((_, code)) => code.push_str(&text),
+                None => self.push(&text, self.style()),
+            },
+            Event::Code(text) => {
+                let style = ContentStyle { foreground_color: Some(Color::Yellow), ..self.style() };
+                self.push(&text, style);
+            }
+            Event::Html(text) | Event::InlineHtml(text) => self.push(&text, self.style()),
+            Event::SoftBreak => self.push(" ", self.style()),
+            Event::HardBreak => self.push("\n", self.style()),
+            Event::TaskListMarker(done) => self.push(if done { "[x] " } else { "[ ] " }, self.style()),
+            Event::Rule => {
+                self.flush();
+                let rule = "─".repeat(self.width.saturating_sub(self.indent()).max(MIN_WIDTH));
+                self.line(&dim(&rule));
+                self.blank();
+            }
+            _ => {}
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Heading { level, .. } => {
+                self.flush();
+                self.heading = Some(level);
+            }
+            Tag::BlockQuote(_) => {
+                self.flush();
+                self.space();
+                self.containers.push(Container::Quote);
+            }
+            Tag::CodeBlock(kind) => {
+                self.flush();
+                let language = match kind {
+                    CodeBlockKind::Fenced(info) => info.split_whitespace().next().unwrap_or("").to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                self.code = Some((language, String::new()));
+            }
+            Tag::List(start) => {
+                self.flush();
+                self.lists.push(start);
+            }
+            Tag::Item => {
+                self.flush();
+                let depth = self.lists.len().saturating_sub(1);
+                let marker = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    }
+                    _ => format!("{} ", BULLETS[depth % BULLETS.len()]),
+                };
+                let width = marker.width();
+                self.containers.push(Container::Item { marker: Some(marker), width });
+            }
+            Tag::Table(alignments) => {
+                self.flush();
+                self.table = Some(Table { alignments, ..Table::default() });
+            }
+            Tag::Emphasis => self.emphasis += 1,
+            Tag::Strong => self.strong += 1,
+            Tag::Strikethrough => self.strikethrough += 1,
+            Tag::Link { dest_url, .. } | Tag::Image { dest_url, .. } => {
+                self.links.push((dest_url.to_string(), self.inline.len()));
+            }
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        match tag {
+            TagEnd::Paragraph | TagEnd::HtmlBlock => {
+                self.flush();
+                self.blank();
+            }
+            TagEnd::Heading(_) => {
+                self.flush();
+                self.heading = None;
+                self.blank();
+            }
+            TagEnd::BlockQuote(_) => {
+                self.flush();
+                self.containers.pop();
+                self.blank();
+            }
+            TagEnd::CodeBlock => {
+                if let Some((language, code)) = self.code.take() {
+                    self.code_block(&language, &code);
+                }
+                self.blank();
+            }
+            TagEnd::List(_) => {
+                self.flush();
+                self.lists.pop();
+                if self.lists.is_empty() {
+                    self.blank();
+                }
+            }
+            TagEnd::Item => {
+                self.flush();
+                // An empty item still shows its marker
+                if matches!(self.containers.last(), Some(Container::Item { marker: Some(_), .. })) {
+                    self.line("");
+                }
+                self.containers.pop();
+            }
+            TagEnd::TableCell => {
+                let cell = std::mem::take(&mut self.inline);
+                let text = cell.iter().map(|(text, style)| style.apply(text.as_str()).to_string()).collect();
+                let width = cell.iter().map(|(text, _)| text.width()).sum();
+                if let Some(table) = &mut self.table {
+                    table.row.push((text, width));
+                }
+            }
+            TagEnd::TableHead | TagEnd::TableRow => {
+                if let Some(table) = &mut self.table {
+                    let row = std::mem::take(&mut table.row);
+                    table.rows.push(row);
+                }
+            }
+            TagEnd::Table => {
+                if let Some(table) = self.table.take() {
+                    self.write_table(table);
+                }
+                self.blank();
+            }
+            TagEnd::Emphasis => self.emphasis -= 1,
+            TagEnd::Strong => self.strong -= 1,
+            TagEnd::Strikethrough => self.strikethrough -= 1,
+            TagEnd::Link | TagEnd::Image => {
+                let Some((url, start)) = self.links.pop() else {
+                    return;
+                };
+                let text: String = self.inline[start..].iter().map(|(text, _)| text.as_str()).collect();
+                if text != url && !url.is_empty() {
+                    let style = ContentStyle { attributes: Attribute::Dim.into(), ..ContentStyle::new() };
+                    self.push(&format!(" ({})", url), style);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Style for text at this point, from the tags it is inside.
+    fn style(&self) -> ContentStyle {
+        let mut style = ContentStyle::new();
+        if self.strong > 0 || self.heading.is_some() {
+            style.attributes.set(Attribute::Bold);
+        }
+        if self.emphasis > 0 {
+            style.attributes.set(Attribute::Italic);
+        }
+        if self.strikethrough > 0 {
+            style.attributes.set(Attribute::CrossedOut);
+        }
+        if !self.links.is_empty() {
+            style.attributes.set(Attribute::Underlined);
+            style.foreground_color = Some(Color::Blue);
+        }
+        if let Some(level) = self.heading {
+            style.foreground_color = Some(Color::Magenta);
+            if level == HeadingLevel::H1 {
+                style.attributes.set(Attribute::Underlined);
+            }
+        }
+        style
+    }
+
+    fn push(&mut self, text: &str, style: ContentStyle) {
+        self.inline.push((text.to_string(), style));
+    }
+
+    /// Columns taken by quote bars and list indents.
+    fn indent(&self) -> usize {
+        self.containers.iter().map(|c| match c {
+            Container::Quote => 2,
+            Container::Item { width, .. } => *width,
+        }).sum()
+    }
+
+    /// Write one line inside the open containers, showing any list markers
+    /// not shown yet.
+    fn line(&mut self, content: &str) {
+        self.space();
+        for container in &mut self.containers {
+            match container {
+                Container::Quote => self.out.push_str(&dim("│ ")),
+                Container::Item { marker, width } => match marker.take() {
+                    Some(marker) => self.out.push_str(&marker),
+                    None => self.out.push_str(&" ".repeat(*width)),
+                },
+            }
+        }
+        self.out.push_str(content);
+        self.out.push('\n');
+    }
+
+    /// Separate the next block with an empty line, which keeps the bar of
+    /// any quote it falls inside.
+    fn blank(&mut self) {
+        self.spaced = !self.out.is_empty();
+    }
+
+    /// Write the empty line `blank` asked for, if it is still due.
+    fn space(&mut self) {
+        if std::mem::take(&mut self.spaced) {
+            for container in &self.containers {
+                if let Container::Quote = container {
+                    self.out.push_str(&dim("│"));
+                }
+            }
+            self.out.push('\n');
+        }
+    }
+
+    /// Write the text read so far, wrapped to the width left inside the
+    /// containers. Hard breaks start a new line.
+    fn flush(&mut self) {
+        let spans = std::mem::take(&mut self.inline);
+        let mut lines: Vec<Vec<Word>> = Vec::new();
+        let mut words = Vec::new();
+        let mut word = Word::default();
+        for (text, style) in &spans {
+            for c in text.chars() {
+                if !c.is_whitespace() {
+                    word.push(c, *style);
+                    continue;
+                }
+                if !word.parts.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+                if c == '\n' {
+                    lines.push(std::mem::take(&mut words));
+                }
+            }
+        }
+        if !word.parts.is_empty() {
+            words.push(word);
+        }
+        lines.push(words);
+        if lines.iter().all(|words| words.is_empty()) {
+            return;
+        }
+
+        let available = self.width.saturating_sub(self.indent()).max(MIN_WIDTH);
+        for words in lines {
+            let mut line = String::new();
+            let mut width = 0;
+            for word in words {
+                if width > 0 && width + 1 + word.width > available {
+                    self.line(&line);
+                    line.clear();
+                    width = 0;
+                }
+                if width > 0 {
+                    line.push(' ');
+                    width += 1;
+                }
+                for (text, style) in &word.parts {
+                    line.push_str(&style.apply(text.as_str()).to_string());
+                }
+                width += word.width;
+            }
+            self.line(&line);
+        }
+    }
+
+    /// Fenced and highlighted, unwrapped so it can be copied as it is.
+    fn code_block(&mut self, language: &str, code: &str) {
+        let syntaxes = syntaxes();
+        let syntax = syntaxes.find_syntax_by_token(language).unwrap_or_else(|| syntaxes.find_syntax_plain_text());
+        let mut highlighter = HighlightLines::new(syntax, self.theme);
+
+        self.line(&dim(&format!("```{}", language)));
+        for line in LinesWithEndings::from(code) {
+            let text = match highlighter.highlight_line(line, syntaxes) {
+                Ok(ranges) => format!("{}\x1b[0m", as_24_bit_terminal_escaped(&ranges, false).trim_end_matches(['\n', '\r'])),
+                Err(_) => line.trim_end_matches(['\n', '\r']).to_string(),
+            };
+            self.line(&text);
+        }
+        self.line(&dim("```"));
+    }
+
+    /// Columns padded to their widest cell, header in bold and ruled off.
+    fn write_table(&mut self, table: Table) {
+        let columns = table.rows.iter().map(Vec::len).max().unwrap_or(0);
+        let mut widths = vec![0; columns];
+        for row in &table.rows {
+            for (i, (_, width)) in row.iter().enumerate() {
+                widths[i] = widths[i].max(*width);
+            }
+        }
+
+        let bold = ContentStyle { attributes: Attribute::Bold.into(), ..ContentStyle::new() };
+        for (r, row) in table.rows.iter().enumerate() {
+            let cells: Vec<String> = widths.iter().enumerate().map(|(i, &width)| {
+                let (text, used) = row.get(i).map_or(("", 0), |(text, used)| (text.as_str(), *used));
+                let text = if r == 0 { bold.apply(text).to_string() } else { text.to_string() };
+                let space = width - used;
+                match table.alignments.get(i) {
+                    Some(Alignment::Right) => format!("{}{}", " ".repeat(space), text),
+                    Some(Alignment::Center) => format!("{}{}{}", " ".repeat(space / 2), text, " ".repeat(space - space / 2)),
+                    _ => format!("{}{}", text, " ".repeat(space)),
+                }
+            }).collect();
+            self.line(cells.join(&dim(" │ ")).trim_end());
+            if r == 0 {
+                let rule: Vec<String> = widths.iter().map(|w| "─".repeat(*w)).collect();
+                self.line(&dim(&rule.join("─┼─")));
+            }
+        }
+    }
+}
+
+fn dim(text: &str) -> String {
+    ContentStyle { attributes: Attribute::Dim.into(), ..ContentStyle::new() }.apply(text).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Drop escape sequences, leaving what the terminal would show.
+    fn visible(styled: &str) -> String {
+        let mut text = String::new();
+        let mut chars = styled.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.by_ref().find(|c| c.is_ascii_alphabetic());
+            } else {
+                text.push(c);
+            }
+        }
+        text
+    }
+
+    fn renderer(width: usize) -> Result<Renderer> {
+        Ok(Renderer { theme: Some(theme(&DisplayConfig::default().theme)?), width: Some(width) })
+    }
+
+    #[test]
+    fn test_blocks_wrap_and_indent() -> Result<()> {
+        let markdown = "# Findings\n\nPort **22** is open on `10.0.0.5` and accepts password logins from anywhere.\n\n\
+            - first\n- second item wraps onto a line of its own\n  1. nested\n\n> quoted\n\nSee [the docs](https://example.com).";
+        let rendered = visible(&renderer(30)?.render(markdown));
+        assert_eq!(rendered, "Findings\n\n\
+            Port 22 is open on 10.0.0.5\nand accepts password logins\nfrom anywhere.\n\n\
+            • first\n• second item wraps onto a\n  line of its own\n  1. nested\n\n\
+            │ quoted\n\n\
+            See the docs\n(https://example.com).");
+        Ok(())
+    }
+
+    #[test]
+    fn test_tables_and_code() -> Result<()> {
+        let markdown = "| Port | State |\n|-----:|-------|\n| 22 | open |\n| 8080 | filtered |\n\n```rust\nfn main() {}\n```\n";
+        let styled = renderer(80)?.render(markdown);
+        assert_eq!(visible(&styled), "Port │ State\n─────┼─────────\n  22 │ open\n8080 │ filtered\n\n```rust\nfn main() {}\n```");
+        // Highlighted in the theme's colours
+        assert!(styled.contains("\x1b[38;2;"));
+
+        assert_eq!(Renderer::plain().render(markdown), markdown.trim_end());
+        Ok(())
+    }
+
+    #[test]
+    fn test_code_blocks() {
+        let markdown = "Run:\n\n```sh\nnmap -sV host\n```\n\n    indented\n\n~~~\nplain\ntext\n~~~\n";
+        assert_eq!(code_blocks(markdown), [
+            CodeBlock { language: "sh".to_string(), code: "nmap -sV host\n".to_string() },
+            CodeBlock { language: String::new(), code: "plain\ntext\n".to_string() },
+        ]);
+    }
+}
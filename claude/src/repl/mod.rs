@@ -0,0 +1,8 @@
+pub mod approval;
+pub mod completion;
+pub mod editor;
+pub mod external;
+pub mod history;
+pub mod markdown;
+pub mod session;
+pub mod terminal;
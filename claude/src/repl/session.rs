@@ -1,10 +1,24 @@
-use anyhow::Result;
-use claude_common::{Config, ClaudeClient, types::{Session, Message}};
-use crossterm::event::{self, Event, KeyCode, KeyEvent};
-use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
-use std::io::{self, Write};
+use anyhow::{bail, Context, Result};
+use claude_common::{Config, ClaudeClient, types::{Attachment, AttachmentKind, Session, Message}};
+use claude_common::attachments::{self, Budget};
+use claude_common::config::Keymap;
+use claude_common::conversation::Conversation;
+use claude_common::api::{ContentBlock, Message as ApiMessage};
+use claude_common::mcp::{McpClient, Prompt, Resource};
+use claude_common::sessions::SessionStore;
+use claude_common::tools::{Approver, ToolRegistry};
+use std::collections::HashMap;
+use std::io::{self, BufRead, IsTerminal};
 use chrono::Utc;
 
+use super::completion::Completer;
+use super::external;
+use super::editor::{in_heredoc, strip_heredoc, Input, LineEditor, HEREDOC};
+use super::history::{History, HistoryKind};
+use super::approval::confirm;
+use super::markdown::{code_blocks, CodeBlock, Renderer};
+use super::terminal::{cancel_requested, copy_to_clipboard, WatchKeys};
+
 #[derive(Debug)]
 enum Mode {
     Chat,
@@ -19,7 +33,44 @@ enum Command {
     Save(String),
     Load(String),
     Model(String),
+    Profile(String),
     Clear,
+    Tools(Option<String>),
+    Resources,
+    Resource(String),
+    Subscribe(String),
+    Unsubscribe(String),
+    Prompts,
+    Prompt(String, HashMap<String, String>),
+    /// Code blocks of the latest reply, or of the message with this number
+    Blocks(Option<usize>),
+    /// Block number and register
+    Yank(Option<usize>, Option<char>),
+    Write { block: Option<usize>, path: String, force: bool },
+    Copy(Option<usize>),
+    /// Attach one file
+    Read(String),
+    /// Attach files matching a pattern, or list what is attached
+    Attach(Option<String>),
+    /// Attach a shell command's output
+    Shell(String),
+    /// Write the next message in an external editor
+    Edit,
+    /// Open the message with this number, or the latest reply, read-only
+    View(Option<usize>),
+    /// Send the last message again, with another model or temperature if given
+    Retry { model: Option<String>, temperature: Option<f32> },
+    /// Drop the last exchange
+    Undo,
+    /// Change the last message, to this text or in the editor, and resend it
+    EditLast(Option<String>),
+    /// Start a branch after this many messages, by default all of them
+    Fork { at: Option<usize>, name: Option<String> },
+    Branches,
+    /// Switch to the branch with this name or number
+    Checkout(String),
+    /// A known command used wrongly, with what is wrong
+    Invalid(String),
     Unknown(String),
 }
 
@@ -27,135 +78,238 @@ pub struct ReplSession {
     mode: Mode,
     client: ClaudeClient,
     config: Config,
-    command_buffer: String,
-    input_buffer: String,
-    history: Vec<Message>,
+    tools: ToolRegistry,
+    approver: Box<dyn Approver>,
+    chat: LineEditor,
+    command: LineEditor,
+    /// Everything typed at either prompt, kept across sessions
+    input_history: History,
+    /// Formats replies for the terminal
+    renderer: Renderer,
+    /// Messages so far, on every branch
+    conversation: Conversation,
     current_model: String,
+    /// Results of the last `:resources` / `:prompts`, for lookup by number
+    resources: Vec<(String, Resource)>,
+    prompts: Vec<(String, Prompt)>,
+    /// Files, command output and resources to send with the next message
+    attachments: Vec<Attachment>,
+    /// Message chosen with `:blocks N` for `:yank`, `:w` and `:copy`,
+    /// instead of the latest reply
+    code_source: Option<usize>,
+    /// The last key was Ctrl-C, so another at an empty prompt exits
+    ctrl_c: bool,
 }
 
 impl ReplSession {
-    pub fn new(client: ClaudeClient, config: Config) -> Self {
+    pub fn new(
+        client: ClaudeClient,
+        config: Config,
+        tools: ToolRegistry,
+        approver: Box<dyn Approver>,
+    ) -> Self {
+        let input_history = History::load(&config.history_file).unwrap_or_else(|e| {
+            eprintln!("Warning: input history not loaded: {:#}", e);
+            History::new(&config.history_file)
+        });
+        let mut chat = LineEditor::new(config.editor.submit_key, config.editor.keymap);
+        chat.set_history(input_history.recall(HistoryKind::Chat));
+        let renderer = Renderer::new(&config.display).unwrap_or_else(|e| {
+            eprintln!("Warning: {:#}; replies are shown unformatted", e);
+            Renderer::plain()
+        });
+        let mut command = LineEditor::new(config.editor.submit_key, Keymap::Emacs);
+        command.set_history(input_history.recall(HistoryKind::Command));
+
         Self {
             mode: Mode::Chat,
             client,
             config: config.clone(),
-            command_buffer: String::new(),
-            input_buffer: String::new(),
-            history: Vec::new(),
+            tools,
+            approver,
+            chat,
+            command,
+            input_history,
+            renderer,
+            conversation: Conversation::default(),
             current_model: config.default_model,
+            resources: Vec::new(),
+            prompts: Vec::new(),
+            attachments: Vec::new(),
+            code_source: None,
+            ctrl_c: false,
         }
     }
 
     pub async fn run(&mut self) -> Result<()> {
+        if !io::stdin().is_terminal() {
+            return self.run_script().await;
+        }
         println!("Claude CLI (Press <Esc> and type :help for commands, :q to quit)\n");
-        enable_raw_mode()?;
-        
-        self.show_prompt();
-        
+
         loop {
-            if let Event::Key(KeyEvent { code, .. }) = event::read()? {
-                match self.mode {
-                    Mode::Chat => self.handle_chat_input(code).await?,
-                    Mode::Command => {
-                        if self.handle_command_input(code).await? {
+            // Servers are only read from between requests, so updates sent
+            // while a prompt waits show up before the next one
+            self.report_resource_updates().await;
+            match self.mode {
+                Mode::Chat => match self.chat.read_line("chat> ")? {
+                    Input::Interrupt if std::mem::take(&mut self.ctrl_c) => break,
+                    Input::Interrupt => {
+                        self.ctrl_c = true;
+                        println!("(Press Ctrl-C again to exit, or type :q)");
+                    }
+                    Input::Line(line) if line.trim().is_empty() => self.ctrl_c = false,
+                    Input::Line(line) => {
+                        self.ctrl_c = false;
+                        self.remember(HistoryKind::Chat, &line);
+                        report(self.send_turn(vec![user_message(line)]).await);
+                    }
+                    Input::Escape => {
+                        self.ctrl_c = false;
+                        self.mode = Mode::Command;
+                    }
+                },
+                Mode::Command => {
+                    self.command.set_completer(self.completer());
+                    if let Input::Line(line) = self.command.read_line(":")? {
+                        self.remember(HistoryKind::Command, &line);
+                        let result = self.execute_command(&line).await;
+                        if let Ok(true) = result {
                             break;
                         }
+                        report(result.map(|_| ()));
                     }
+                    self.command.clear();
+                    self.mode = Mode::Chat;
                 }
             }
         }
-        
-        disable_raw_mode()?;
+
         println!("\nGoodbye!");
         Ok(())
     }
 
-    async fn handle_chat_input(&mut self, code: KeyCode) -> Result<()> {
-        match code {
-            KeyCode::Esc => {
-                self.mode = Mode::Command;
-                println!();
-                print!(":");
-                io::stdout().flush()?;
-            }
-            KeyCode::Enter => {
-                if !self.input_buffer.trim().is_empty() {
-                    println!();
-                    let response = self.client.chat(&self.input_buffer, &self.current_model).await?;
-                    
-                    self.history.push(Message {
-                        role: "user".to_string(),
-                        content: self.input_buffer.clone(),
-                        timestamp: Utc::now(),
-                    });
-                    
-                    self.history.push(Message {
-                        role: "assistant".to_string(),
-                        content: response.clone(),
-                        timestamp: Utc::now(),
-                    });
-                    
-                    println!("\n{}\n", response);
-                    self.input_buffer.clear();
-                    self.show_prompt();
-                }
+    /// Drive the session from piped input without touching the terminal:
+    /// lines starting with `:` are commands, `"""` blocks span lines, and
+    /// anything else is sent as a message. The first failure ends the script
+    /// with an error, so the exit status tells whether all of it ran.
+    async fn run_script(&mut self) -> Result<()> {
+        let mut lines = io::stdin().lock().lines();
+        while let Some(line) = lines.next() {
+            let mut text = line?;
+            while in_heredoc(&text) {
+                let Some(next) = lines.next() else {
+                    bail!("Input ended inside a {} block", HEREDOC);
+                };
+                text.push('\n');
+                text.push_str(&next?);
             }
-            KeyCode::Char(c) => {
-                self.input_buffer.push(c);
-                print!("{}", c);
-                io::stdout().flush()?;
+            let text = strip_heredoc(&text).unwrap_or(text);
+
+            if text.trim().is_empty() {
+                continue;
             }
-            KeyCode::Backspace => {
-                if !self.input_buffer.is_empty() {
-                    self.input_buffer.pop();
-                    print!("\x08 \x08");
-                    io::stdout().flush()?;
+            if text.trim_start().starts_with(':') {
+                if self.execute_command(&text).await? {
+                    break;
                 }
+            } else {
+                self.send_turn(vec![user_message(text)]).await?;
             }
-            _ => {}
         }
         Ok(())
     }
 
-    async fn handle_command_input(&mut self, code: KeyCode) -> Result<bool> {
-        match code {
-            KeyCode::Enter => {
-                println!();
-                let should_quit = self.execute_command().await?;
-                if !should_quit {
-                    self.mode = Mode::Chat;
-                    self.command_buffer.clear();
-                    self.show_prompt();
-                }
-                Ok(should_quit)
-            }
-            KeyCode::Char(c) => {
-                self.command_buffer.push(c);
-                print!("{}", c);
-                io::stdout().flush()?;
-                Ok(false)
-            }
-            KeyCode::Backspace => {
-                if !self.command_buffer.is_empty() {
-                    self.command_buffer.pop();
-                    print!("\x08 \x08");
-                    io::stdout().flush()?;
+    /// Add submitted input to the history file and to what its prompt recalls.
+    fn remember(&mut self, kind: HistoryKind, line: &str) {
+        if let Err(e) = self.input_history.add(kind, line) {
+            eprintln!("Warning: input history not saved: {:#}", e);
+        }
+        let editor = match kind {
+            HistoryKind::Chat => &mut self.chat,
+            HistoryKind::Command => &mut self.command,
+        };
+        editor.set_history(self.input_history.recall(kind));
+    }
+
+    /// Send `turn` after the current history, attaching any pending resources
+    /// to its last message, and record the exchange.
+    async fn send_turn(&mut self, mut turn: Vec<Message>) -> Result<()> {
+        if let Some(last) = turn.last_mut() {
+            last.attachments.append(&mut self.attachments);
+        }
+
+        let mut messages: Vec<ApiMessage> = self.conversation.messages().into_iter()
+            .chain(turn.iter())
+            .filter(|m| m.is_conversation())
+            .map(Message::api_message)
+            .collect();
+        let sent = messages.len();
+
+        // Ctrl-C or Esc abandons the request, keeping what the model said in
+        // any tool rounds that finished
+        let keys = WatchKeys::start();
+        let outcome = tokio::select! {
+            response = self.tools.converse(&self.client, &self.current_model, &mut messages, self.approver.as_mut()) => Ok(response),
+            ctrl_c = cancel_requested(), if keys.is_some() => Err(ctrl_c),
+        };
+        drop(keys);
+        // Commands run in this turn belong to it, however it ended
+        let runs = self.tools.take_shell_runs();
+        let (response, interrupted, failure) = match outcome {
+            Ok(Ok(response)) => (response, false, None),
+            Ok(Err(e)) if messages.len() == sent => {
+                // Nothing was sent, so the attachments wait for the next try
+                if let Some(last) = turn.last_mut() {
+                    self.attachments.append(&mut last.attachments);
                 }
-                Ok(false)
+                return Err(e);
             }
-            KeyCode::Esc => {
-                self.mode = Mode::Chat;
-                self.command_buffer.clear();
-                println!();
-                self.show_prompt();
-                Ok(false)
+            // Tool rounds finished before a later request failed; what they
+            // did is kept, as for Ctrl-C
+            Ok(Err(e)) => (partial_response(&messages[sent..]), true, Some(e)),
+            Err(ctrl_c) => {
+                self.ctrl_c = ctrl_c;
+                (partial_response(&messages[sent..]), true, None)
             }
-            _ => Ok(false)
+        };
+
+        for message in turn {
+            self.conversation.push(message);
+        }
+        for run in runs {
+            self.conversation.push(Message {
+                role: "tool".to_string(),
+                content: run.summary(),
+                timestamp: Utc::now(),
+                interrupted: false,
+                attachments: Vec::new(),
+            });
+        }
+        self.conversation.push(Message {
+            role: "assistant".to_string(),
+            content: response.clone(),
+            timestamp: Utc::now(),
+            interrupted,
+            attachments: Vec::new(),
+        });
+        self.code_source = None;
+
+        let reply = self.renderer.render(&response);
+        match (interrupted, response.is_empty()) {
+            (false, _) => println!("\n{}\n", reply),
+            (true, true) => println!("\n[interrupted]\n"),
+            (true, false) => println!("\n{}\n[interrupted]\n", reply),
+        }
+        self.report_resource_updates().await;
+        match failure {
+            Some(e) => Err(e.context("The reply was cut short; what the tools did is kept")),
+            None => Ok(()),
         }
     }
 
-    async fn execute_command(&mut self) -> Result<bool> {
-        match self.parse_command() {
+    async fn execute_command(&mut self, line: &str) -> Result<bool> {
+        match parse_command(line) {
             Command::Quit => Ok(true),
             Command::Help => {
                 self.show_help();
@@ -178,52 +332,144 @@ impl ReplSession {
                 println!("Switched to model: {}", self.current_model);
                 Ok(false)
             }
+            Command::Profile(name) => {
+                self.switch_profile(&name)?;
+                Ok(false)
+            }
+            Command::Tools(filter) => {
+                self.list_tools(filter);
+                Ok(false)
+            }
             Command::Clear => {
-                self.history.clear();
+                self.conversation = Conversation::default();
+                self.code_source = None;
                 println!("History cleared");
                 Ok(false)
             }
-            Command::Unknown(cmd) => {
-                println!("Unknown command: {}", cmd);
+            Command::Resources => {
+                self.list_resources().await?;
                 Ok(false)
             }
-        }
-    }
-
-    fn parse_command(&self) -> Command {
-        let cmd = self.command_buffer.trim();
-        if cmd.is_empty() {
-            return Command::Unknown(String::new());
-        }
-
-        let parts: Vec<&str> = cmd.split_whitespace().collect();
-        match parts[0] {
-            ":q" | ":quit" => Command::Quit,
-            ":help" => Command::Help,
-            ":list" => Command::List(parts.get(1).map(|s| s.to_string())),
-            ":save" => {
-                if parts.len() > 1 {
-                    Command::Save(parts[1].to_string())
-                } else {
-                    Command::Unknown(":save requires a name".to_string())
+            Command::Resource(reference) => {
+                self.attach_resource(&reference).await?;
+                Ok(false)
+            }
+            Command::Subscribe(reference) => {
+                self.subscribe(&reference, true).await?;
+                Ok(false)
+            }
+            Command::Unsubscribe(reference) => {
+                self.subscribe(&reference, false).await?;
+                Ok(false)
+            }
+            Command::Prompts => {
+                self.list_prompts().await?;
+                Ok(false)
+            }
+            Command::Prompt(reference, arguments) => {
+                self.run_prompt(&reference, arguments).await?;
+                Ok(false)
+            }
+            Command::Blocks(number) => {
+                self.list_blocks(number)?;
+                Ok(false)
+            }
+            Command::Yank(block, register) => {
+                let (number, block) = self.code_block(block)?;
+                self.chat.yank(register, block.code.trim_end_matches('\n'));
+                let target = register.map_or("the paste buffer".to_string(), |r| format!("register {}", r));
+                println!("Yanked code block {} ({}) into {}", number, lines(&block.code), target);
+                Ok(false)
+            }
+            Command::Write { block, path, force } => {
+                self.write_block(block, &path, force)?;
+                Ok(false)
+            }
+            Command::Copy(block) => {
+                let (number, block) = self.code_block(block)?;
+                copy_to_clipboard(&block.code)?;
+                println!("Copied code block {} ({}) to the clipboard", number, lines(&block.code));
+                Ok(false)
+            }
+            Command::Read(path) => {
+                let attachment = attachments::read_file(&path, &std::env::current_dir()?, self.budget())?;
+                println!("Attached {} ({} bytes) to next message", attachment.source, attachment.size());
+                self.attachments.push(attachment);
+                Ok(false)
+            }
+            Command::Attach(Some(pattern)) => {
+                let collected = attachments::collect(&pattern, &std::env::current_dir()?, self.budget())?;
+                for note in &collected.skipped {
+                    println!("Skipped: {}", note);
                 }
+                for attachment in &collected.attachments {
+                    println!("Attached {} ({} bytes)", attachment.source, attachment.size());
+                }
+                let count = collected.attachments.len();
+                println!("{} {} attached to next message", count, if count == 1 { "file" } else { "files" });
+                self.attachments.extend(collected.attachments);
+                Ok(false)
+            }
+            Command::Attach(None) => {
+                self.list_attachments();
+                Ok(false)
+            }
+            Command::Shell(command) => {
+                self.attach_command(&command).await?;
+                Ok(false)
+            }
+            Command::Edit => {
+                self.edit_message().await?;
+                Ok(false)
+            }
+            Command::View(number) => {
+                self.view_message(number)?;
+                Ok(false)
+            }
+            Command::Retry { model, temperature } => {
+                let index = self.last_exchange()?;
+                let original = self.conversation.messages()[index];
+                let message = Message { timestamp: Utc::now(), interrupted: false, ..original.clone() };
+                self.resend(message, model, temperature).await?;
+                Ok(false)
+            }
+            Command::Undo => {
+                let index = self.last_exchange()?;
+                let count = self.conversation.len() - index;
+                let kept = self.set_aside(index);
+                println!("Removed the last exchange ({} messages), kept on branch {}", count, kept);
+                Ok(false)
             }
-            ":load" => {
-                if parts.len() > 1 {
-                    Command::Load(parts[1].to_string())
-                } else {
-                    Command::Unknown(":load requires a name".to_string())
+            Command::EditLast(text) => {
+                self.edit_last(text).await?;
+                Ok(false)
+            }
+            Command::Fork { at, name } => {
+                let at = at.unwrap_or(self.conversation.len());
+                let branch = self.conversation.fork(at, name)?;
+                match at {
+                    0 => println!("Switched to new branch {}, starting over", branch.name),
+                    _ => println!("Switched to new branch {} after message [{}]", branch.name, at),
                 }
+                self.code_source = None;
+                Ok(false)
             }
-            ":model" => {
-                if parts.len() > 1 {
-                    Command::Model(parts[1].to_string())
-                } else {
-                    Command::Unknown(":model requires a model name".to_string())
+            Command::Branches => {
+                println!("\nBranches:");
+                for line in self.conversation.tree() {
+                    println!("  {}", line);
                 }
+                Ok(false)
             }
-            ":clear" => Command::Clear,
-            _ => Command::Unknown(cmd.to_string()),
+            Command::Checkout(reference) => {
+                let branch = self.conversation.checkout(&reference)?.name.clone();
+                println!("Switched to branch {} ({} messages)", branch, self.conversation.len());
+                self.code_source = None;
+                Ok(false)
+            }
+            Command::Invalid(problem) => bail!("{}", problem),
+            Command::Unknown(cmd) if cmd.is_empty() => Ok(false),
+            Command::Unknown(cmd) => bail!("Unknown command: {}", cmd),
         }
     }
 
@@ -235,70 +481,920 @@ impl ReplSession {
         println!("  :save <name>     Save current session");
         println!("  :load <name>     Load a saved session");
         println!("  :model <name>    Switch Claude model");
+        println!("  :profile <name>  Switch to a configured profile's model and system prompt");
         println!("  :clear           Clear current session");
+        println!("  :tools [filter]  List the tools the model can use");
+        println!("  :resources       List MCP server resources");
+        println!("  :resource <n>    Attach a resource (number or URI) to the next message");
+        println!("  :subscribe <n>   Watch a resource for changes, reported before each prompt");
+        println!("  :unsubscribe <n> Stop watching a resource");
+        println!("  :prompts         List MCP server prompts");
+        println!("  :prompt <n> [key=value ...]");
+        println!("                   Send a server prompt with arguments");
+        println!("  :blocks [n]      List code blocks in the last reply, or in message n");
+        println!("  :yank [b] [r]    Yank code block b into register r (Vi) or for Ctrl-Y");
+        println!("  :w [b] <path>    Write code block b to a file (:w! overwrites)");
+        println!("  :copy [b]        Copy code block b to the clipboard (OSC 52)");
+        println!("  :read <path>     Attach a file, image or PDF to the next message");
+        println!("  :attach [glob]   Attach files (a directory or glob, minding .gitignore), or list attachments");
+        println!("  :!<command>      Run a shell command and attach its output");
+        println!("  :edit            Write the next message in $VISUAL or $EDITOR");
+        println!("  :view [n]        Open message n, or the last reply, in the editor read-only");
+        println!("  :retry [model] [temperature]");
+        println!("                   Resend the last message, optionally to another model or at a temperature");
+        println!("  :undo            Drop the last message and its reply");
+        println!("  :edit-last [text]");
+        println!("                   Change the last message, in the editor unless text is given, and resend it");
+        println!("  :fork [n] [name] Start a branch after message n (default: the last)");
+        println!("  :branches        Show the conversation's branches as a tree");
+        println!("  :checkout <b>    Switch to branch b, by name or number");
         println!("\nIn chat mode:");
         println!("  <Esc>            Enter command mode");
         println!("  <Enter>          Send message");
+        println!("  Ctrl-C, <Esc>    Stop waiting for a reply, keeping what arrived");
+        println!("  Ctrl-C Ctrl-C    Exit (Ctrl-C also clears a half-typed line)");
+        println!("  Ctrl-Z           Suspend to the shell; resume with fg");
+        println!("\nIn command mode:");
+        println!("  <Tab>            Complete commands, sessions, models, profiles, tools and paths");
+        println!("\nLine editing:");
+        println!("  Ctrl-A/E, Home/End       Start/end of line");
+        println!("  Ctrl-B/F, Left/Right     Back/forward a character");
+        println!("  Alt-B/F, Ctrl-Left/Right Back/forward a word");
+        println!("  Ctrl-W, Alt-Backspace    Delete the word before the cursor");
+        println!("  Ctrl-U/K, Alt-D          Delete to start/end of line, next word");
+        println!("  Ctrl-Y                   Paste the last deleted text");
+        println!("  Up/Down, Ctrl-P/N        Previous/next line, or earlier input");
+        println!("  Ctrl-R                   Search earlier input");
+        if self.config.editor.keymap == Keymap::Vi {
+            println!("\nVi keymap ([I]nsert, [N]ormal, [V]isual shown before the prompt):");
+            println!("  <Esc>, i a I A o O, v    Normal, Insert and Visual modes");
+            println!("  w b e W B E 0 ^ $ f t F T ; , gg G    Motions, with counts");
+            println!("  d c y (dd cc yy), x D C s S r ~       Operators and edits");
+            println!("  iw aw i\" a\" i( a( i[ i{{ i<           Text objects");
+            println!("  \"a-\"z p P, u Ctrl-R, :                Registers, undo/redo, command mode");
+        }
     }
 
     fn show_history(&self, filter: Option<String>) {
-        if self.history.is_empty() {
+        if self.conversation.is_empty() {
             println!("\nNo messages in current session");
             return;
         }
 
-        println!("\nChat History:");
-        for (i, msg) in self.history.iter().enumerate() {
+        match self.conversation.branches().len() {
+            1 => println!("\nChat History:"),
+            _ => println!("\nChat History (branch {}):", self.conversation.current().name),
+        }
+        for (i, msg) in self.conversation.messages().into_iter().enumerate() {
             if let Some(ref f) = filter {
                 if !msg.content.contains(f) {
                     continue;
                 }
             }
-            println!("\n[{}] {}: {}", i + 1, msg.role, msg.content);
+            print_message(i + 1, msg);
         }
     }
 
-    fn show_prompt(&self) {
-        match self.mode {
-            Mode::Chat => print!("chat> "),
-            Mode::Command => print!(":"),
+    /// Where the last exchange starts: the last message the user sent.
+    fn last_exchange(&self) -> Result<usize> {
+        self.conversation.messages().iter()
+            .rposition(|m| m.role == "user")
+            .context("No messages sent yet")
+    }
+
+    /// Keep the branch as it is on a new one and cut it back to its first
+    /// `index` messages. Returns the new branch's name.
+    fn set_aside(&mut self, index: usize) -> String {
+        self.code_source = None;
+        self.conversation.set_aside(index)
+    }
+
+    /// Send `message` in place of the last exchange, which is kept on a
+    /// branch of its own, with `model` and `temperature` for this request only.
+    async fn resend(&mut self, message: Message, model: Option<String>, temperature: Option<f32>) -> Result<()> {
+        let index = self.last_exchange()?;
+        let kept = self.set_aside(index);
+        println!("Resending message [{}]; the previous exchange is kept on branch {}", index + 1, kept);
+
+        // Pending attachments wait for the next new message
+        let pending = std::mem::take(&mut self.attachments);
+        let model = model.map(|model| std::mem::replace(&mut self.current_model, model));
+        let temperature = temperature.map(|t| self.client.set_temperature(Some(t)));
+        let result = self.send_turn(vec![message]).await;
+        if let Some(model) = model {
+            self.current_model = model;
+        }
+        if let Some(temperature) = temperature {
+            self.client.set_temperature(temperature);
+        }
+        self.attachments = pending;
+
+        if result.is_err() && self.conversation.len() == index {
+            // Nothing was recorded, so the exchange goes back
+            self.conversation.take_back(&kept)?;
+        }
+        result
+    }
+
+    /// Resend the last message as `text`, or as changed in the editor.
+    async fn edit_last(&mut self, text: Option<String>) -> Result<()> {
+        let original = self.conversation.messages()[self.last_exchange()?];
+        let content = match text {
+            Some(text) => text,
+            None => {
+                let Some(text) = external::compose(&original.content)? else {
+                    println!("Nothing sent: the message was empty or not saved");
+                    return Ok(());
+                };
+                println!("chat> {}", text);
+                text
+            }
+        };
+        let message = Message { content, timestamp: Utc::now(), interrupted: false, ..original.clone() };
+        self.remember(HistoryKind::Chat, &message.content);
+        self.resend(message, None, None).await
+    }
+
+    /// Room still free for attachments to the next message.
+    fn budget(&self) -> Budget {
+        Budget::after(&self.attachments)
+    }
+
+    fn list_attachments(&self) {
+        if self.attachments.is_empty() {
+            println!("\nNothing attached to the next message");
+            return;
+        }
+        println!("\nAttached to the next message:");
+        for attachment in &self.attachments {
+            println!("  {}", describe(attachment));
         }
-        io::stdout().flush().unwrap();
+    }
+
+    /// Run `command`, show its output and attach it. Ctrl-C or Esc stops it.
+    async fn attach_command(&mut self, command: &str) -> Result<()> {
+        let cwd = std::env::current_dir()?;
+        let keys = WatchKeys::start();
+        let outcome = tokio::select! {
+            attachment = attachments::run_command(command, &cwd) => Some(attachment?),
+            _ = cancel_requested(), if keys.is_some() => None,
+        };
+        drop(keys);
+        let Some(attachment) = outcome else {
+            println!("Stopped; nothing attached");
+            return Ok(());
+        };
+
+        print!("{}", attachment.content);
+        if !attachment.content.is_empty() && !attachment.content.ends_with('\n') {
+            println!();
+        }
+        if let Some(reason) = self.budget().refuse(&attachment) {
+            println!("Not attached: {}", reason);
+            return Ok(());
+        }
+        println!("Attached output of {} ({} bytes) to next message", command, attachment.content.len());
+        self.attachments.push(attachment);
+        Ok(())
+    }
+
+    /// Add `attachments`, such as `--file` arguments, to the first message.
+    pub fn attach(&mut self, attachments: Vec<Attachment>) {
+        self.attachments.extend(attachments);
+    }
+
+    /// Compose a message, starting from what was typed at the chat prompt,
+    /// and send it once saved. An empty or unsaved file sends nothing.
+    async fn edit_message(&mut self) -> Result<()> {
+        let Some(text) = external::compose(self.chat.text())? else {
+            println!("Nothing sent: the message was empty or not saved");
+            return Ok(());
+        };
+        self.chat.clear();
+        println!("chat> {}", text);
+        self.remember(HistoryKind::Chat, &text);
+        self.send_turn(vec![user_message(text)]).await
+    }
+
+    fn view_message(&self, number: Option<usize>) -> Result<()> {
+        let index = match number {
+            Some(n) if n >= 1 && n <= self.conversation.len() => n - 1,
+            Some(n) => bail!("No message {} (see :list)", n),
+            None => match self.conversation.messages().iter().rposition(|m| m.role == "assistant") {
+                Some(index) => index,
+                None => bail!("No replies yet"),
+            },
+        };
+        external::view(&format!("message-{}", index + 1), &self.conversation.messages()[index].content)
+    }
+
+    /// Number shown by `:list` and text of the reply code commands act on.
+    fn code_reply(&self) -> Result<(usize, &Message)> {
+        let index = match self.code_source {
+            Some(index) => index,
+            None => match self.conversation.messages().iter().rposition(|m| m.role == "assistant") {
+                Some(index) => index,
+                None => bail!("No replies yet"),
+            },
+        };
+        let reply = self.conversation.get(index).context("No replies yet")?;
+        Ok((index + 1, reply))
+    }
+
+    fn list_blocks(&mut self, number: Option<usize>) -> Result<()> {
+        if let Some(number) = number {
+            match self.conversation.get(number.wrapping_sub(1)) {
+                Some(message) if message.role == "assistant" => self.code_source = Some(number - 1),
+                Some(message) => bail!("Message {} is from {}, not a reply", number, message.role),
+                None => bail!("No message {} (see :list)", number),
+            }
+        }
+        let (number, reply) = self.code_reply()?;
+        let blocks = code_blocks(&reply.content);
+        if blocks.is_empty() {
+            println!("\nNo code blocks in reply [{}]", number);
+            return Ok(());
+        }
+
+        println!("\nCode blocks in reply [{}]:", number);
+        for (i, block) in blocks.iter().enumerate() {
+            let language = if block.language.is_empty() { "text" } else { &block.language };
+            let first = block.code.lines().find(|l| !l.trim().is_empty()).unwrap_or_default();
+            let preview: String = first.trim().chars().take(60).collect();
+            println!("  [{}] {}, {}: {}", i + 1, language, lines(&block.code), preview);
+        }
+        Ok(())
+    }
+
+    /// Block `number` of the reply, or its only block. Numbers start at 1.
+    fn code_block(&self, number: Option<usize>) -> Result<(usize, CodeBlock)> {
+        let (reply, message) = self.code_reply()?;
+        let mut blocks = code_blocks(&message.content);
+        match (number, blocks.len()) {
+            (_, 0) => bail!("No code blocks in reply [{}]", reply),
+            (None, 1) => Ok((1, blocks.remove(0))),
+            (None, count) => bail!("Reply [{}] has {} code blocks; give a number (see :blocks)", reply, count),
+            (Some(n), count) if n == 0 || n > count => bail!("No code block {} in reply [{}], which has {}", n, reply, count),
+            (Some(n), _) => Ok((n, blocks.remove(n - 1))),
+        }
+    }
+
+    fn write_block(&self, number: Option<usize>, path: &str, force: bool) -> Result<()> {
+        let (number, block) = self.code_block(number)?;
+        let path = match path.strip_prefix("~/") {
+            Some(rest) => dirs::home_dir().unwrap_or_default().join(rest),
+            None => std::path::PathBuf::from(path),
+        };
+        if path.exists() && !force {
+            if !io::stdin().is_terminal() {
+                bail!("{} already exists; use :w! to overwrite it", path.display());
+            }
+            if !confirm(&format!("{} exists. Overwrite?", path.display()))? {
+                return Ok(());
+            }
+        }
+        std::fs::write(&path, &block.code)
+            .with_context(|| format!("Could not write {}", path.display()))?;
+        println!("Wrote code block {} ({}) to {}", number, lines(&block.code), path.display());
+        Ok(())
     }
 
     fn save_session(&self, name: &str) -> Result<()> {
         let session = Session {
             id: name.to_string(),
             model: self.current_model.clone(),
-            messages: self.history.clone(),
+            conversation: self.conversation.clone(),
             created_at: Utc::now(),
             updated_at: Utc::now(),
         };
 
-        let session_dir = self.config.config_dir.join("sessions");
-        std::fs::create_dir_all(&session_dir)?;
-        
-        let file_path = session_dir.join(format!("{}.json", name));
-        let content = serde_json::to_string_pretty(&session)?;
-        std::fs::write(file_path, content)?;
-        
+        SessionStore::new(&self.config.config_dir).save(&session)?;
         println!("Session saved as: {}", name);
         Ok(())
     }
 
     fn load_session(&mut self, name: &str) -> Result<()> {
-        let file_path = self.config.config_dir
-            .join("sessions")
-            .join(format!("{}.json", name));
-            
-        let content = std::fs::read_to_string(file_path)?;
-        let session: Session = serde_json::from_str(&content)?;
+        let session = SessionStore::new(&self.config.config_dir).load(name)?;
         
-        self.history = session.messages;
+        self.conversation = session.conversation;
         self.current_model = session.model;
+        self.code_source = None;
         
         println!("Loaded session: {}", name);
         self.show_history(None);
         Ok(())
     }
-}
\ No newline at end of file
+
+    fn switch_profile(&mut self, name: &str) -> Result<()> {
+        let profile = self.config.profile(name)?.clone();
+        if let Some(model) = profile.model {
+            self.current_model = model;
+        }
+        self.client.set_system(profile.system);
+        println!("Switched to profile: {} (model {})", name, self.current_model);
+        Ok(())
+    }
+
+    fn list_tools(&self, filter: Option<String>) {
+        let tools = self.tools.tools();
+        let shown: Vec<_> = tools.iter()
+            .filter(|(server, tool)| filter.as_deref().is_none_or(|f| server.contains(f) || tool.name.contains(f)))
+            .collect();
+        if shown.is_empty() {
+            println!("\nNo tools available");
+            return;
+        }
+
+        println!("\nTools:");
+        for (server, tool) in shown {
+            println!("  {}: {}", server, tool.name);
+            if let Some(description) = tool.description.as_deref().and_then(|d| d.lines().next()) {
+                println!("      {}", description);
+            }
+        }
+    }
+
+    /// What Tab can offer on the command line right now.
+    fn completer(&self) -> Completer {
+        let tools = self.tools.tools();
+        let mut servers: Vec<String> = tools.iter().map(|(server, _)| server.to_string()).collect();
+        servers.dedup();
+        let mut profiles: Vec<String> = self.config.profiles.keys().cloned().collect();
+        profiles.sort();
+        let models = self.config.profiles.values()
+            .filter_map(|p| p.model.clone())
+            .chain([self.config.default_model.clone(), self.current_model.clone()])
+            .collect();
+
+        Completer {
+            config_dir: self.config.config_dir.clone(),
+            models,
+            profiles,
+            servers,
+            tools: tools.iter().map(|(_, tool)| tool.name.clone()).collect(),
+            prompts: self.prompts.iter().map(|(_, prompt)| prompt.name.clone()).collect(),
+            branches: self.conversation.branches().iter().map(|b| b.name.clone()).collect(),
+        }
+    }
+
+    fn server(&mut self, name: &str) -> Result<&mut McpClient> {
+        self.tools.client_mut(name)
+            .ok_or_else(|| anyhow::anyhow!("MCP server '{}' is not connected", name))
+    }
+
+    async fn list_resources(&mut self) -> Result<()> {
+        self.resources.clear();
+        for client in self.tools.clients_mut() {
+            let server = client.name().to_string();
+            match client.list_resources().await {
+                Ok(resources) => self.resources.extend(resources.into_iter().map(|r| (server.clone(), r))),
+                Err(e) => println!("{}: {:#}", server, e),
+            }
+        }
+
+        if self.resources.is_empty() {
+            println!("\nNo resources available");
+            return Ok(());
+        }
+
+        println!("\nResources:");
+        for (i, (server, resource)) in self.resources.iter().enumerate() {
+            println!("  [{}] {}: {} ({})", i + 1, server, resource.name, resource.uri);
+            if let Some(description) = &resource.description {
+                println!("      {}", description);
+            }
+        }
+        Ok(())
+    }
+
+    /// Find the server for a resource given by list number or URI.
+    fn resolve_resource(&self, reference: &str) -> Result<(String, String)> {
+        let found = match reference.parse::<usize>() {
+            Ok(n) => self.resources.get(n.wrapping_sub(1)),
+            Err(_) => self.resources.iter().find(|(_, r)| r.uri == reference),
+        };
+
+        match found {
+            Some((server, resource)) => Ok((server.clone(), resource.uri.clone())),
+            None => Err(anyhow::anyhow!("Unknown resource '{}', run :resources first", reference)),
+        }
+    }
+
+    async fn attach_resource(&mut self, reference: &str) -> Result<()> {
+        let (server, uri) = self.resolve_resource(reference)?;
+        let contents = self.server(&server)?.read_resource(&uri).await?;
+
+        for item in contents {
+            let attachment = Attachment {
+                kind: AttachmentKind::Resource,
+                mime_type: item.mime_type.clone(),
+                ..Attachment::file(item.uri.clone(), item.text().to_string())
+            };
+            if let Some(reason) = self.budget().refuse(&attachment) {
+                println!("Skipped: {}", reason);
+                continue;
+            }
+            println!("Attached {} ({} bytes) to next message", item.uri, attachment.content.len());
+            self.attachments.push(attachment);
+        }
+        Ok(())
+    }
+
+    async fn subscribe(&mut self, reference: &str, subscribe: bool) -> Result<()> {
+        let (server, uri) = self.resolve_resource(reference)?;
+        if subscribe {
+            self.server(&server)?.subscribe(&uri).await?;
+            println!("Subscribed to {}", uri);
+        } else {
+            self.server(&server)?.unsubscribe(&uri).await?;
+            println!("Unsubscribed from {}", uri);
+        }
+        Ok(())
+    }
+
+    /// Take in what servers sent since they were last heard from and say
+    /// which subscribed resources changed.
+    async fn report_resource_updates(&mut self) {
+        for client in self.tools.clients_mut() {
+            if let Err(e) = client.poll().await {
+                eprintln!("Warning: {:#}", e);
+            }
+            for uri in client.take_updated_resources() {
+                println!("Resource changed on {}: {}", client.name(), uri);
+            }
+        }
+    }
+
+    async fn list_prompts(&mut self) -> Result<()> {
+        self.prompts.clear();
+        for client in self.tools.clients_mut() {
+            let server = client.name().to_string();
+            match client.list_prompts().await {
+                Ok(prompts) => self.prompts.extend(prompts.into_iter().map(|p| (server.clone(), p))),
+                Err(e) => println!("{}: {:#}", server, e),
+            }
+        }
+
+        if self.prompts.is_empty() {
+            println!("\nNo prompts available");
+            return Ok(());
+        }
+
+        println!("\nPrompts:");
+        for (i, (server, prompt)) in self.prompts.iter().enumerate() {
+            let arguments: Vec<String> = prompt.arguments.iter()
+                .map(|a| if a.required { a.name.clone() } else { format!("[{}]", a.name) })
+                .collect();
+            println!("  [{}] {}: {} {}", i + 1, server, prompt.name, arguments.join(" "));
+            if let Some(description) = &prompt.description {
+                println!("      {}", description);
+            }
+        }
+        Ok(())
+    }
+
+    async fn run_prompt(&mut self, reference: &str, arguments: HashMap<String, String>) -> Result<()> {
+        let found = match reference.parse::<usize>() {
+            Ok(n) => self.prompts.get(n.wrapping_sub(1)),
+            Err(_) => self.prompts.iter().find(|(_, p)| p.name == reference),
+        };
+        let Some((server, prompt)) = found.cloned() else {
+            return Err(anyhow::anyhow!("Unknown prompt '{}', run :prompts first", reference));
+        };
+
+        let missing: Vec<&str> = prompt.arguments.iter()
+            .filter(|a| a.required && !arguments.contains_key(&a.name))
+            .map(|a| a.name.as_str())
+            .collect();
+        if !missing.is_empty() {
+            return Err(anyhow::anyhow!("{} requires: {}", prompt.name, missing.join(", ")));
+        }
+
+        let messages = self.server(&server)?.get_prompt(&prompt.name, &arguments).await?;
+        let turn = messages.iter()
+            .map(|m| Message {
+                role: m.role.clone(),
+                content: m.text(),
+                timestamp: Utc::now(),
+                interrupted: false,
+                attachments: Vec::new(),
+            })
+            .collect();
+        self.send_turn(turn).await
+    }
+}
+
+/// Print a failure without leaving the session.
+fn report(result: Result<()>) {
+    if let Err(e) = result {
+        println!("Error: {:#}", e);
+    }
+}
+
+/// A message as `:list` shows it, under its number.
+fn print_message(number: usize, msg: &Message) {
+    let note = if msg.interrupted { " [interrupted]" } else { "" };
+    println!("\n[{}] {}: {}{}", number, msg.role, msg.content, note);
+    for attachment in &msg.attachments {
+        println!("    + {}", describe(attachment));
+    }
+}
+
+/// One line about an attachment: what it is, where from and its size.
+fn describe(attachment: &Attachment) -> String {
+    let kind = match attachment.kind {
+        AttachmentKind::File => "file",
+        AttachmentKind::Command => "output of",
+        AttachmentKind::Resource => "resource",
+        AttachmentKind::Image => "image",
+        AttachmentKind::Document => "document",
+    };
+    format!("{} {} ({} bytes)", kind, attachment.source, attachment.size())
+}
+
+fn user_message(content: String) -> Message {
+    Message {
+        role: "user".to_string(),
+        content,
+        timestamp: Utc::now(),
+        interrupted: false,
+        attachments: Vec::new(),
+    }
+}
+
+/// Text the model wrote in the tool rounds of a request that was cut short.
+fn partial_response(exchange: &[ApiMessage]) -> String {
+    exchange.iter()
+        .filter(|m| m.role == "assistant")
+        .flat_map(|m| &m.content)
+        .filter_map(|block| match block {
+            ContentBlock::Text { text } => Some(text.trim()),
+            _ => None,
+        })
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn lines(code: &str) -> String {
+    match code.lines().count() {
+        1 => "1 line".to_string(),
+        n => format!("{} lines", n),
+    }
+}
+
+/// Parse a command line, with or without the leading `:` the prompt shows.
+fn parse_command(line: &str) -> Command {
+    let cmd = line.trim();
+    if cmd.is_empty() {
+        return Command::Unknown(String::new());
+    }
+
+    let parts: Vec<&str> = cmd.split_whitespace().collect();
+    let name = parts[0].strip_prefix(':').unwrap_or(parts[0]);
+    // Everything after the command name, spaces and all
+    let rest = cmd.split_once(char::is_whitespace).map_or("", |(_, rest)| rest.trim());
+    if let Some(command) = cmd.strip_prefix(':').unwrap_or(cmd).strip_prefix('!') {
+        return match command.trim() {
+            "" => Command::Invalid(":! requires a command".to_string()),
+            command => Command::Shell(command.to_string()),
+        };
+    }
+    match name {
+        "q" | "quit" => Command::Quit,
+        "help" => Command::Help,
+        "list" => Command::List(parts.get(1).map(|s| s.to_string())),
+        "save" => {
+            if parts.len() > 1 {
+                Command::Save(parts[1].to_string())
+            } else {
+                Command::Invalid(":save requires a name".to_string())
+            }
+        }
+        "load" => {
+            if parts.len() > 1 {
+                Command::Load(parts[1].to_string())
+            } else {
+                Command::Invalid(":load requires a name".to_string())
+            }
+        }
+        "model" => {
+            if parts.len() > 1 {
+                Command::Model(parts[1].to_string())
+            } else {
+                Command::Invalid(":model requires a model name".to_string())
+            }
+        }
+        "profile" => {
+            if parts.len() > 1 {
+                Command::Profile(parts[1].to_string())
+            } else {
+                Command::Invalid(":profile requires a profile name".to_string())
+            }
+        }
+        "clear" => Command::Clear,
+        "tools" => Command::Tools(parts.get(1).map(|s| s.to_string())),
+        "resources" => Command::Resources,
+        "resource" | "subscribe" | "unsubscribe" => {
+            let Some(reference) = parts.get(1).map(|s| s.to_string()) else {
+                return Command::Invalid(format!(":{} requires a resource number or URI", name));
+            };
+            match name {
+                "resource" => Command::Resource(reference),
+                "subscribe" => Command::Subscribe(reference),
+                _ => Command::Unsubscribe(reference),
+            }
+        }
+        "prompts" => Command::Prompts,
+        "prompt" => {
+            if parts.len() > 1 {
+                let arguments = parts[2..].iter()
+                    .filter_map(|arg| arg.split_once('='))
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect();
+                Command::Prompt(parts[1].to_string(), arguments)
+            } else {
+                Command::Invalid(":prompt requires a prompt name or number".to_string())
+            }
+        }
+        "blocks" => match parts.get(1) {
+            None => Command::Blocks(None),
+            Some(n) => match n.parse() {
+                Ok(n) => Command::Blocks(Some(n)),
+                Err(_) => Command::Invalid(":blocks takes a message number".to_string()),
+            },
+        },
+        "yank" => {
+            let mut block = None;
+            let mut register = None;
+            for arg in &parts[1..] {
+                let name = arg.strip_prefix('"').unwrap_or(arg);
+                let mut chars = name.chars();
+                match (arg.parse().ok(), chars.next(), chars.next()) {
+                    (Some(n), _, _) if block.is_none() => block = Some(n),
+                    (None, Some(r), None) if register.is_none() && (r.is_ascii_alphabetic() || r == '"') => register = Some(r),
+                    _ => return Command::Invalid(":yank takes a block number and a register letter".to_string()),
+                }
+            }
+            Command::Yank(block, register)
+        }
+        "w" | "w!" => {
+            // A leading number picks the block when a path follows it
+            let block = parts.get(1).and_then(|n| n.parse().ok()).filter(|_| parts.len() > 2);
+            let path = match block {
+                Some(_) => rest.split_once(char::is_whitespace).map_or("", |(_, path)| path.trim()),
+                None => rest,
+            };
+            if path.is_empty() {
+                return Command::Invalid(":w requires a path".to_string());
+            }
+            Command::Write { block, path: path.to_string(), force: name == "w!" }
+        }
+        "read" if rest.is_empty() => Command::Invalid(":read requires a path".to_string()),
+        "read" => Command::Read(rest.to_string()),
+        "attach" => Command::Attach(Some(rest.to_string()).filter(|r| !r.is_empty())),
+        "edit" => Command::Edit,
+        "view" => match parts.get(1) {
+            None => Command::View(None),
+            Some(n) => match n.parse() {
+                Ok(n) => Command::View(Some(n)),
+                Err(_) => Command::Invalid(":view takes a message number".to_string()),
+            },
+        },
+        "retry" => {
+            let mut model = None;
+            let mut temperature = None;
+            for arg in &parts[1..] {
+                match arg.parse::<f32>() {
+                    Ok(t) if temperature.is_none() && (0.0..=1.0).contains(&t) => temperature = Some(t),
+                    Ok(_) => return Command::Invalid(":retry takes one temperature, from 0 to 1".to_string()),
+                    Err(_) if model.is_none() => model = Some(arg.to_string()),
+                    Err(_) => return Command::Invalid(":retry takes a model name and a temperature".to_string()),
+                }
+            }
+            Command::Retry { model, temperature }
+        }
+        "undo" => Command::Undo,
+        "edit-last" => Command::EditLast(Some(rest.to_string()).filter(|r| !r.is_empty())),
+        "fork" => {
+            let mut at = None;
+            let mut name = None;
+            for arg in &parts[1..] {
+                match arg.parse() {
+                    Ok(n) if at.is_none() => at = Some(n),
+                    Err(_) if name.is_none() => name = Some(arg.to_string()),
+                    _ => return Command::Invalid(":fork takes a message number and a branch name".to_string()),
+                }
+            }
+            Command::Fork { at, name }
+        }
+        "branches" => Command::Branches,
+        "checkout" => match parts.get(1) {
+            Some(branch) => Command::Checkout(branch.to_string()),
+            None => Command::Invalid(":checkout requires a branch name or number".to_string()),
+        },
+        "copy" => match parts.get(1) {
+            None => Command::Copy(None),
+            Some(n) => match n.parse() {
+                Ok(n) => Command::Copy(Some(n)),
+                Err(_) => Command::Invalid(":copy takes a block number".to_string()),
+            },
+        },
+        _ => Command::Unknown(cmd.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claude_common::config::permissions::PermissionPolicy;
+    use claude_common::mcp::ClientHost;
+    use claude_common::config::ShellConfig;
+    use claude_common::tools::{BuiltinTools, NonInteractive, Shell};
+    use crate::repl::completion::COMMANDS;
+
+    #[test]
+    fn test_parse_command_with_or_without_colon() {
+        assert!(matches!(parse_command(":q"), Command::Quit));
+        assert!(matches!(parse_command("quit"), Command::Quit));
+        assert!(matches!(parse_command(" :load  scan-notes "), Command::Load(name) if name == "scan-notes"));
+        assert!(matches!(parse_command("subscribe"), Command::Invalid(msg) if msg == ":subscribe requires a resource number or URI"));
+    }
+
+    #[test]
+    fn test_parse_code_block_commands() {
+        assert!(matches!(parse_command("yank"), Command::Yank(None, None)));
+        assert!(matches!(parse_command("yank 2 a"), Command::Yank(Some(2), Some('a'))));
+        assert!(matches!(parse_command("yank \"B"), Command::Yank(None, Some('B'))));
+        assert!(matches!(parse_command("yank ab"), Command::Invalid(_)));
+        assert!(matches!(parse_command("w 2 out/scan results.sh"),
+            Command::Write { block: Some(2), path, force: false } if path == "out/scan results.sh"));
+        assert!(matches!(parse_command("w! 2"), Command::Write { block: None, path, force: true } if path == "2"));
+        assert!(matches!(parse_command("w"), Command::Invalid(_)));
+        assert!(matches!(parse_command(":!ls -la src"), Command::Shell(c) if c == "ls -la src"));
+        assert!(matches!(parse_command("read notes/scan results.md"), Command::Read(p) if p == "notes/scan results.md"));
+        assert!(matches!(parse_command("attach"), Command::Attach(None)));
+    }
+
+    #[test]
+    fn test_parse_turn_commands() {
+        assert!(matches!(parse_command("retry"), Command::Retry { model: None, temperature: None }));
+        assert!(matches!(parse_command("retry 0.2 claude-3-opus"),
+            Command::Retry { model: Some(m), temperature: Some(t) } if m == "claude-3-opus" && t == 0.2));
+        assert!(matches!(parse_command("retry 1.5"), Command::Invalid(_)));
+        assert!(matches!(parse_command("retry claude-3-opus claude-3-haiku"), Command::Invalid(_)));
+        assert!(matches!(parse_command("edit-last  Scan port 443 too "), Command::EditLast(Some(t)) if t == "Scan port 443 too"));
+        assert!(matches!(parse_command("edit-last"), Command::EditLast(None)));
+        assert!(matches!(parse_command("fork 4 udp"), Command::Fork { at: Some(4), name: Some(n) } if n == "udp"));
+        assert!(matches!(parse_command("fork"), Command::Fork { at: None, name: None }));
+        assert!(matches!(parse_command("fork 4 5"), Command::Invalid(_)));
+        assert!(matches!(parse_command("checkout"), Command::Invalid(_)));
+    }
+
+    #[test]
+    fn test_interrupted_reply_keeps_what_arrived() {
+        let text = |text: &str| ContentBlock::Text { text: text.to_string() };
+        let exchange = [
+            ApiMessage {
+                role: "assistant".to_string(),
+                content: vec![
+                    text("Scanning 10.0.0.1 first.\n"),
+                    ContentBlock::ToolUse { id: "tu_1".to_string(), name: "nmap__scan".to_string(), input: serde_json::Value::Null },
+                ],
+            },
+            ApiMessage {
+                role: "user".to_string(),
+                content: vec![ContentBlock::ToolResult { tool_use_id: "tu_1".to_string(), content: "22/tcp open".to_string(), is_error: false }],
+            },
+            ApiMessage { role: "assistant".to_string(), content: vec![text("Port 22 is open."), text("  ")] },
+        ];
+
+        let mut reply = Message {
+            role: "assistant".to_string(),
+            interrupted: true,
+            ..user_message(partial_response(&exchange))
+        };
+        assert_eq!(reply.content, "Scanning 10.0.0.1 first.\nPort 22 is open.");
+        assert_eq!(reply.conversation_text(), "Scanning 10.0.0.1 first.\nPort 22 is open.\n[interrupted]");
+
+        // Cut short before any text arrived, the reply is only the marker
+        reply.content = partial_response(&exchange[1..2]);
+        assert_eq!(reply.conversation_text(), "[interrupted]");
+    }
+
+    /// A session in `dir` sending to `base_url`.
+    fn session(dir: &std::path::Path, base_url: &str, tools: ToolRegistry) -> ReplSession {
+        let config = Config {
+            api_key: String::new(),
+            config_dir: dir.to_path_buf(),
+            history_file: dir.join("history.json"),
+            log_dir: dir.join("logs"),
+            ..Config::default()
+        };
+        let client = ClaudeClient::new(String::new()).with_base_url(base_url);
+        ReplSession::new(client, config, tools, Box::new(NonInteractive))
+    }
+
+    /// Serve each of `responses`, a status and JSON body, to one request,
+    /// returning the base URL.
+    async fn fake_api(responses: Vec<(u16, serde_json::Value)>) -> Result<String> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(async move {
+            for (status, body) in responses {
+                let Ok((mut stream, _)) = listener.accept().await else { return };
+                // Read the headers and as much body as they announce
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                loop {
+                    let Ok(n @ 1..) = stream.read(&mut buf).await else { return };
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request);
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length = head.lines()
+                            .find_map(|l| l.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().to_string()))
+                            .and_then(|v| v.parse().ok())
+                            .unwrap_or(0);
+                        if body.len() >= length {
+                            break;
+                        }
+                    }
+                }
+                let body = body.to_string();
+                let response = format!(
+                    "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status, body.len(), body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        Ok(url)
+    }
+
+    #[tokio::test]
+    async fn test_failure_after_tool_rounds_keeps_what_they_did() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let url = fake_api(vec![
+            (200, serde_json::json!({
+                "content": [
+                    { "type": "text", "text": "Checking first." },
+                    { "type": "tool_use", "id": "tu_1", "name": "bash", "input": { "command": "echo checked" } },
+                ],
+                "stop_reason": "tool_use",
+            })),
+            (529, serde_json::json!({ "type": "error", "error": { "type": "overloaded_error" } })),
+        ]).await?;
+        let mut policy = PermissionPolicy::default();
+        policy.rules.push(serde_json::from_value(serde_json::json!(
+            { "server": "builtin", "tool": "bash", "permission": "allow" }
+        ))?);
+        let shell = Shell::new(ShellConfig { enabled: true, ..ShellConfig::default() });
+        let mut tools = ToolRegistry::new(policy, ClientHost::default());
+        tools.add_builtin(BuiltinTools::new(dir.path())?.with_shell(shell));
+        let mut session = session(dir.path(), &url, tools);
+
+        assert!(session.send_turn(vec![user_message("Check".to_string())]).await.is_err());
+        let messages = session.conversation.messages();
+        let roles: Vec<&str> = messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, ["user", "tool", "assistant"]);
+        assert!(messages[1].content.starts_with("$ echo checked"));
+        assert_eq!(messages[2].conversation_text(), "Checking first.\n[interrupted]");
+        assert!(session.tools.take_shell_runs().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_a_failed_send_puts_everything_back() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        // Nothing listens on port 1, so every send fails
+        let tools = ToolRegistry::new(PermissionPolicy::default(), ClientHost::default());
+        let mut session = session(dir.path(), "http://127.0.0.1:1", tools);
+        session.current_model = "claude-3-haiku".to_string();
+        session.client.set_temperature(Some(0.7));
+        for (role, content) in [("user", "Scan the /24"), ("assistant", "Found 3 hosts")] {
+            session.conversation.push(Message { role: role.to_string(), ..user_message(content.to_string()) });
+        }
+
+        session.attachments.push(Attachment::file("notes.md".to_string(), "For later".to_string()));
+
+        let message = user_message("Scan the /24".to_string());
+        assert!(session.resend(message, Some("claude-3-opus".to_string()), Some(0.2)).await.is_err());
+        assert_eq!(session.current_model, "claude-3-haiku");
+        assert_eq!(session.client.set_temperature(None), Some(0.7));
+        // The exchange set aside for the retry is back, with no branch left over
+        assert_eq!(session.conversation.branches().len(), 1);
+        let contents: Vec<&str> = session.conversation.messages().iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["Scan the /24", "Found 3 hosts"]);
+        assert_eq!(session.attachments.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_every_completed_command_parses() {
+        for (name, _) in COMMANDS {
+            let line = format!("{} argument", name);
+            assert!(!matches!(parse_command(&line), Command::Unknown(_)), "{}", name);
+        }
+    }
+}
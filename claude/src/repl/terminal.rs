@@ -0,0 +1,391 @@
+use anyhow::{bail, Result};
+use crossterm::cursor::{SetCursorStyle, Show};
+use crossterm::event::{
+    self, DisableBracketedPaste, EnableBracketedPaste, Event, KeyCode, KeyEventKind, KeyModifiers,
+    KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::{execute, queue};
+use crossterm::style::{Attribute, ResetColor, SetAttribute};
+use crossterm::terminal::{self, disable_raw_mode, enable_raw_mode};
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU8, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Notify;
+
+/// How often keys are checked for while a request is running.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Terminal features turned on by `RawMode`, undone by `restore`
+const PASTE: u8 = 1;
+const KEYBOARD: u8 = 2;
+const CURSOR_SHAPE: u8 = 4;
+
+/// Features currently on, so a signal or panic knows what to undo.
+static ACTIVE: AtomicU8 = AtomicU8::new(0);
+
+/// Terminal settings from before the session changed anything.
+static ORIGINAL: OnceLock<libc::termios> = OnceLock::new();
+
+/// A full-screen program such as an editor has the terminal.
+static CHILD: AtomicBool = AtomicBool::new(false);
+
+/// SIGTERM or SIGHUP once one has arrived, 0 before.
+static TERMINATED: AtomicI32 = AtomicI32::new(0);
+
+/// Wakes `terminated` when `TERMINATED` is set.
+static TERMINATION: Notify = Notify::const_new();
+
+/// Puts the terminal back as it was found when the program ends, however it
+/// ends: returning, an error, a panic, SIGTERM or SIGHUP. Ctrl-Z and SIGTSTP
+/// suspend with the terminal restored and set it up again on resume.
+///
+/// SIGTERM and SIGHUP do not exit on the spot: `terminated` resolves, key
+/// reads fail, and the program ends through `exit_if_terminated` once the
+/// session has been dropped.
+pub struct TerminalGuard;
+
+impl TerminalGuard {
+    /// Remember the terminal's settings and start watching for signals, or
+    /// `None` when stdin is not a terminal. Must be called inside the Tokio
+    /// runtime, before anything changes the mode.
+    pub fn install() -> Option<Self> {
+        let original = termios()?;
+        ORIGINAL.get_or_init(|| original);
+
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            restore();
+            previous(info);
+        }));
+
+        let (Ok(mut terminate), Ok(mut hangup), Ok(mut stop), Ok(mut resume)) = (
+            signal(SignalKind::terminate()),
+            signal(SignalKind::hangup()),
+            signal(SignalKind::from_raw(libc::SIGTSTP)),
+            signal(SignalKind::from_raw(libc::SIGCONT)),
+        ) else {
+            return Some(Self);
+        };
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = terminate.recv() => terminate_with(libc::SIGTERM),
+                    _ = hangup.recv() => terminate_with(libc::SIGHUP),
+                    _ = stop.recv() => if CHILD.load(Ordering::SeqCst) {
+                        // The child sets up the terminal itself when continued
+                        stop_self()
+                    } else {
+                        suspend()
+                    },
+                    // Continued after a SIGSTOP we did not see: just redraw
+                    _ = resume.recv() => redraw(),
+                }
+            }
+        });
+        Some(Self)
+    }
+
+    /// After SIGTERM or SIGHUP, with the session and everything it held
+    /// dropped, restore the terminal and exit as the signal would have.
+    pub fn exit_if_terminated(self) {
+        let signal = TERMINATED.load(Ordering::SeqCst);
+        if signal != 0 {
+            drop(self);
+            let _ = writeln!(io::stdout());
+            std::process::exit(128 + signal);
+        }
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+fn termios() -> Option<libc::termios> {
+    // SAFETY: termios is plain data, for which all zeroes is a valid value
+    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+    // SAFETY: tcgetattr only writes through the pointer, which is to a live termios
+    (unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } == 0).then_some(termios)
+}
+
+fn set_termios(termios: &libc::termios) {
+    // SAFETY: tcsetattr only reads the termios, which outlives the call
+    unsafe {
+        libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, termios);
+    }
+}
+
+/// Note the signal and wake whatever waits for it; the first one counts.
+fn terminate_with(signal: i32) {
+    if TERMINATED.compare_exchange(0, signal, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+        TERMINATION.notify_one();
+    }
+}
+
+/// Resolve once SIGTERM or SIGHUP has arrived.
+pub async fn terminated() {
+    while TERMINATED.load(Ordering::SeqCst) == 0 {
+        TERMINATION.notified().await;
+    }
+}
+
+/// Read the next terminal event, as `event::read` does, but fail once
+/// SIGTERM or SIGHUP has arrived so a session waiting for keys can end.
+pub fn read_event() -> Result<Event> {
+    loop {
+        if TERMINATED.load(Ordering::SeqCst) != 0 {
+            bail!("Terminated by a signal");
+        }
+        if event::poll(POLL_INTERVAL)? {
+            return Ok(event::read()?);
+        }
+    }
+}
+
+/// Undo every feature that is on and return to the original settings.
+/// Safe to call more than once.
+fn restore() {
+    if io::stdout().is_terminal() {
+        let _ = undo(ACTIVE.load(Ordering::SeqCst), &mut io::stdout());
+    }
+    if let Some(original) = ORIGINAL.get() {
+        set_termios(original);
+    }
+}
+
+/// Write what turns off the `active` features and resets colours and the cursor.
+fn undo(active: u8, out: &mut impl Write) -> io::Result<()> {
+    if active & CURSOR_SHAPE != 0 {
+        queue!(out, SetCursorStyle::DefaultUserShape)?;
+    }
+    if active & KEYBOARD != 0 {
+        queue!(out, PopKeyboardEnhancementFlags)?;
+    }
+    if active & PASTE != 0 {
+        queue!(out, DisableBracketedPaste)?;
+    }
+    queue!(out, SetAttribute(Attribute::Reset), ResetColor, Show)?;
+    out.flush()
+}
+
+/// Stop the process with the terminal restored, then set it up again as it
+/// was once the shell continues it.
+pub fn suspend() {
+    let Some(current) = termios() else {
+        return;
+    };
+    restore();
+    let _ = writeln!(io::stdout());
+    let _ = io::stdout().flush();
+    stop_self();
+    reapply(&current);
+    redraw();
+}
+
+fn stop_self() {
+    // SAFETY: getpid cannot fail, and SIGSTOP to ourselves only pauses the process
+    unsafe {
+        libc::kill(libc::getpid(), libc::SIGSTOP);
+    }
+}
+
+/// Put back settings and features `restore` undid.
+fn reapply(termios: &libc::termios) {
+    set_termios(termios);
+    let active = ACTIVE.load(Ordering::SeqCst);
+    let mut out = io::stdout();
+    if active & PASTE != 0 {
+        let _ = execute!(out, EnableBracketedPaste);
+    }
+    if active & KEYBOARD != 0 {
+        let _ = execute!(out, PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES));
+    }
+}
+
+/// Run a full-screen program, such as an editor, with the terminal as it was
+/// found, then set it up again for the session whatever state the program
+/// left it in.
+pub fn run_interactive(command: &mut Command) -> Result<ExitStatus> {
+    let Some(current) = termios() else {
+        bail!("Running {:?} needs a terminal", command.get_program());
+    };
+    restore();
+    let _ = io::stdout().flush();
+
+    CHILD.store(true, Ordering::SeqCst);
+    let status = command.status();
+    CHILD.store(false, Ordering::SeqCst);
+
+    reapply(&current);
+    Ok(status?)
+}
+
+/// Have the line editor redraw by reporting a resize.
+fn redraw() {
+    // SAFETY: getpid cannot fail, and SIGWINCH is only ever handled by crossterm
+    unsafe {
+        libc::kill(libc::getpid(), libc::SIGWINCH);
+    }
+}
+
+/// Raw mode, bracketed paste and, where the terminal supports it, key
+/// reporting that tells Shift-Enter from Enter, for as long as this is alive.
+pub struct RawMode {
+    features: Active,
+}
+
+/// Marks features as on in `ACTIVE` for as long as it is alive.
+struct Active(u8);
+
+impl Active {
+    fn set(features: u8) -> Self {
+        ACTIVE.fetch_or(features, Ordering::SeqCst);
+        Self(features)
+    }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        ACTIVE.fetch_and(!self.0, Ordering::SeqCst);
+    }
+}
+
+impl RawMode {
+    /// `cursor_shape` when the caller will change the cursor's shape, so the
+    /// user's own is put back afterwards.
+    pub fn enable(cursor_shape: bool) -> Result<Self> {
+        // Asking the terminal takes a round trip, so only ask once
+        static ENHANCEMENT: OnceLock<bool> = OnceLock::new();
+
+        enable_raw_mode()?;
+        let enhanced = *ENHANCEMENT.get_or_init(|| terminal::supports_keyboard_enhancement().unwrap_or(false));
+        execute!(io::stdout(), EnableBracketedPaste)?;
+        let mut features = PASTE;
+        if enhanced {
+            execute!(io::stdout(), PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES))?;
+            features |= KEYBOARD;
+        }
+        if cursor_shape {
+            features |= CURSOR_SHAPE;
+        }
+        Ok(Self { features: Active::set(features) })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let features = self.features.0;
+        if features & CURSOR_SHAPE != 0 {
+            let _ = execute!(io::stdout(), SetCursorStyle::DefaultUserShape);
+        }
+        if features & KEYBOARD != 0 {
+            let _ = execute!(io::stdout(), PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(io::stdout(), DisableBracketedPaste);
+        let _ = disable_raw_mode();
+    }
+}
+
+/// While alive, keys reach the program unechoed and one at a time, and
+/// Ctrl-C arrives as a key rather than a SIGINT that would also stop MCP
+/// servers. Output is processed as usual, so anything printed meanwhile
+/// keeps its line breaks.
+pub struct WatchKeys {
+    saved: libc::termios,
+}
+
+impl WatchKeys {
+    /// `None` when stdin is not a terminal.
+    pub fn start() -> Option<Self> {
+        let saved = termios()?;
+        let mut termios = saved;
+        termios.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+        termios.c_cc[libc::VMIN] = 1;
+        termios.c_cc[libc::VTIME] = 0;
+        set_termios(&termios);
+        Some(Self { saved })
+    }
+}
+
+impl Drop for WatchKeys {
+    fn drop(&mut self) {
+        set_termios(&self.saved);
+    }
+}
+
+/// Put `text` on the system clipboard with an OSC 52 sequence, which the
+/// terminal handles, so it reaches the local clipboard over SSH too. Inside
+/// tmux the sequence is passed through to the outer terminal.
+pub fn copy_to_clipboard(text: &str) -> Result<()> {
+    use base64::Engine;
+
+    if !io::stdout().is_terminal() {
+        bail!("Copying needs output to go to a terminal");
+    }
+    let encoded = base64::engine::general_purpose::STANDARD.encode(text);
+    let sequence = format!("\x1b]52;c;{}\x07", encoded);
+    let mut out = io::stdout();
+    if std::env::var_os("TMUX").is_some() {
+        write!(out, "\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"))?;
+    } else {
+        write!(out, "{}", sequence)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Resolve once Ctrl-C or Esc is pressed, with whether it was Ctrl-C. Other
+/// keys are dropped. Only checks between awaits, so a prompt that reads keys
+/// itself, such as a tool approval, gets them first.
+pub async fn cancel_requested() -> bool {
+    loop {
+        while event::poll(Duration::ZERO).unwrap_or(false) {
+            match event::read() {
+                Ok(Event::Key(key)) if key.kind != KeyEventKind::Release => match key.code {
+                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return true,
+                    KeyCode::Char('z') if key.modifiers.contains(KeyModifiers::CONTROL) => suspend(),
+                    KeyCode::Esc => return false,
+                    _ => {}
+                },
+                Ok(_) => {}
+                // Nothing to read keys from; the request can only finish
+                Err(_) => std::future::pending::<()>().await,
+            }
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_active_features_are_tracked_and_undone() -> Result<()> {
+        let raw = Active::set(PASTE | KEYBOARD);
+        let cursor = Active::set(CURSOR_SHAPE);
+        assert_eq!(ACTIVE.load(Ordering::SeqCst), PASTE | KEYBOARD | CURSOR_SHAPE);
+
+        drop(cursor);
+        assert_eq!(ACTIVE.load(Ordering::SeqCst), PASTE | KEYBOARD);
+        let mut out = Vec::new();
+        undo(ACTIVE.load(Ordering::SeqCst), &mut out)?;
+        let out = String::from_utf8(out)?;
+        assert!(out.contains("\x1b[<1u"), "keyboard flags popped: {:?}", out);
+        assert!(out.contains("\x1b[?2004l"), "bracketed paste off: {:?}", out);
+        assert!(!out.contains(" q"), "cursor shape left alone: {:?}", out);
+        assert!(out.ends_with("\x1b[?25h"), "cursor shown: {:?}", out);
+
+        drop(raw);
+        assert_eq!(ACTIVE.load(Ordering::SeqCst), 0);
+        let mut out = Vec::new();
+        undo(0, &mut out)?;
+        assert!(!String::from_utf8(out)?.contains("2004"));
+        Ok(())
+    }
+}
@@ -0,0 +1,41 @@
+use assert_cmd::Command;
+use predicates::prelude::*;
+use tempfile::TempDir;
+
+/// `claude` with its own empty configuration, reading `input` as a script.
+fn claude(config_dir: &TempDir, input: &str) -> assert_cmd::assert::Assert {
+    Command::cargo_bin("claude")
+        .unwrap()
+        .env("CLAUDE_CONFIG_DIR", config_dir.path())
+        .env_remove("CLAUDE_API_KEY")
+        .write_stdin(input)
+        .assert()
+}
+
+#[test]
+fn test_repl_commands() {
+    let config_dir = TempDir::new().unwrap();
+    claude(&config_dir, ":help\n:q\n")
+        .success()
+        .stdout(predicate::str::contains("Available Commands:"));
+}
+
+#[test]
+fn test_script_saves_and_stops_at_quit() {
+    let config_dir = TempDir::new().unwrap();
+    claude(&config_dir, ":model claude-3-haiku\n:save scripted\n:q\n:load missing\n")
+        .success()
+        .stdout(predicate::str::contains("Session saved as: scripted"));
+    assert!(config_dir.path().join("sessions/scripted.json").exists());
+}
+
+#[test]
+fn test_script_failure_sets_exit_status() {
+    let config_dir = TempDir::new().unwrap();
+    claude(&config_dir, ":help\n:load missing\n:help\n")
+        .failure()
+        .stderr(predicate::str::contains("No saved session named 'missing'"));
+    claude(&config_dir, ":frobnicate\n")
+        .failure()
+        .stderr(predicate::str::contains("Unknown command: :frobnicate"));
+}